@@ -0,0 +1,411 @@
+use candid::{Encode, Principal};
+
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::api::management_canister::main::{
+    create_canister, install_code, CanisterInstallMode, CanisterSettings, CreateCanisterArgument,
+    InstallCodeArgument,
+};
+use ic_cdk::export_candid;
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use types::{MarketDetails, MarketRecord, MarketStatus, StateDetails};
+
+type Amount = u128;
+
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const _ADMIN_MEMORY: MemoryId = MemoryId::new(1);
+const _MARKET_WASM_MEMORY: MemoryId = MemoryId::new(2);
+const _MARKETS_MEMORY: MemoryId = MemoryId::new(3);
+
+/// Cycles sent along with the creation of each market canister
+const MARKET_CANISTER_CYCLES: u128 = 2_000_000_000_000; // 2T Cycles
+
+/// Max length of an asset symbol
+const MAX_SYMBOL_LENGTH: usize = 20;
+
+/// Max length of a stored setup error
+const MAX_ERROR_LENGTH: usize = 200;
+
+thread_local! {
+
+    static MEMORY_MANAGER:RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default())) ;
+
+
+    static ADMIN :RefCell<StableCell<Principal,Memory>> = RefCell::new(StableCell::init(MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_ADMIN_MEMORY)
+    }),Principal::anonymous()).unwrap());
+
+    /// Wasm module installed on every new market canister
+    static MARKET_WASM :RefCell<StableCell<Vec<u8>,Memory>> = RefCell::new(StableCell::init(MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_MARKET_WASM_MEMORY)
+    }),Vec::new()).unwrap());
+
+    /// Markets created by the factory keyed by canister id
+    static MARKETS :RefCell<StableBTreeMap<Principal,MarketRecord,Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_MARKETS_MEMORY)
+    })));
+
+    /// Markets whose setup is currently awaiting a stage
+    static MARKETS_IN_SETUP :RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+
+}
+
+#[ic_cdk::init]
+fn init() {
+    ADMIN.with_borrow_mut(|reference| reference.set(ic_cdk::caller()).unwrap());
+}
+
+/// Get Markets
+///
+/// Returns all markets created by the factory
+#[ic_cdk::query(name = "getMarkets")]
+fn get_markets() -> Vec<MarketRecord> {
+    MARKETS.with_borrow(|reference| reference.iter().map(|(_, record)| record).collect())
+}
+
+/// Get Market
+///
+/// Returns the record of a single market
+#[ic_cdk::query(name = "getMarket")]
+fn get_market(canister_id: Principal) -> Option<MarketRecord> {
+    _get_market(canister_id)
+}
+
+///////////////////////////
+//  Admin Functions
+//////////////////////////
+
+/// Upload Market Wasm
+///
+/// Sets the perp wasm module installed on markets created afterwards
+///
+/// Note
+///  - The wasm module can be gzipped to fit within the ingress message limit
+#[ic_cdk::update(name = "uploadMarketWasm", guard = "admin_guard")]
+fn upload_market_wasm(wasm_module: Vec<u8>) {
+    MARKET_WASM.with_borrow_mut(|reference| reference.set(wasm_module).unwrap());
+}
+
+/// Create Market
+///
+/// Creates a perp canister ,installs the market wasm ,sets the initial state details ,registers the market with the vault ,
+/// starts the market's timers and proposes the factory admin as the market's owner
+///
+/// Params
+///  - Market Details :The init argument of the perp canister
+///  - State Details :The initial state details of the market
+///  - Debt Ceiling :The max debt of the market in the vault
+///
+/// Returns
+///  - Canister Id :The canister id of the new market if every setup stage succeeded
+///
+/// Note
+///  - The factory must hold the RiskManager role in the vault to register the market
+///  - If a setup stage fails ,the market is kept in the registry at the failed stage and can be completed with completeMarketSetup
+///  - The factory is the owner of the market till the factory admin calls acceptOwnership on the market ,the market's
+///  admin endpoints e.g grantRole and setMarketMode are only available to the admin afterwards
+#[ic_cdk::update(name = "createMarket", guard = "admin_guard")]
+async fn create_market(
+    market_details: MarketDetails,
+    state_details: StateDetails,
+    debt_ceiling: Amount,
+) -> Result<Principal, String> {
+    _validate_market_details(&market_details)?;
+
+    if MARKET_WASM.with_borrow(|reference| reference.get().is_empty()) {
+        return Err("Market wasm not uploaded".to_string());
+    }
+
+    let settings = CanisterSettings {
+        controllers: Some(vec![ic_cdk::id(), ic_cdk::caller()]),
+        ..Default::default()
+    };
+
+    let canister_id = match create_canister(
+        CreateCanisterArgument {
+            settings: Some(settings),
+        },
+        MARKET_CANISTER_CYCLES,
+    )
+    .await
+    {
+        Ok((record,)) => record.canister_id,
+        Err(error) => return Err(_reject_reason(error)),
+    };
+
+    _insert_market(MarketRecord {
+        canister_id,
+        market_details,
+        state_details,
+        debt_ceiling,
+        status: MarketStatus::Created,
+        created_at: ic_cdk::api::time(),
+        last_error: String::new(),
+    });
+
+    _setup_market(canister_id).await?;
+
+    Ok(canister_id)
+}
+
+/// Complete Market Setup
+///
+/// Retries the setup of a market from the stage it failed at
+#[ic_cdk::update(name = "completeMarketSetup", guard = "admin_guard")]
+async fn complete_market_setup(canister_id: Principal) -> Result<(), String> {
+    if _get_market(canister_id).is_none() {
+        return Err("Market not found".to_string());
+    }
+    _setup_market(canister_id).await
+}
+
+/// Setup Market
+///
+/// Completes each remaining setup stage of a market in order ,stopping at the first stage that fails
+///
+/// Note
+///  - The market is locked till the setup returns ,so a stage is never run twice concurrently
+async fn _setup_market(canister_id: Principal) -> Result<(), String> {
+    let _lock = SetupLock::acquire(canister_id)?;
+
+    loop {
+        let mut record = _get_market(canister_id).unwrap();
+
+        let result: Result<(), String> = match record.status {
+            MarketStatus::Created => install_code(InstallCodeArgument {
+                mode: CanisterInstallMode::Install,
+                canister_id,
+                wasm_module: MARKET_WASM.with_borrow(|reference| reference.get().clone()),
+                arg: Encode!(&record.market_details).unwrap(),
+            })
+            .await
+            .map_err(_reject_reason),
+            MarketStatus::Installed => {
+                match ic_cdk::call(canister_id, "updateStateDetails", (record.state_details,)).await
+                {
+                    Ok((result,)) => result,
+                    Err(error) => Err(_reject_reason(error)),
+                }
+            }
+            MarketStatus::Configured => ic_cdk::call(
+                record.market_details.vault_id,
+                "approveMarket",
+                (canister_id, record.debt_ceiling),
+            )
+            .await
+            .map_err(_reject_reason),
+            MarketStatus::Registered => ic_cdk::call(canister_id, "startTimer", ())
+                .await
+                .map_err(_reject_reason),
+            MarketStatus::TimersStarted => ic_cdk::call(
+                canister_id,
+                "proposeOwner",
+                (ADMIN.with_borrow(|reference| *reference.get()),),
+            )
+            .await
+            .map_err(_reject_reason),
+            MarketStatus::Active => return Ok(()),
+        };
+
+        match result {
+            Ok(()) => {
+                record.status = match record.status {
+                    MarketStatus::Created => MarketStatus::Installed,
+                    MarketStatus::Installed => MarketStatus::Configured,
+                    MarketStatus::Configured => MarketStatus::Registered,
+                    MarketStatus::Registered => MarketStatus::TimersStarted,
+                    _ => MarketStatus::Active,
+                };
+                record.last_error = String::new();
+                _insert_market(record);
+            }
+            Err(reason) => {
+                let reason: String = reason.chars().take(MAX_ERROR_LENGTH).collect();
+
+                record.last_error = reason.clone();
+                _insert_market(record);
+
+                return Err(reason);
+            }
+        }
+    }
+}
+
+/// Validate Market Details
+///
+/// Returns an error if the market details would be rejected by the perp canister or exceed the record bound
+fn _validate_market_details(market_details: &MarketDetails) -> Result<(), String> {
+    if market_details.base_asset.symbol.len() > MAX_SYMBOL_LENGTH
+        || market_details.quote_asset.symbol.len() > MAX_SYMBOL_LENGTH
+    {
+        return Err("Asset symbol too long".to_string());
+    }
+
+    if !market_details.tick_grid.is_valid() {
+        return Err("Invalid tick grid".to_string());
+    }
+
+    if !market_details.decimals.is_valid() {
+        return Err("Invalid asset decimals".to_string());
+    }
+
+    Ok(())
+}
+
+/// Setup Lock
+///
+/// Marks a market's setup as in progress till dropped
+///
+/// Note
+///  - The lock is also dropped if the setup traps after an await ,as the call context is cleaned up
+struct SetupLock {
+    canister_id: Principal,
+}
+
+impl SetupLock {
+    fn acquire(canister_id: Principal) -> Result<SetupLock, String> {
+        let acquired = MARKETS_IN_SETUP.with_borrow_mut(|reference| reference.insert(canister_id));
+        if !acquired {
+            return Err("Market setup already in progress".to_string());
+        }
+        Ok(SetupLock { canister_id })
+    }
+}
+
+impl Drop for SetupLock {
+    fn drop(&mut self) {
+        MARKETS_IN_SETUP.with_borrow_mut(|reference| reference.remove(&self.canister_id));
+    }
+}
+
+fn _reject_reason((code, message): (RejectionCode, String)) -> String {
+    format!("{:?} {}", code, message)
+}
+
+fn _get_market(canister_id: Principal) -> Option<MarketRecord> {
+    MARKETS.with_borrow(|reference| reference.get(&canister_id))
+}
+
+fn _insert_market(record: MarketRecord) {
+    MARKETS.with_borrow_mut(|reference| reference.insert(record.canister_id, record));
+}
+
+fn admin_guard() -> Result<(), String> {
+    if ADMIN.with_borrow(|reference| *reference.get()) == ic_cdk::caller() {
+        return Ok(());
+    }
+    Err("Caller is not the admin".to_string())
+}
+
+export_candid!();
+
+pub mod types;
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+    use ic_stable_structures::Storable;
+    use types::{Asset, AssetDecimals, MarketMode, TickGrid, TickModel};
+
+    #[test]
+    fn test_setup_lock() {
+        let canister_id = Principal::from_slice(&[1]);
+
+        let lock = SetupLock::acquire(canister_id).unwrap();
+        assert!(SetupLock::acquire(canister_id).is_err());
+
+        // other markets are not locked
+        assert!(SetupLock::acquire(Principal::from_slice(&[2])).is_ok());
+
+        drop(lock);
+        assert!(SetupLock::acquire(canister_id).is_ok());
+    }
+
+    #[test]
+    fn test_validate_market_details() {
+        let mut market_details = MarketDetails::default();
+        assert_eq!(_validate_market_details(&market_details), Ok(()));
+
+        market_details.base_asset = Asset {
+            symbol: "X".repeat(MAX_SYMBOL_LENGTH + 1),
+            ..Asset::default()
+        };
+        assert!(_validate_market_details(&market_details).is_err());
+
+        let mut market_details = MarketDetails {
+            tick_grid: TickGrid {
+                tick_spacing: 0,
+                ..TickGrid::default()
+            },
+            ..MarketDetails::default()
+        };
+        assert!(_validate_market_details(&market_details).is_err());
+
+        // the integral width would overflow
+        market_details.tick_grid = TickGrid {
+            tick_spacing: u64::MAX / 10,
+            ..TickGrid::default()
+        };
+        assert!(_validate_market_details(&market_details).is_err());
+
+        market_details.tick_grid = TickGrid {
+            base_price: u64::MAX as u128 + 1,
+            ..TickGrid::default()
+        };
+        assert!(_validate_market_details(&market_details).is_err());
+
+        // geometric ticks must be whole basis points
+        market_details.tick_grid = TickGrid {
+            tick_spacing: 1500,
+            model: TickModel::Geometric,
+            ..TickGrid::default()
+        };
+        assert!(_validate_market_details(&market_details).is_err());
+
+        market_details.tick_grid.tick_spacing = 2000;
+        assert_eq!(_validate_market_details(&market_details), Ok(()));
+
+        market_details.decimals = AssetDecimals { base: 19, quote: 6 };
+        assert!(_validate_market_details(&market_details).is_err());
+    }
+
+    #[test]
+    fn test_market_record_within_bound() {
+        let asset = Asset {
+            symbol: "X".repeat(MAX_SYMBOL_LENGTH),
+            ..Asset::default()
+        };
+        let record = MarketRecord {
+            canister_id: Principal::management_canister(),
+            market_details: MarketDetails {
+                base_asset: asset.clone(),
+                quote_asset: asset,
+                ..MarketDetails::default()
+            },
+            state_details: StateDetails {
+                mode: MarketMode::Active,
+                current_tick: u64::MAX,
+                max_leverage_bps: u64::MAX,
+                min_collateral: u128::MAX,
+            },
+            debt_ceiling: u128::MAX,
+            status: MarketStatus::Configured,
+            created_at: u64::MAX,
+            last_error: "X".repeat(MAX_ERROR_LENGTH),
+        };
+
+        let bytes = record.to_bytes();
+        assert!(bytes.len() <= 1000);
+        assert_eq!(
+            MarketRecord::from_bytes(bytes).last_error,
+            record.last_error
+        );
+    }
+}
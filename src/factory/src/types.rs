@@ -0,0 +1,67 @@
+use candid::{CandidType, Decode, Encode, Principal};
+
+use serde::Deserialize;
+
+use std::borrow::Cow;
+
+use ic_stable_structures::{storable::Bound, Storable};
+
+pub use market_types::{
+    Asset, AssetClass, AssetDecimals, MarketDetails, MarketMode, StateDetails, TickGrid, TickModel,
+};
+
+type Amount = u128;
+type Time = u64;
+
+/// Market Status
+///
+/// The setup stage of a market ,each stage is completed in order till the market is active
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum MarketStatus {
+    /// Canister is created ,wasm is not yet installed
+    Created,
+    /// Wasm is installed ,initial state details are not yet set
+    Installed,
+    /// State details are set ,market is not yet registered with the vault
+    Configured,
+    /// Market is registered with the vault ,the market's timers are not yet started
+    Registered,
+    /// Funding ,oracle and retry timers are started ,the factory admin is not yet proposed as the market's owner
+    TimersStarted,
+    /// Factory admin is proposed as the market's owner and the market is open for trading
+    Active,
+}
+
+/// Market Record
+///
+/// A market created by the factory
+#[derive(CandidType, Clone, Debug, Deserialize)]
+pub struct MarketRecord {
+    pub canister_id: Principal,
+    pub market_details: MarketDetails,
+    pub state_details: StateDetails,
+    /// Debt Ceiling
+    ///
+    /// max debt of the market in the vault ,set when the market is registered with the vault
+    pub debt_ceiling: Amount,
+    pub status: MarketStatus,
+    pub created_at: Time,
+    /// Last Error
+    ///
+    /// the reason the last setup stage failed ,empty if no stage has failed
+    pub last_error: String,
+}
+
+impl Storable for MarketRecord {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1000,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
@@ -0,0 +1,15 @@
+/// Max token decimals of either asset of a market
+pub const _MAX_ASSET_DECIMALS: u8 = 18;
+
+pub const _BASE_PRICE: u128 = 1_000_000_000;
+
+pub const _ONE_BASIS_POINT: u64 = 1000;
+
+/// Max distance between two neighbouring ticks ,i.e the 100% tick
+pub const _MAX_TICK_SPACING: u64 = 10_000 * _ONE_BASIS_POINT;
+
+/// Number of ticks within an integral ,i.e the bits used by an integral's bitmap
+pub const _TICKS_PER_INTEGRAL: u64 = 100;
+
+/// Leverage of 1x in whole basis points ,leverage is not scaled by _ONE_BASIS_POINT like percentages
+pub const _ONE_X_LEVERAGE_BPS: u64 = 10_000;
@@ -0,0 +1,243 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use constants::{
+    _BASE_PRICE, _MAX_ASSET_DECIMALS, _MAX_TICK_SPACING, _ONE_BASIS_POINT, _TICKS_PER_INTEGRAL,
+};
+
+pub type Tick = u64;
+pub type Amount = u128;
+
+/// The enum defining the different asset classes.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, PartialEq)]
+pub enum AssetClass {
+    /// The cryptocurrency asset class.
+    #[default]
+    Cryptocurrency,
+    /// The fiat currency asset class.
+    FiatCurrency,
+}
+
+/// Exchange rates are derived for pairs of assets captured in this struct.
+#[derive(CandidType, Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Asset {
+    /// The symbol/code of the asset.
+    pub symbol: String,
+    /// The asset class.
+    pub class: AssetClass,
+}
+
+///Market Details
+#[derive(Clone, Deserialize, CandidType, Debug)]
+pub struct MarketDetails {
+    /// The details of the  perpetual asset also seen as the base asset  
+    pub base_asset: Asset,
+
+    /// the details of the collateral token  in asset  all margin is paid
+    pub quote_asset: Asset,
+    /// Vault ID
+    ///
+    /// The canister ID of the vault canister
+    pub vault_id: Principal,
+    /// Watcher ID
+    ///
+    /// The cansiter ID of the watcher canister
+    pub watcher_id: Principal,
+
+    pub xrc_id: Principal,
+
+    /// Decimals
+    ///
+    /// The token decimals of the base (perp) asset and the quote (collateral) asset
+    pub decimals: AssetDecimals,
+
+    /// Tick Grid
+    ///
+    /// The tick spacing and base price of the market's order book
+    pub tick_grid: TickGrid,
+}
+
+impl Default for MarketDetails {
+    fn default() -> MarketDetails {
+        MarketDetails {
+            base_asset: Asset::default(),
+            quote_asset: Asset::default(),
+            vault_id: Principal::anonymous(),
+            xrc_id: Principal::anonymous(),
+            watcher_id: Principal::anonymous(),
+            decimals: AssetDecimals::default(),
+            tick_grid: TickGrid::default(),
+        }
+    }
+}
+
+/// Asset Decimals
+///
+/// The token decimals of the base and quote assets ,used to convert raw amounts of one asset to the other at a price
+///
+/// Note
+///  - Prices are always given per whole base token in whole quote tokens with _PRICE_DECIMAL precision
+#[derive(Clone, Copy, Default, Deserialize, CandidType, Debug, PartialEq, Eq)]
+pub struct AssetDecimals {
+    /// Base
+    ///
+    /// The token decimals of the base (perp) asset
+    pub base: u8,
+    /// Quote
+    ///
+    /// The token decimals of the quote (collateral) asset
+    pub quote: u8,
+}
+
+impl AssetDecimals {
+    /// Scales
+    ///
+    /// Returns the factors base and quote raw amounts are multiplied by to have the same decimals ,
+    /// one of which is always 1
+    pub fn scales(&self) -> (Amount, Amount) {
+        if self.base >= self.quote {
+            (1, 10u128.pow((self.base - self.quote) as u32))
+        } else {
+            (10u128.pow((self.quote - self.base) as u32), 1)
+        }
+    }
+
+    /// Is Valid
+    ///
+    /// Returns false if either decimal exceeds _MAX_ASSET_DECIMALS
+    pub fn is_valid(&self) -> bool {
+        self.base <= _MAX_ASSET_DECIMALS && self.quote <= _MAX_ASSET_DECIMALS
+    }
+}
+
+/// Tick Grid
+///
+/// Determines the ticks orders can be placed at and the price of each tick
+///
+/// Note
+///  - Ticks are grouped into integrals of _TICKS_PER_INTEGRAL ticks ,each integral having a single bitmap
+///  - The price of a tick is determined by the tick model (see TickModel)
+#[derive(Clone, Copy, Deserialize, CandidType, Debug, PartialEq, Eq)]
+pub struct TickGrid {
+    /// Tick Spacing
+    ///
+    /// The distance between two neighbouring ticks in _ONE_PERCENT units
+    pub tick_spacing: u64,
+    /// Base Price
+    ///
+    /// The price of the 100% tick (linear) or the anchor tick (geometric) with _PRICE_DECIMAL precision
+    pub base_price: Amount,
+    /// Model
+    ///
+    /// The mapping of ticks to prices
+    pub model: TickModel,
+}
+
+/// Tick Model
+///
+/// Determines how the price of a tick is calculated
+#[derive(Clone, Copy, Default, Deserialize, CandidType, Debug, PartialEq, Eq)]
+pub enum TickModel {
+    /// Price is the tick as a percentage of the base price ,so each tick is a constant absolute price step
+    #[default]
+    Linear,
+    /// Each basis point tick away from _GEOMETRIC_ANCHOR_TICK is a constant relative price step of 1.0001
+    ///
+    /// Note
+    ///  - Ticks are limited to _MAX_GEOMETRIC_STEPS basis point steps on either side of the anchor tick
+    Geometric,
+}
+
+impl TickGrid {
+    /// Integral Width
+    ///
+    /// The distance between the tick zeros of two neighbouring integrals
+    pub fn integral_width(&self) -> u64 {
+        self.tick_spacing * _TICKS_PER_INTEGRAL
+    }
+
+    /// Is Valid
+    ///
+    /// Returns false if either the tick spacing or the base price is zero ,the tick spacing exceeds _MAX_TICK_SPACING
+    /// or the base price does not fit within 64 bits
+    ///
+    /// Note
+    ///  - Bounding the tick spacing keeps the integral width from overflowing
+    ///  - A 64 bit base price keeps tick prices from overflowing in either model
+    ///  - The geometric model also requires a tick spacing of whole basis points
+    pub fn is_valid(&self) -> bool {
+        if self.tick_spacing == 0
+            || self.tick_spacing > _MAX_TICK_SPACING
+            || self.base_price == 0
+            || self.base_price > u64::MAX as u128
+        {
+            return false;
+        }
+        match self.model {
+            TickModel::Linear => true,
+            TickModel::Geometric => self.tick_spacing.is_multiple_of(_ONE_BASIS_POINT),
+        }
+    }
+}
+
+impl Default for TickGrid {
+    fn default() -> Self {
+        TickGrid {
+            tick_spacing: _ONE_BASIS_POINT,
+            base_price: _BASE_PRICE,
+            model: TickModel::Linear,
+        }
+    }
+}
+
+/// Market Mode
+///
+/// Determines which trading actions the market allows
+#[derive(CandidType, Default, Debug, PartialEq, Eq, Copy, Deserialize, Clone)]
+pub enum MarketMode {
+    /// Opening and closing positions are allowed
+    Active,
+    /// Only closing positions and cancelling limit orders are allowed
+    ReduceOnly,
+    /// Opening long positions is halted ,everything else is allowed
+    LongsHalted,
+    /// Opening short positions is halted ,everything else is allowed
+    ShortsHalted,
+    /// No position can be opened or closed
+    #[default]
+    Paused,
+}
+
+///State Details comprises for useful parameters that change more frequently
+/// compared to market details  that is  set on initialization
+
+#[derive(CandidType, Default, Debug, PartialEq, Eq, Copy, Deserialize, Clone)]
+pub struct StateDetails {
+    /// Market Mode
+    ///
+    /// the trading actions the market allows
+    pub mode: MarketMode,
+    /// Current Tick
+    ///
+    ///
+    pub current_tick: Tick,
+    /// Max Leverage
+    ///
+    /// the maximum leverage allowed for any position in basis points
+    ///
+    /// a leverage of 1x is 10_000 ,so a leverage of 2.5x would be written as 25_000
+    pub max_leverage_bps: u64,
+    /// Minimum Collateral
+    ///
+    /// minimum collateral or minimum margin to open a position
+    ///
+    /// Note:
+    ///
+    /// -this amount  is actuallly  reduced by the reduction i.e (10::pow(token_decimal - 6))
+    pub min_collateral: Amount,
+}
+
+pub mod constants;
+
+#[cfg(feature = "stable")]
+mod storable;
@@ -0,0 +1,152 @@
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::Storable;
+use serde::Deserialize;
+use std::borrow::Cow;
+
+use crate::constants::_ONE_X_LEVERAGE_BPS;
+use crate::{
+    Amount, Asset, AssetDecimals, MarketDetails, MarketMode, StateDetails, Tick, TickGrid,
+};
+
+impl Storable for MarketDetails {
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(market_details) => market_details,
+            Err(_) => Decode!(bytes.as_ref(), LegacyMarketDetails).unwrap().into(),
+        }
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Legacy Market Details
+///
+/// Market details stored before asset decimals and tick grids ,only read when decoding
+#[derive(Deserialize, CandidType)]
+struct LegacyMarketDetails {
+    base_asset: Asset,
+    quote_asset: Asset,
+    vault_id: Principal,
+    watcher_id: Principal,
+    xrc_id: Principal,
+    collateral_decimal: u8,
+}
+
+impl From<LegacyMarketDetails> for MarketDetails {
+    /// Legacy markets used the default tick grid and made no conversion between base and quote amounts ,
+    /// so both assets are given the collateral decimals
+    fn from(legacy: LegacyMarketDetails) -> MarketDetails {
+        MarketDetails {
+            base_asset: legacy.base_asset,
+            quote_asset: legacy.quote_asset,
+            vault_id: legacy.vault_id,
+            watcher_id: legacy.watcher_id,
+            xrc_id: legacy.xrc_id,
+            decimals: AssetDecimals {
+                base: legacy.collateral_decimal,
+                quote: legacy.collateral_decimal,
+            },
+            tick_grid: TickGrid::default(),
+        }
+    }
+}
+
+impl Storable for StateDetails {
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(state_details) => state_details,
+            Err(_) => Decode!(bytes.as_ref(), LegacyStateDetails).unwrap().into(),
+        }
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Legacy State Details
+///
+/// State details stored before market modes and basis point leverage ,only read when decoding
+///
+/// Note
+///  - The base token multiple these held is unused and skipped when decoding
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct LegacyStateDetails {
+    not_paused: bool,
+    current_tick: Tick,
+    max_leveragex10: u8,
+    min_collateral: Amount,
+}
+
+impl From<LegacyStateDetails> for StateDetails {
+    fn from(legacy: LegacyStateDetails) -> StateDetails {
+        StateDetails {
+            mode: if legacy.not_paused {
+                MarketMode::Active
+            } else {
+                MarketMode::Paused
+            },
+            current_tick: legacy.current_tick,
+            max_leverage_bps: legacy.max_leveragex10 as u64 * (_ONE_X_LEVERAGE_BPS / 10),
+            min_collateral: legacy.min_collateral,
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+
+    #[test]
+    fn test_legacy_market_details_decode() {
+        let legacy = LegacyMarketDetails {
+            base_asset: Asset::default(),
+            quote_asset: Asset::default(),
+            vault_id: Principal::anonymous(),
+            watcher_id: Principal::anonymous(),
+            xrc_id: Principal::anonymous(),
+            collateral_decimal: 6,
+        };
+
+        let market_details = MarketDetails::from_bytes(Cow::Owned(Encode!(&legacy).unwrap()));
+
+        assert_eq!(market_details.decimals, AssetDecimals { base: 6, quote: 6 });
+        assert_eq!(market_details.tick_grid, TickGrid::default());
+
+        // current market details still decode as is
+        let market_details = MarketDetails::from_bytes(market_details.to_bytes());
+        assert_eq!(market_details.decimals, AssetDecimals { base: 6, quote: 6 });
+    }
+
+    #[test]
+    fn test_legacy_state_details_decode() {
+        let legacy = LegacyStateDetails {
+            not_paused: false,
+            current_tick: 199_900_000,
+            max_leveragex10: 25,
+            min_collateral: 1000,
+        };
+
+        let state_details = StateDetails::from_bytes(Cow::Owned(Encode!(&legacy).unwrap()));
+
+        assert_eq!(state_details.mode, MarketMode::Paused);
+        assert_eq!(state_details.current_tick, 199_900_000);
+        assert_eq!(state_details.max_leverage_bps, 25_000);
+        assert_eq!(state_details.min_collateral, 1000);
+
+        let legacy = LegacyStateDetails {
+            not_paused: true,
+            ..legacy
+        };
+        let state_details = StateDetails::from_bytes(Cow::Owned(Encode!(&legacy).unwrap()));
+        assert_eq!(state_details.mode, MarketMode::Active);
+
+        // current state details still decode as is
+        assert_eq!(
+            StateDetails::from_bytes(state_details.to_bytes()),
+            state_details
+        );
+    }
+}
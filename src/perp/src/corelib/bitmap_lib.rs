@@ -1,18 +1,22 @@
 use super::bit_lib::{_least_sigbit_position, _most_sigbit_position};
 use super::tick_lib::{_next_default_tick, _tick_zero};
 
-use super::constants::{_ONE_BASIS_POINT, _ONE_PERCENT};
+use super::constants::_TICKS_PER_INTEGRAL;
+use crate::types::TickGrid;
 
 /// Flip Bit
 ///
 /// This function is used to flip a particlar bit on a bitmap,
 /// it either initialises it if it's not initialised or the reverse
+///
+/// Note
+///  - The bit position is the decimal part of a tick on the market's grid (see tick_lib) ,so the bitmap layout is the same for every tick spacing
 
 pub fn _flip_bit(bitmap: u128, bit_position: u64) -> u128 {
-    if bit_position == 0 {
+    if bit_position == 0 || bit_position >= _TICKS_PER_INTEGRAL {
         return bitmap;
     }
-    let mask = 1 << (99 - bit_position);
+    let mask = 1 << (_TICKS_PER_INTEGRAL - 1 - bit_position);
     return bitmap ^ mask;
 }
 
@@ -23,16 +27,23 @@ pub fn _flip_bit(bitmap: u128, bit_position: u64) -> u128 {
 /// Note
 ///  - This function returns the next default tick (see tick_lib) if no tick is initialised within the bitmap
 
-pub fn _next_initialised_tick(bitmap: u128, integral: u64, bit_position: u64, buy: bool) -> u64 {
-    let reference = 99 - bit_position;
+pub fn _next_initialised_tick(
+    bitmap: u128,
+    integral: u64,
+    bit_position: u64,
+    buy: bool,
+    grid: &TickGrid,
+) -> u64 {
+    let reference = _TICKS_PER_INTEGRAL - 1 - bit_position;
     if buy {
         let mask = ((1u128) << reference) - 1;
         let masked = bitmap & mask;
 
         if masked == 0 {
-            return _next_default_tick(integral, true); // (integral + 1) * _ONE_PERCENT;
+            return _next_default_tick(integral, true, grid); // (integral + 1) * _ONE_PERCENT;
         } else {
-            return (integral * _ONE_PERCENT) + (_most_sigbit_position(masked) * _ONE_BASIS_POINT);
+            return _tick_zero(integral, grid)
+                + (_most_sigbit_position(masked) * grid.tick_spacing);
         }
     } else {
         let mask = !(((1u128) << (reference + 1)) - 1);
@@ -40,12 +51,13 @@ pub fn _next_initialised_tick(bitmap: u128, integral: u64, bit_position: u64, bu
 
         if masked == 0 {
             if bit_position == 0 {
-                return _next_default_tick(integral, false);
+                return _next_default_tick(integral, false, grid);
             }
 
-            return _tick_zero(integral); // (integral - 1) * _ONE_PERCENT + (99 * _ONE_BASIS_POINT)
+            return _tick_zero(integral, grid); // (integral - 1) * _ONE_PERCENT + (99 * _ONE_BASIS_POINT)
         } else {
-            return (integral * _ONE_PERCENT) + (_least_sigbit_position(masked) * _ONE_BASIS_POINT);
+            return _tick_zero(integral, grid)
+                + (_least_sigbit_position(masked) * grid.tick_spacing);
         }
     }
 }
@@ -53,19 +65,22 @@ pub fn _next_initialised_tick(bitmap: u128, integral: u64, bit_position: u64, bu
 #[test]
 
 fn test_next_initialised_tick() {
+    use super::constants::{_ONE_BASIS_POINT, _ONE_PERCENT};
+
+    let grid = TickGrid::default();
     let bitmap = 8;
     let integral = 100;
-    let next1 = _next_initialised_tick(bitmap, integral, 96, true);
+    let next1 = _next_initialised_tick(bitmap, integral, 96, true, &grid);
 
     assert_eq!(next1, (integral + 1) * _ONE_PERCENT);
 
     let bitmap2 = 20;
 
-    let next2 = _next_initialised_tick(bitmap2, integral, 95, true);
+    let next2 = _next_initialised_tick(bitmap2, integral, 95, true, &grid);
 
     assert_eq!(next2, (integral * _ONE_PERCENT) + (97 * _ONE_BASIS_POINT));
 
-    let next3 = _next_initialised_tick(bitmap2, integral, 97, false);
+    let next3 = _next_initialised_tick(bitmap2, integral, 97, false, &grid);
 
     assert_eq!(next3, (integral * _ONE_PERCENT) + (95 * _ONE_BASIS_POINT));
 }
@@ -87,5 +102,51 @@ mod unit_test {
         let val2 = _flip_bit(bitmap, 96);
 
         assert_eq!(val2, 0);
+
+        // positions outside the bitmap are ignored
+        assert_eq!(_flip_bit(bitmap, _TICKS_PER_INTEGRAL), bitmap);
+    }
+
+    #[test]
+    fn test_next_initialised_tick_custom_grid() {
+        use crate::corelib::tick_lib::_int_and_dec;
+
+        // ticks 5 basis points apart ,each integral spans 5%
+        let grid = TickGrid {
+            tick_spacing: 5 * 1000,
+            ..TickGrid::default()
+        };
+
+        let tick1 = 1_530_000;
+        let tick2 = 1_550_000;
+
+        let (integral, dec1) = _int_and_dec(tick1, &grid);
+        let (_, dec2) = _int_and_dec(tick2, &grid);
+        assert_eq!((integral, dec1, dec2), (3, 6, 10));
+
+        let bitmap = _flip_bit(_flip_bit(0, dec1), dec2);
+
+        assert_eq!(
+            _next_initialised_tick(bitmap, integral, 0, true, &grid),
+            tick1
+        );
+        assert_eq!(
+            _next_initialised_tick(bitmap, integral, dec1, true, &grid),
+            tick2
+        );
+        assert_eq!(
+            _next_initialised_tick(bitmap, integral, dec2, false, &grid),
+            tick1
+        );
+        // no tick initialised above ,so the tick zero of the next integral is returned
+        assert_eq!(
+            _next_initialised_tick(bitmap, integral, dec2, true, &grid),
+            4 * grid.integral_width()
+        );
+        // no tick initialised below ,so the tick zero of the integral is returned
+        assert_eq!(
+            _next_initialised_tick(bitmap, integral, dec1, false, &grid),
+            3 * grid.integral_width()
+        );
     }
 }
@@ -0,0 +1,276 @@
+use super::bitmap_lib::_next_initialised_tick;
+use super::store_lib::Store;
+use super::tick_lib::{_exceeded_stopping_tick, _int_and_dec, _next_default_tick};
+use crate::types::{TickDetails, TickGrid};
+
+type Tick = u64;
+type Amount = u128;
+
+/// BookParams for reading the depth of the order book
+///
+/// Unlike SwapParams ,BookParams only holds immutable references and never modifies the book
+pub struct BookParams<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> {
+    /// Integrals Bitmaps
+    ///
+    /// Store  of integrals to their bitmaps
+    pub integrals_bitmaps: &'a MB,
+    /// Ticks Details
+    ///
+    /// Store  of ticks to their  respective tick_details
+    pub ticks_details: &'a TD,
+    /// Grid
+    ///
+    /// The tick grid of the market
+    pub grid: &'a TickGrid,
+}
+
+impl<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> BookParams<'a, MB, TD> {
+    /// Depth Function
+    ///
+    /// Walks the initialised ticks starting at the start tick in the particular direction ,the same way a swap would
+    ///
+    /// Params
+    ///  - Start Tick :The tick to start walking from ,usually the current state tick
+    ///  - Stopping Tick :The tick beyond which the walk does not go
+    ///  - Levels :The maximum number of ticks with liquidity to return
+    ///  - Buy :true for walking up through sell orders (asks) and false for walking down through buy orders (bids)
+    ///
+    /// Returns
+    ///  - Depth :A vector of ticks and the static liquidity at each tick ,ordered from the start tick outwards
+    ///
+    /// Note
+    ///  - Liquidity is in the base (perp) asset for asks and in the quote (collateral) asset for bids
+    pub fn _depth(
+        &self,
+        start_tick: Tick,
+        stopping_tick: Tick,
+        levels: usize,
+        buy: bool,
+    ) -> Vec<(Tick, Amount)> {
+        let mut depth: Vec<(Tick, Amount)> = Vec::new();
+
+        let mut loop_current_tick = start_tick;
+
+        while depth.len() < levels {
+            let (integral, bit_position) = _int_and_dec(loop_current_tick, self.grid);
+
+            // no tick below tick zero of integral zero
+            let lowest_tick = !buy && integral == 0;
+
+            let bitmap = match self.integrals_bitmaps._get(&integral) {
+                Some(res) => res,
+                None => {
+                    if lowest_tick {
+                        break;
+                    }
+                    let next_default_tick = _next_default_tick(integral, buy, self.grid);
+                    if _exceeded_stopping_tick(next_default_tick, stopping_tick, buy) {
+                        break;
+                    };
+                    loop_current_tick = next_default_tick;
+                    continue;
+                }
+            };
+
+            if let Some(tick_details) = self.ticks_details._get(&loop_current_tick) {
+                let liquidity = if buy {
+                    tick_details.liq_bounds_token0._liquidity_within()
+                } else {
+                    tick_details.liq_bounds_token1._liquidity_within()
+                };
+
+                if liquidity > 0 {
+                    depth.push((loop_current_tick, liquidity));
+                }
+            }
+
+            if lowest_tick && bit_position == 0 {
+                break;
+            }
+
+            let next_initialised_tick =
+                _next_initialised_tick(bitmap, integral, bit_position, buy, self.grid);
+
+            if _exceeded_stopping_tick(next_initialised_tick, stopping_tick, buy) {
+                break;
+            };
+
+            loop_current_tick = next_initialised_tick;
+        }
+
+        return depth;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::corelib::bitmap_lib::_flip_bit;
+    use crate::corelib::constants::{_ONE_BASIS_POINT, _ONE_PERCENT};
+    use std::collections::HashMap;
+
+    type MB = HashMap<u64, u128>;
+    type TD = HashMap<Tick, TickDetails>;
+
+    fn _fill_tick(
+        integrals_bitmaps: &mut MB,
+        ticks_details: &mut TD,
+        tick: Tick,
+        amount_in: Amount,
+        buy: bool,
+        grid: &TickGrid,
+    ) {
+        let tick_details = ticks_details.entry(tick).or_insert_with(|| {
+            let (int, dec) = _int_and_dec(tick, grid);
+            let bitmap = integrals_bitmaps.entry(int).or_insert(0);
+            *bitmap = _flip_bit(*bitmap, dec);
+            TickDetails::default()
+        });
+        tick_details._add_liquidity(buy, amount_in);
+    }
+
+    #[test]
+    fn test_depth_both_sides() {
+        let grid = TickGrid::default();
+        let mut integrals_bitmaps = MB::new();
+        let mut ticks_details = TD::new();
+
+        let current_tick = 200 * _ONE_PERCENT;
+
+        // asks
+        let ask_ticks = [
+            200 * _ONE_PERCENT + 5 * _ONE_BASIS_POINT,
+            201 * _ONE_PERCENT,
+            203 * _ONE_PERCENT + 50 * _ONE_BASIS_POINT,
+        ];
+        for (index, tick) in ask_ticks.iter().enumerate() {
+            _fill_tick(
+                &mut integrals_bitmaps,
+                &mut ticks_details,
+                *tick,
+                1000 * (index as u128 + 1),
+                false,
+                &grid,
+            );
+        }
+        // bids
+        let bid_ticks = [
+            199 * _ONE_PERCENT + 99 * _ONE_BASIS_POINT,
+            150 * _ONE_PERCENT,
+        ];
+        for tick in bid_ticks {
+            _fill_tick(
+                &mut integrals_bitmaps,
+                &mut ticks_details,
+                tick,
+                500,
+                true,
+                &grid,
+            );
+        }
+
+        let book = BookParams {
+            integrals_bitmaps: &integrals_bitmaps,
+            ticks_details: &ticks_details,
+            grid: &grid,
+        };
+
+        let asks = book._depth(current_tick, 250 * _ONE_PERCENT, 10, true);
+        assert_eq!(
+            asks,
+            vec![
+                (ask_ticks[0], 1000),
+                (ask_ticks[1], 2000),
+                (ask_ticks[2], 3000)
+            ]
+        );
+
+        // levels are limited
+        let top_ask = book._depth(current_tick, 250 * _ONE_PERCENT, 1, true);
+        assert_eq!(top_ask, vec![(ask_ticks[0], 1000)]);
+
+        // walk stops at stopping tick
+        let bids = book._depth(current_tick, 160 * _ONE_PERCENT, 10, false);
+        assert_eq!(bids, vec![(bid_ticks[0], 500)]);
+
+        let all_bids = book._depth(current_tick, 0, 10, false);
+        assert_eq!(all_bids, vec![(bid_ticks[0], 500), (bid_ticks[1], 500)]);
+    }
+
+    #[test]
+    fn test_depth_empty_book() {
+        let integrals_bitmaps = MB::new();
+        let ticks_details = TD::new();
+
+        let book = BookParams {
+            integrals_bitmaps: &integrals_bitmaps,
+            ticks_details: &ticks_details,
+            grid: &TickGrid::default(),
+        };
+
+        assert!(book._depth(5 * _ONE_PERCENT, 0, 10, false).is_empty());
+        assert!(book
+            ._depth(5 * _ONE_PERCENT, 10 * _ONE_PERCENT, 10, true)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_depth_coarse_grid() {
+        // ticks 10 basis points apart ,each integral spans 10%
+        let grid = TickGrid {
+            tick_spacing: 10 * _ONE_BASIS_POINT,
+            ..TickGrid::default()
+        };
+        let mut integrals_bitmaps = MB::new();
+        let mut ticks_details = TD::new();
+
+        let current_tick = 200 * _ONE_PERCENT;
+
+        // asks within the same integral and across an empty integral
+        let ask_ticks = [
+            200 * _ONE_PERCENT + 10 * _ONE_BASIS_POINT,
+            209 * _ONE_PERCENT + 90 * _ONE_BASIS_POINT,
+            225 * _ONE_PERCENT,
+        ];
+        for tick in ask_ticks {
+            _fill_tick(
+                &mut integrals_bitmaps,
+                &mut ticks_details,
+                tick,
+                1000,
+                false,
+                &grid,
+            );
+        }
+        // bid in the previous integral
+        let bid_tick = 195 * _ONE_PERCENT;
+        _fill_tick(
+            &mut integrals_bitmaps,
+            &mut ticks_details,
+            bid_tick,
+            500,
+            true,
+            &grid,
+        );
+
+        assert_eq!(integrals_bitmaps.len(), 3);
+
+        let book = BookParams {
+            integrals_bitmaps: &integrals_bitmaps,
+            ticks_details: &ticks_details,
+            grid: &grid,
+        };
+
+        assert_eq!(
+            book._depth(current_tick, 250 * _ONE_PERCENT, 10, true),
+            ask_ticks
+                .iter()
+                .map(|tick| (*tick, 1000))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            book._depth(current_tick, 0, 10, false),
+            vec![(bid_tick, 500)]
+        );
+    }
+}
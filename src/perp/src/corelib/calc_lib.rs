@@ -1,6 +1,8 @@
 use ic_cdk::api::time;
 
 use super::constants::*;
+use super::math_lib::{_mul_div, Rounding};
+use crate::types::MathError;
 
 type Amount = u128;
 
@@ -9,39 +11,102 @@ type Amount = u128;
 /// This function calculates the interest on a leveraged position since when it was filled
 /// The interest is calculated on an hourly basis
 ///
-/// Note:Interest only counts if position is older than one hour  
+/// Note:Interest only counts if position is older than one hour ,interest is owed so it is rounded up
 
-pub fn _calc_interest(debt: Amount, interest_rate: u32, start_time: u64) -> Amount {
-    let mut fee: Amount = 0;
+pub fn _calc_interest(
+    debt: Amount,
+    interest_rate: u32,
+    start_time: u64,
+) -> Result<Amount, MathError> {
+    return _calc_interest_at(debt, interest_rate, start_time, time());
+}
 
+/// Calculate Interest At Function
+///
+/// Similar to the Calculate Interest Function but for a given current time
+///
+/// Note:A position exactly one hour old is not charged ,the first hour is charged once the position is older than one hour
+pub fn _calc_interest_at(
+    debt: Amount,
+    interest_rate: u32,
+    start_time: u64,
+    current_time: u64,
+) -> Result<Amount, MathError> {
     let one_hour: u64 = 3600 * ((10u64).pow(9));
 
-    let mut _starting_time = start_time;
+    // an hour is only charged once it has fully elapsed ,i.e start time + hours * one hour < current time
+    let hours = current_time.saturating_sub(start_time).saturating_sub(1) / one_hour;
 
-    let current_time = time();
+    let hourly_fee = _mul_div(
+        interest_rate as u128,
+        debt,
+        u128::from(100 * _ONE_PERCENT),
+        Rounding::Up,
+    )?;
 
-    while start_time + one_hour < current_time {
-        fee += ((interest_rate as u128) * debt) / u128::from(100 * _ONE_PERCENT);
+    return hourly_fee
+        .checked_mul(hours as u128)
+        .ok_or(MathError::Overflow);
+}
 
-        _starting_time += one_hour;
-    }
+/// Calculate Backoff
+///
+/// Calculates the delay before the next attempt after a number of failed attempts,
+/// doubling from the base delay with every attempt up to the max delay
+pub fn _calc_backoff(base_delay: u64, attempts: u32, max_delay: u64) -> u64 {
+    let multiple = 1u64
+        .checked_shl(attempts.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    return base_delay.saturating_mul(multiple).min(max_delay);
+}
+
+/// Calculate Debt
+///
+/// Calculates the debt borrowed for a position of the collateral at the leverage
+///
+/// Note:Leverage is in basis points and must be at least 1x ,debt is paid out by the vault so it is rounded down
+pub fn _calc_debt(collateral: Amount, leverage_bps: u64) -> Result<Amount, MathError> {
+    _mul_div(
+        collateral,
+        (leverage_bps - _ONE_X_LEVERAGE_BPS) as u128,
+        _ONE_X_LEVERAGE_BPS as u128,
+        Rounding::Down,
+    )
+}
 
-    return fee;
+/// Calculate Margin Ratio
+///
+/// Calculates the equity of a position as a percentage of the current position value
+///
+/// Note:Returns zero if equity is exhausted and saturates at u64::MAX
+pub fn _calc_margin_ratio(equity: i128, position_value: Amount) -> u64 {
+    if equity <= 0 || position_value == 0 {
+        return 0;
+    }
+    let ratio = _mul_div(
+        equity as u128,
+        (100 * _ONE_PERCENT) as u128,
+        position_value,
+        Rounding::Down,
+    );
+    return ratio.map_or(u64::MAX, |ratio| ratio.min(u64::MAX as u128) as u64);
 }
 
 /// Calculates Shares
 ///
 /// This function calculates the amount of shares given the amount of asset being put in ,the current total shares and the current net liquidity
+///
+/// Note:Shares are rounded down ,same as share values ,so a share is never worth more than what was put in
 
 pub fn _calc_shares(
     amount_in: Amount,
     init_total_shares: Amount,
     init_liquidity: Amount,
-) -> Amount {
+) -> Result<Amount, MathError> {
     if init_total_shares == 0 {
-        return amount_in;
+        return Ok(amount_in);
     }
-    return (amount_in * init_total_shares) / init_liquidity;
+    return _mul_div(amount_in, init_total_shares, init_liquidity, Rounding::Down);
 }
 
 /// Calculate Shares Value
@@ -51,18 +116,171 @@ pub fn _calc_shares_value(
     shares: Amount,
     init_total_shares: Amount,
     init_liquidity: Amount,
-) -> Amount {
-    return (shares * init_liquidity) / init_total_shares;
+) -> Result<Amount, MathError> {
+    return _mul_div(shares, init_liquidity, init_total_shares, Rounding::Down);
 }
 
 /// Percentage Functions
 ///
 /// These functions  calculates percentages  
 
-pub fn _percentage128(x: u64, value: Amount) -> Amount {
-    return ((x as u128) * value) / (100 * _ONE_PERCENT as u128);
+pub fn _percentage128(x: u64, value: Amount, rounding: Rounding) -> Result<Amount, MathError> {
+    return _mul_div(x as u128, value, (100 * _ONE_PERCENT) as u128, rounding);
 }
 
-pub fn _percentage64(x: u64, value: u64) -> u64 {
-    return (x * value) / (100 * _ONE_PERCENT);
+pub fn _percentage64(x: u64, value: u64) -> Result<u64, MathError> {
+    let percentage = _percentage128(x, value as u128, Rounding::Down)?;
+    return u64::try_from(percentage).map_err(|_| MathError::Overflow);
+}
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+
+    #[test]
+    fn test_calc_interest_at() {
+        let one_hour: u64 = 3600 * ((10u64).pow(9));
+        let start_time = 10 * one_hour;
+
+        // 1% hourly
+        let interest_rate = _ONE_PERCENT as u32;
+
+        // less than an hour
+        assert_eq!(
+            _calc_interest_at(
+                1_000_000,
+                interest_rate,
+                start_time,
+                start_time + one_hour - 1
+            )
+            .unwrap(),
+            0
+        );
+
+        // exactly an hour
+        assert_eq!(
+            _calc_interest_at(1_000_000, interest_rate, start_time, start_time + one_hour).unwrap(),
+            0
+        );
+
+        assert_eq!(
+            _calc_interest_at(
+                1_000_000,
+                interest_rate,
+                start_time,
+                start_time + one_hour + 1
+            )
+            .unwrap(),
+            10_000
+        );
+
+        assert_eq!(
+            _calc_interest_at(
+                1_000_000,
+                interest_rate,
+                start_time,
+                start_time + 3 * one_hour
+            )
+            .unwrap(),
+            20_000
+        );
+
+        // partial hours are not counted
+        assert_eq!(
+            _calc_interest_at(
+                1_000_000,
+                interest_rate,
+                start_time,
+                start_time + 3 * one_hour + one_hour / 2
+            )
+            .unwrap(),
+            30_000
+        );
+
+        // fractions of the hourly fee are owed in full
+        assert_eq!(
+            _calc_interest_at(
+                1_000_001,
+                interest_rate,
+                start_time,
+                start_time + one_hour + 1
+            )
+            .unwrap(),
+            10_001
+        );
+
+        // start time after current time
+        assert_eq!(
+            _calc_interest_at(1_000_000, interest_rate, start_time, 0).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_calc_backoff() {
+        let (base_delay, max_delay) = (60, 3600);
+
+        assert_eq!(_calc_backoff(base_delay, 0, max_delay), 60);
+        assert_eq!(_calc_backoff(base_delay, 1, max_delay), 60);
+        assert_eq!(_calc_backoff(base_delay, 2, max_delay), 120);
+        assert_eq!(_calc_backoff(base_delay, 4, max_delay), 480);
+
+        // capped at max delay
+        assert_eq!(_calc_backoff(base_delay, 10, max_delay), max_delay);
+        assert_eq!(_calc_backoff(base_delay, 100, max_delay), max_delay);
+    }
+
+    #[test]
+    fn test_calc_debt() {
+        assert_eq!(_calc_debt(1000, _ONE_X_LEVERAGE_BPS).unwrap(), 0);
+        assert_eq!(_calc_debt(1000, 15_000).unwrap(), 500);
+        // steps finer than 0.1x
+        assert_eq!(_calc_debt(1000, 10_025).unwrap(), 2);
+        // leverage above 25.5x
+        assert_eq!(_calc_debt(1000, 1_000_000).unwrap(), 99_000);
+        // 18 decimal collateral whose product with the leverage overflows 128 bits
+        let collateral = u128::MAX / 10;
+        assert_eq!(
+            _calc_debt(collateral, 2 * _ONE_X_LEVERAGE_BPS),
+            Ok(collateral)
+        );
+        assert_eq!(
+            _calc_debt(u128::MAX, 3 * _ONE_X_LEVERAGE_BPS),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_calc_margin_ratio() {
+        assert_eq!(_calc_margin_ratio(250, 1000), 25 * _ONE_PERCENT);
+        assert_eq!(_calc_margin_ratio(0, 1000), 0);
+        assert_eq!(_calc_margin_ratio(-100, 1000), 0);
+        assert_eq!(_calc_margin_ratio(100, 0), 0);
+    }
+
+    #[test]
+    fn test_calc_shares() {
+        assert_eq!(_calc_shares(1000, 0, 0), Ok(1000));
+        assert_eq!(_calc_shares(1000, 500, 2000), Ok(250));
+        // shares and share values both round down
+        assert_eq!(_calc_shares(1001, 500, 2000), Ok(250));
+        assert_eq!(_calc_shares_value(251, 500, 2000), Ok(1004));
+        assert_eq!(_calc_shares_value(1, 3, 2), Ok(0));
+        assert_eq!(_calc_shares_value(250, 500, 2000), Ok(1000));
+
+        let amount = 10u128.pow(30);
+        assert_eq!(_calc_shares(amount, amount, amount), Ok(amount));
+        assert_eq!(_calc_shares_value(amount, amount, amount), Ok(amount));
+
+        assert_eq!(_calc_shares(1000, 500, 0), Err(MathError::DivisionByZero));
+        assert_eq!(
+            _percentage128(10 * _ONE_PERCENT, amount, Rounding::Down),
+            Ok(amount / 10)
+        );
+        assert_eq!(
+            _percentage64(200 * _ONE_PERCENT, u64::MAX),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(_calc_margin_ratio(i128::MAX, 1), u64::MAX);
+    }
 }
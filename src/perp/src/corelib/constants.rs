@@ -1,7 +1,13 @@
+pub use market_types::constants::{
+    _BASE_PRICE, _MAX_ASSET_DECIMALS, _ONE_BASIS_POINT, _ONE_X_LEVERAGE_BPS, _TICKS_PER_INTEGRAL,
+};
+
 pub const _PRICE_DECIMAL: u128 = 1000_000_000;
 
-pub const _BASE_PRICE: u128 = 1000_000_000;
+pub const _ONE_PERCENT: u64 = 100_000;
 
-pub const _ONE_BASIS_POINT: u64 = 1000;
+/// Max number of basis point steps from the anchor tick in the geometric tick model ,1.0001^200_000 is about 4.85e8
+pub const _MAX_GEOMETRIC_STEPS: u64 = 200_000;
 
-pub const _ONE_PERCENT: u64 = 100_000;
+/// Tick priced at the base price in the geometric tick model
+pub const _GEOMETRIC_ANCHOR_TICK: u64 = _MAX_GEOMETRIC_STEPS * _ONE_BASIS_POINT;
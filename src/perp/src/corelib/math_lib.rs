@@ -0,0 +1,204 @@
+use crate::types::MathError;
+
+///MathLib library for multiplying and dividing amounts without overflowing the intermediate product
+///
+/// The product of two amounts is held in 256 bits as a (high ,low) pair of u128 words
+
+type Amount = u128;
+
+/// Rounding
+///
+/// The direction a division result that is not exact is rounded in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Rounds towards zero
+    Down,
+    /// Rounds away from zero
+    Up,
+}
+
+/// Mul Div
+///
+/// Calculates a * b / denominator with a 256 bit intermediate product
+///
+/// Returns
+///  - DivisionByZero if the denominator is zero
+///  - Overflow if the result does not fit within 128 bits
+pub fn _mul_div(
+    a: Amount,
+    b: Amount,
+    denominator: Amount,
+    rounding: Rounding,
+) -> Result<Amount, MathError> {
+    if denominator == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+
+    let (high, low) = _full_mul(a, b);
+
+    let (quotient, remainder) = _div_rem_256(high, low, denominator)?;
+
+    _round(quotient, remainder, rounding)
+}
+
+/// Div
+///
+/// Calculates numerator / denominator rounded in the rounding direction
+pub fn _div(
+    numerator: Amount,
+    denominator: Amount,
+    rounding: Rounding,
+) -> Result<Amount, MathError> {
+    if denominator == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+    _round(numerator / denominator, numerator % denominator, rounding)
+}
+
+/// Checked Add
+///
+/// Adds two amounts ,returning Overflow instead of panicking if the sum does not fit within 128 bits
+pub fn _checked_add(a: Amount, b: Amount) -> Result<Amount, MathError> {
+    a.checked_add(b).ok_or(MathError::Overflow)
+}
+
+fn _round(quotient: Amount, remainder: Amount, rounding: Rounding) -> Result<Amount, MathError> {
+    if rounding == Rounding::Up && remainder != 0 {
+        return quotient.checked_add(1).ok_or(MathError::Overflow);
+    }
+    Ok(quotient)
+}
+
+/// Full Mul
+///
+/// Calculates the 256 bit product of two amounts as a (high ,low) pair
+fn _full_mul(a: Amount, b: Amount) -> (Amount, Amount) {
+    if let Some(product) = a.checked_mul(b) {
+        return (0, product);
+    }
+
+    const MASK: Amount = u64::MAX as Amount;
+
+    let (a_high, a_low) = (a >> 64, a & MASK);
+    let (b_high, b_low) = (b >> 64, b & MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    // sum of the middle 64 bit words ,fits within 66 bits
+    let middle = (low_low >> 64) + (low_high & MASK) + (high_low & MASK);
+
+    let low = (low_low & MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+
+    (high, low)
+}
+
+/// Div Rem 256
+///
+/// Divides a 256 bit (high ,low) number by a non zero denominator returning the quotient and remainder
+///
+/// Note
+///  - Returns Overflow if the quotient does not fit within 128 bits i.e high is not less than the denominator
+fn _div_rem_256(
+    high: Amount,
+    low: Amount,
+    denominator: Amount,
+) -> Result<(Amount, Amount), MathError> {
+    if high == 0 {
+        return Ok((low / denominator, low % denominator));
+    }
+    if high >= denominator {
+        return Err(MathError::Overflow);
+    }
+
+    // binary long division ,the remainder is always less than the denominator before each shift
+    let mut remainder = high;
+    let mut quotient: Amount = 0;
+
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+
+        if carry == 1 || remainder >= denominator {
+            remainder = remainder.wrapping_sub(denominator);
+            quotient |= 1;
+        }
+    }
+
+    Ok((quotient, remainder))
+}
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+
+    #[test]
+    fn test_mul_div() {
+        assert_eq!(_mul_div(10, 20, 3, Rounding::Down), Ok(66));
+        assert_eq!(_mul_div(10, 20, 3, Rounding::Up), Ok(67));
+        assert_eq!(_mul_div(10, 20, 4, Rounding::Up), Ok(50));
+
+        // intermediate product overflows u128 but the result does not
+        let one_token = 10u128.pow(18);
+        let amount = 10u128.pow(12) * one_token;
+        assert!(amount.checked_mul(amount).is_none());
+
+        assert_eq!(_mul_div(amount, amount, amount, Rounding::Down), Ok(amount));
+        assert_eq!(
+            _mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Up),
+            Ok(u128::MAX)
+        );
+        assert_eq!(
+            _mul_div(u128::MAX, 3, 4, Rounding::Down),
+            Ok(u128::MAX / 4 * 3 + 2)
+        );
+        assert_eq!(
+            _mul_div(u128::MAX, 3, 4, Rounding::Up),
+            Ok(u128::MAX / 4 * 3 + 3)
+        );
+
+        // results beyond 128 bits and division by zero are errors
+        assert_eq!(
+            _mul_div(u128::MAX, 2, 1, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(
+            _mul_div(u128::MAX, u128::MAX, u128::MAX - 1, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(
+            _mul_div(u128::MAX, u128::MAX, u128::MAX, Rounding::Down),
+            Ok(u128::MAX)
+        );
+        assert_eq!(
+            _mul_div(1, 1, 0, Rounding::Down),
+            Err(MathError::DivisionByZero)
+        );
+        // 7 * b = 2^129 - 1 ,so the quotient by 2 is u128::MAX with a remainder of 1
+        let b = 97_223_533_405_982_418_132_392_744_980_505_203_273;
+        assert_eq!(_mul_div(7, b, 2, Rounding::Down), Ok(u128::MAX));
+        // rounding up past the max is an overflow
+        assert_eq!(_mul_div(7, b, 2, Rounding::Up), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn test_full_mul_and_div() {
+        // (2^128 - 1)^2 = 2^256 - 2^129 + 1
+        assert_eq!(_full_mul(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+        assert_eq!(_full_mul(1 << 64, 1 << 64), (1, 0));
+
+        let (high, low) = _full_mul(123_456_789_987_654_321 << 40, 987_654_321_123_456_789 << 30);
+        assert_eq!(
+            _div_rem_256(high, low, 987_654_321_123_456_789 << 30),
+            Ok((123_456_789_987_654_321 << 40, 0))
+        );
+
+        assert_eq!(_div(7, 2, Rounding::Down), Ok(3));
+        assert_eq!(_div(7, 2, Rounding::Up), Ok(4));
+        assert_eq!(_div(7, 0, Rounding::Up), Err(MathError::DivisionByZero));
+    }
+}
@@ -5,19 +5,25 @@ pub mod order_lib;
 pub mod bit_lib;
 pub mod bitmap_lib;
 
+pub mod book_lib;
+
 pub mod calc_lib;
 
+pub mod math_lib;
+
 pub mod constants;
 
 pub mod price_lib;
 
+pub mod store_lib;
+
 pub mod tick_lib;
 
 #[cfg(test)]
 
 mod unit_test {
     use super::*;
-    use crate::types::{Amount, Tick, TickDetails};
+    use crate::types::{Amount, AssetDecimals, Tick, TickDetails, TickGrid};
     use std::cell::RefCell;
     use std::collections::HashMap;
 
@@ -120,6 +126,171 @@ mod unit_test {
             assert_eq!(amount_remaining, order1.order_size - amount_out);
         }
     }
+    #[test]
+    fn test_randomized_sequences_keep_balances_and_liquidity() {
+        for seed in 1..=25 {
+            _run_random_sequence(seed, 300);
+        }
+    }
+
+    /// Run Random Sequence
+    ///
+    /// Places ,fills and closes orders in a random sequence while tracking the balances held for the book ,
+    /// i.e every amount put in by order owners and swappers minus every amount paid out to them
+    ///
+    /// Asserts that
+    ///  - neither balance ever goes negative and each covers the liquidity left at all ticks
+    ///  - the liquidity at a tick never goes negative
+    ///  - no liquidity is left at any tick once every order is closed
+    ///
+    /// Note
+    ///  - Closing an order ahead of another open order at the same tick shifts the position of the later order by the removed liquidity ,
+    /// so orders are only closed newest first at a tick until the end of the sequence
+    fn _run_random_sequence(seed: u64, steps: u64) {
+        TICKS_DETAILS.with_borrow_mut(|reference| reference.clear());
+        MULTIPLIERS_BITMAPS.with_borrow_mut(|reference| reference.clear());
+
+        let spacing = TickGrid::default().tick_spacing;
+        let mid_tick = 200 * constants::_ONE_PERCENT + 37 * spacing;
+
+        let mut random_state = seed;
+        let (mut base_balance, mut quote_balance): (Amount, Amount) = (0, 0);
+        let mut open_orders: Vec<order_lib::LimitOrder> = Vec::new();
+
+        let close = |order: order_lib::LimitOrder,
+                     base_balance: &mut Amount,
+                     quote_balance: &mut Amount| {
+            let (amount_out, amount_remaining) = _close_order(&order);
+            let (out_balance, remaining_balance) = if order.buy {
+                (base_balance, quote_balance)
+            } else {
+                (quote_balance, base_balance)
+            };
+            *out_balance = out_balance
+                .checked_sub(amount_out)
+                .expect("balance went negative paying out a filled order");
+            *remaining_balance = remaining_balance
+                .checked_sub(amount_remaining)
+                .expect("balance went negative refunding an order");
+        };
+
+        for _ in 0..steps {
+            let size = 1 + (_next_random(&mut random_state) % 1_000_000_000_007) as Amount;
+            let offset = _next_random(&mut random_state) % 30;
+
+            let crossed_ticks = match _next_random(&mut random_state) % 5 {
+                // buy orders below the mid tick and sell orders at or above it
+                0 => {
+                    let mut order =
+                        order_lib::LimitOrder::new(size, mid_tick - (offset + 1) * spacing, true);
+                    _open_order(&mut order);
+                    quote_balance += size;
+                    open_orders.push(order);
+                    Vec::new()
+                }
+                1 => {
+                    let mut order =
+                        order_lib::LimitOrder::new(size, mid_tick + offset * spacing, false);
+                    _open_order(&mut order);
+                    base_balance += size;
+                    open_orders.push(order);
+                    Vec::new()
+                }
+                2 => {
+                    let (amount_out, amount_remaining, _, crossed_ticks) =
+                        _swap(size, true, mid_tick, mid_tick + offset * spacing);
+                    quote_balance += size - amount_remaining;
+                    base_balance = base_balance
+                        .checked_sub(amount_out)
+                        .expect("base balance went negative paying out a buy");
+                    crossed_ticks
+                }
+                3 => {
+                    let (amount_out, amount_remaining, _, crossed_ticks) = _swap(
+                        size,
+                        false,
+                        mid_tick - spacing,
+                        mid_tick - (offset + 1) * spacing,
+                    );
+                    base_balance += size - amount_remaining;
+                    quote_balance = quote_balance
+                        .checked_sub(amount_out)
+                        .expect("quote balance went negative paying out a sell");
+                    crossed_ticks
+                }
+                _ => {
+                    if !open_orders.is_empty() {
+                        // the newest open order at the tick of a random order is closed (see Note)
+                        let index = (_next_random(&mut random_state) as usize) % open_orders.len();
+                        let ref_tick = open_orders[index].ref_tick;
+                        let index = open_orders
+                            .iter()
+                            .rposition(|order| order.ref_tick == ref_tick)
+                            .unwrap();
+                        close(
+                            open_orders.remove(index),
+                            &mut base_balance,
+                            &mut quote_balance,
+                        );
+                    }
+                    Vec::new()
+                }
+            };
+
+            // orders at crossed ticks are closed straight away ,as the watcher does
+            let (crossed, remaining): (Vec<_>, Vec<_>) = open_orders
+                .into_iter()
+                .partition(|order| crossed_ticks.contains(&order.ref_tick));
+            open_orders = remaining;
+            for order in crossed {
+                close(order, &mut base_balance, &mut quote_balance);
+            }
+
+            let (base_liquidity, quote_liquidity) = _total_liquidity();
+            assert!(base_liquidity <= base_balance);
+            assert!(quote_liquidity <= quote_balance);
+        }
+
+        for order in open_orders {
+            close(order, &mut base_balance, &mut quote_balance);
+        }
+
+        // no phantom liquidity ,only rounding dust is left with the book
+        assert_eq!(_total_liquidity(), (0, 0));
+    }
+
+    /// Total Liquidity
+    ///
+    /// Returns the base and quote liquidity within all ticks ,panicking if the liquidity at any tick is negative
+    fn _total_liquidity() -> (Amount, Amount) {
+        TICKS_DETAILS.with_borrow(|reference| {
+            reference
+                .values()
+                .fold((0, 0), |(base_liquidity, quote_liquidity), tick_details| {
+                    let within = |bounds: crate::types::LiquidityBoundary| {
+                        bounds
+                            .upper_bound
+                            .checked_sub(bounds.lower_bound)
+                            .expect("tick liquidity went negative")
+                    };
+                    (
+                        base_liquidity + within(tick_details.liq_bounds_token0),
+                        quote_liquidity + within(tick_details.liq_bounds_token1),
+                    )
+                })
+        })
+    }
+
+    /// Next Random
+    ///
+    /// Xorshift pseudo random numbers ,so every sequence can be reproduced from its seed
+    fn _next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
     ///
     ///
     ///
@@ -139,6 +310,7 @@ mod unit_test {
                     order,
                     integrals_bitmaps: multipliers_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
                 };
                 open_order_params.open_order();
             })
@@ -156,8 +328,10 @@ mod unit_test {
                     order,
                     multipliers_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
+                    decimals: &AssetDecimals::default(),
                 };
-                close_order_params.close_order()
+                close_order_params.close_order().unwrap()
             })
         })
     }
@@ -169,9 +343,9 @@ mod unit_test {
         stopping_tick: Tick,
     ) -> (Amount, Amount, Tick, Vec<Tick>) {
         TICKS_DETAILS.with(|ref_ticks_details| {
-            let ticks_details = &mut ref_ticks_details.borrow_mut();
+            let ticks_details = &mut *ref_ticks_details.borrow_mut();
             MULTIPLIERS_BITMAPS.with(|ref_multiplier_bitmaps| {
-                let multipliers_bitmaps = &mut ref_multiplier_bitmaps.borrow_mut();
+                let multipliers_bitmaps = &mut *ref_multiplier_bitmaps.borrow_mut();
                 let mut swap_params = swap_lib::SwapParams {
                     buy,
                     init_tick,
@@ -179,6 +353,8 @@ mod unit_test {
                     order_size,
                     integrals_bitmaps: multipliers_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
+                    decimals: &AssetDecimals::default(),
                 };
                 swap_params._swap()
             })
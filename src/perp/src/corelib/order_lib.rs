@@ -1,73 +1,82 @@
 use serde::{Deserialize, Serialize};
 
 use super::bitmap_lib::_flip_bit;
+use super::math_lib::Rounding;
 
 use super::price_lib::_equivalent;
+use super::store_lib::Store;
 use super::tick_lib::{_int_and_dec, _tick_to_price};
 
 use candid::CandidType;
 
-use std::collections::HashMap;
-
-use crate::types::TickDetails;
+use crate::types::{AssetDecimals, MathError, TickDetails, TickGrid};
 
 type Amount = u128;
 type Tick = u64;
-type MB = HashMap<u64, u128>;
-type TD = HashMap<Tick, TickDetails>;
 
 /// Order Trait for different OrderTypes
 pub trait Order {
     fn _opening_update(&mut self, ref_tick_details: &mut TickDetails);
-    fn _closing_update(&self, ref_tick_details: &mut TickDetails) -> (Amount, Amount);
+    fn _closing_update(
+        &self,
+        ref_tick_details: &mut TickDetails,
+        grid: &TickGrid,
+        decimals: &AssetDecimals,
+    ) -> Result<(Amount, Amount), MathError>;
 }
 
 ///OpenOrderParams for creating orders
-#[derive(CandidType)]
-pub struct OpenOrderParams<'a> {
+pub struct OpenOrderParams<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> {
     /// Multiplier BitMaps
     ///
-    ///A HashMap of  multipliers (percentiles) to their respective bitmap
+    ///A Store of  multipliers (percentiles) to their respective bitmap
     pub integrals_bitmaps: &'a mut MB,
     ///Ticks Details
     ///
-    ///A HashMap of tick to their tick_details
+    ///A Store of tick to their tick_details
     pub ticks_details: &'a mut TD,
     /// Order
     ///
     /// A mutable refrence to any generic type that implements the Order trait  determing which order type is being opened
     pub order: &'a mut LimitOrder,
+    /// Grid
+    ///
+    /// The tick grid of the market
+    pub grid: &'a TickGrid,
 }
 
-impl<'a> OpenOrderParams<'a> {
+impl<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> OpenOrderParams<'a, MB, TD> {
     /// Open Order function
     ///
     /// creates an order at a particular tick
     pub fn open_order(&mut self) {
-        let tick_details = self
-            .ticks_details
-            .entry(self.order.ref_tick)
-            .or_insert_with(|| {
+        let mut tick_details = match self.ticks_details._get(&self.order.ref_tick) {
+            Some(res) => res,
+            None => {
                 //  flip bitmap
 
-                let (multiplier, bit_position) = _int_and_dec(self.order.ref_tick);
+                let (multiplier, bit_position) = _int_and_dec(self.order.ref_tick, self.grid);
 
-                let map = self.integrals_bitmaps.entry(multiplier).or_insert(0);
-                *map = _flip_bit(*map, bit_position);
+                let map = self.integrals_bitmaps._get(&multiplier).unwrap_or(0);
+                self.integrals_bitmaps
+                    ._insert(multiplier, _flip_bit(map, bit_position));
 
                 // initialises it with a default value
 
                 TickDetails::default()
-            });
+            }
+        };
+
+        self.order._opening_update(&mut tick_details);
 
-        self.order._opening_update(tick_details);
+        self.ticks_details
+            ._insert(self.order.ref_tick, tick_details);
     }
 }
 
 ///CloseOrderParams for closing order
 
-#[derive(CandidType)]
-pub struct CloseOrderParams<'a> {
+pub struct CloseOrderParams<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> {
     ///Order
     ///
     /// An immutable reference  to a  generic type order that implements the Order trait,
@@ -76,15 +85,23 @@ pub struct CloseOrderParams<'a> {
 
     /// Multipliers Bitmaps
     ///
-    ///A HashMap of  multipliers (percentiles) to their respective bitmap
+    ///A Store of  multipliers (percentiles) to their respective bitmap
     pub multipliers_bitmaps: &'a mut MB,
     ///Ticks Details
     ///
-    ///A HashMap of tick to their tick_details
+    ///A Store of tick to their tick_details
     pub ticks_details: &'a mut TD,
+    /// Grid
+    ///
+    /// The tick grid of the market
+    pub grid: &'a TickGrid,
+    /// Decimals
+    ///
+    /// The token decimals of the market's assets
+    pub decimals: &'a AssetDecimals,
 }
 
-impl<'a> CloseOrderParams<'a> {
+impl<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> CloseOrderParams<'a, MB, TD> {
     /// Close_order function
     ///
     /// Returns a tuple
@@ -92,38 +109,51 @@ impl<'a> CloseOrderParams<'a> {
     /// Note
     /// - If closing a trade order ,tuple represents amount out vs amount remaining
     /// - If closing a liquidity order ,tuple represents token0 amount and token1 amount corresponding order shares(see LiquidityOrder)
-    pub fn close_order(&mut self) -> (Amount, Amount) {
-        match self.ticks_details.get_mut(&self.order.ref_tick) {
-            Some(tick_details) => {
+    /// - The stores are left unchanged if the amounts can not be calculated
+    pub fn close_order(&mut self) -> Result<(Amount, Amount), MathError> {
+        match self.ticks_details._get(&self.order.ref_tick) {
+            Some(mut tick_details) => {
                 // if closing a trade  order ,this returns
                 // amount_out and amount remaining
                 // if closing a Liquidity order
                 // it returns token0 amount and token1 amount
-                let (amount0, amount1) = self.order._closing_update(tick_details);
+                let (amount0, amount1) =
+                    self.order
+                        ._closing_update(&mut tick_details, self.grid, self.decimals)?;
 
                 //  if all liquidity is zero delete tick_details
                 if tick_details.liq_bounds_token0._liquidity_within() == 0
                     && tick_details.liq_bounds_token1._liquidity_within() == 0
                 {
-                    self.ticks_details.remove(&self.order.ref_tick);
+                    self.ticks_details._remove(&self.order.ref_tick);
 
-                    let (multiplier, bit_position) = _int_and_dec(self.order.ref_tick);
+                    let (multiplier, bit_position) = _int_and_dec(self.order.ref_tick, self.grid);
                     // flip bitmap
 
-                    self.multipliers_bitmaps
-                        .entry(multiplier)
-                        .and_modify(|res| *res = _flip_bit(*res, bit_position));
+                    if let Some(bitmap) = self.multipliers_bitmaps._get(&multiplier) {
+                        self.multipliers_bitmaps
+                            ._insert(multiplier, _flip_bit(bitmap, bit_position));
+                    }
+                } else {
+                    self.ticks_details
+                        ._insert(self.order.ref_tick, tick_details);
                 };
-                return (amount0, amount1);
+                return Ok((amount0, amount1));
             }
             None => {
                 // if tick details does not exist means all trade order  that currently references that tick
                 //   has been filled
-                let tick_price = _tick_to_price(self.order.ref_tick);
-                return (
-                    _equivalent(self.order.order_size, tick_price, self.order.buy),
+                let tick_price = _tick_to_price(self.order.ref_tick, self.grid);
+                return Ok((
+                    _equivalent(
+                        self.order.order_size,
+                        tick_price,
+                        self.order.buy,
+                        self.decimals,
+                        Rounding::Down,
+                    )?,
                     0,
-                );
+                ));
             }
         };
     }
@@ -193,10 +223,21 @@ impl Order for LimitOrder {
     /// - Amount Out :This returns the amount of the particular asset expected from the order
     /// i.e base asset(perp asset) for a buy order and quote asset (collateral asset) for a sell order
     /// - Amount Remaining :This  returns the amount  not filled in the order  
-
-    fn _closing_update(&self, tick_details: &mut TickDetails) -> (Amount, Amount) {
-        let tick_price = _tick_to_price(self.ref_tick);
-        let equivalent = |amount: Amount| -> Amount { _equivalent(amount, tick_price, self.buy) };
+    ///
+    /// Note
+    ///  - The tick details are only updated if the amount out can be calculated
+    ///  - The amount out is paid out to the order owner so it is rounded down
+
+    fn _closing_update(
+        &self,
+        tick_details: &mut TickDetails,
+        grid: &TickGrid,
+        decimals: &AssetDecimals,
+    ) -> Result<(Amount, Amount), MathError> {
+        let tick_price = _tick_to_price(self.ref_tick, grid);
+        let equivalent = |amount: Amount| -> Result<Amount, MathError> {
+            _equivalent(amount, tick_price, self.buy, decimals, Rounding::Down)
+        };
 
         let (tick_lower_bound, order_lower_bound) = if self.buy {
             (
@@ -218,17 +259,17 @@ impl Order for LimitOrder {
         } else if tick_lower_bound < order_lower_bound + self.order_size {
             // order partially filled
             (
-                equivalent(tick_lower_bound - order_lower_bound),
+                equivalent(tick_lower_bound - order_lower_bound)?,
                 (order_lower_bound + self.order_size) - tick_lower_bound,
             )
         } else {
             // order fully filled
-            (equivalent(self.order_size), 0)
+            (equivalent(self.order_size)?, 0)
         };
 
         tick_details._remove_liquidity(self.buy, amount_remaining);
 
-        return (amount_out, amount_remaining);
+        return Ok((amount_out, amount_remaining));
     }
 }
 
@@ -382,6 +423,7 @@ mod unit_test_order_lib {
                     order,
                     integrals_bitmaps: multipliers_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
                 };
                 open_order_params.open_order();
             })
@@ -402,8 +444,10 @@ mod unit_test_order_lib {
                     order,
                     multipliers_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
+                    decimals: &AssetDecimals::default(),
                 };
-                close_order_params.close_order()
+                close_order_params.close_order().unwrap()
             })
         })
     }
@@ -1,11 +1,329 @@
 use super::constants::_PRICE_DECIMAL;
+use super::math_lib::{_div, _mul_div, Rounding};
+use crate::types::{AssetDecimals, MathError};
 
 type Amount = u128;
 
-pub fn _equivalent(amount: Amount, price: Amount, buy: bool) -> Amount {
+/// Equivalent
+///
+/// Converts a raw amount of one asset to the raw amount of the other asset at a price
+///
+/// Params
+///  - Amount :The amount of quote asset for a buy and base asset for a sell
+///  - Price :The price of a whole base token in whole quote tokens
+///  - Buy :true to convert quote to base and false to convert base to quote
+///  - Decimals :The token decimals of both assets
+///  - Rounding :Down for amounts paid out and up for amounts owed ,so rounding always favours the protocol
+///
+/// Note
+///  - Returns an error if the price is zero on a buy or the result does not fit within 128 bits
+pub fn _equivalent(
+    amount: Amount,
+    price: Amount,
+    buy: bool,
+    decimals: &AssetDecimals,
+    rounding: Rounding,
+) -> Result<Amount, MathError> {
     if buy {
-        return (amount * _PRICE_DECIMAL) / price;
+        return _quote_to_base(amount, price, decimals, rounding);
+    }
+    let (base_scale, quote_scale) = decimals.scales();
+    if base_scale == 1 {
+        return _mul_div(amount, price, _PRICE_DECIMAL * quote_scale, rounding);
+    }
+    // the base scale and the price decimal are both powers of ten ,so the smaller divides the larger exactly
+    if base_scale <= _PRICE_DECIMAL {
+        _mul_div(amount, price, _PRICE_DECIMAL / base_scale, rounding)
+    } else {
+        _mul_div(amount, price, 1, rounding)?
+            .checked_mul(base_scale / _PRICE_DECIMAL)
+            .ok_or(MathError::Overflow)
+    }
+}
+
+/// Quote To Base
+///
+/// Calculates quote amount * quote scale * _PRICE_DECIMAL / (divisor * base scale)
+///
+/// Note
+///  - One of both scales is always 1 ,dividing by the base scale after the full division rounded in the same direction
+/// gives the same result as a single division
+fn _quote_to_base(
+    quote_amount: Amount,
+    divisor: Amount,
+    decimals: &AssetDecimals,
+    rounding: Rounding,
+) -> Result<Amount, MathError> {
+    let (base_scale, quote_scale) = decimals.scales();
+    if base_scale == 1 {
+        return _mul_div(
+            quote_amount,
+            quote_scale * _PRICE_DECIMAL,
+            divisor,
+            rounding,
+        );
+    }
+    let amount = _mul_div(quote_amount, _PRICE_DECIMAL, divisor, rounding)?;
+    _div(amount, base_scale, rounding)
+}
+
+/// Average Price
+///
+/// Calculates the average execution price of a swap
+///
+/// Params
+///  - Amount In :The amount of asset swapped ,quote asset for a buy and base asset for a sell
+///  - Amount Out :The amount of asset gotten from the swap
+///  - Buy :The swap direction
+///  - Decimals :The token decimals of both assets
+///
+/// Note
+///  - returns zero if nothing was swapped
+///  - the average price is rounded down
+pub fn _average_price(
+    amount_in: Amount,
+    amount_out: Amount,
+    buy: bool,
+    decimals: &AssetDecimals,
+) -> Result<Amount, MathError> {
+    if amount_in == 0 || amount_out == 0 {
+        return Ok(0);
+    }
+    if buy {
+        return _implied_price(amount_in, amount_out, decimals, Rounding::Down);
     } else {
-        return (amount * price) / _PRICE_DECIMAL;
+        return _implied_price(amount_out, amount_in, decimals, Rounding::Down);
+    }
+}
+
+/// Implied Price
+///
+/// Calculates the price at which a quote asset amount is equivalent to a base asset amount
+///
+/// Note
+///  - returns zero if base amount is zero
+pub fn _implied_price(
+    quote_amount: Amount,
+    base_amount: Amount,
+    decimals: &AssetDecimals,
+    rounding: Rounding,
+) -> Result<Amount, MathError> {
+    if base_amount == 0 {
+        return Ok(0);
+    }
+    return _quote_to_base(quote_amount, base_amount, decimals, rounding);
+}
+
+/// Price Impact
+///
+/// Calculates the deviation of an execution price from a reference price in basis points
+pub fn _price_impact(reference_price: Amount, execution_price: Amount) -> u64 {
+    if reference_price == 0 || execution_price == 0 {
+        return 0;
+    }
+    let impact = _mul_div(
+        reference_price.abs_diff(execution_price),
+        10_000,
+        reference_price,
+        Rounding::Down,
+    );
+    return impact.map_or(u64::MAX, |impact| impact.min(u64::MAX as u128) as u64);
+}
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+
+    #[test]
+    fn test_average_price_and_impact() {
+        let decimals = AssetDecimals::default();
+        // 200% price
+        let price = 2 * _PRICE_DECIMAL;
+
+        let amount_in = 1_000_000;
+        let amount_out = _equivalent(amount_in, price, true, &decimals, Rounding::Down).unwrap();
+
+        assert_eq!(
+            _average_price(amount_in, amount_out, true, &decimals).unwrap(),
+            price
+        );
+        assert_eq!(
+            _average_price(amount_out, amount_in, false, &decimals).unwrap(),
+            price
+        );
+
+        // 1% worse than the reference price
+        let execution_price = price + price / 100;
+        assert_eq!(_price_impact(price, execution_price), 100);
+
+        assert_eq!(
+            _implied_price(amount_in, amount_out, &decimals, Rounding::Down).unwrap(),
+            price
+        );
+
+        assert_eq!(_average_price(0, 0, true, &decimals).unwrap(), 0);
+        assert_eq!(
+            _equivalent(amount_in, 0, true, &decimals, Rounding::Down),
+            Err(MathError::DivisionByZero)
+        );
+        assert_eq!(_price_impact(price, 0), 0);
+    }
+
+    #[test]
+    fn test_equivalent_with_different_decimals() {
+        // 8 decimal collateral against an 18 decimal base ,one base token priced at 2500 collateral tokens
+        let decimals = AssetDecimals { base: 18, quote: 8 };
+        let price = 2500 * _PRICE_DECIMAL;
+
+        let one_base = 10u128.pow(18);
+        let one_quote = 10u128.pow(8);
+
+        assert_eq!(
+            _equivalent(one_base, price, false, &decimals, Rounding::Down).unwrap(),
+            2500 * one_quote
+        );
+        assert_eq!(
+            _equivalent(2500 * one_quote, price, true, &decimals, Rounding::Down).unwrap(),
+            one_base
+        );
+        // 10 collateral tokens buy 0.004 base tokens
+        assert_eq!(
+            _equivalent(10 * one_quote, price, true, &decimals, Rounding::Down).unwrap(),
+            4 * one_base / 1000
+        );
+        assert_eq!(
+            _implied_price(2500 * one_quote, one_base, &decimals, Rounding::Down).unwrap(),
+            price
+        );
+        assert_eq!(
+            _average_price(10 * one_quote, 4 * one_base / 1000, true, &decimals).unwrap(),
+            price
+        );
+
+        // the reverse ,18 decimal collateral against an 8 decimal base
+        let decimals = AssetDecimals { base: 8, quote: 18 };
+        let one_base = 10u128.pow(8);
+        let one_quote = 10u128.pow(18);
+
+        assert_eq!(
+            _equivalent(one_base, price, false, &decimals, Rounding::Down).unwrap(),
+            2500 * one_quote
+        );
+        assert_eq!(
+            _equivalent(one_quote, price, true, &decimals, Rounding::Down).unwrap(),
+            one_base / 2500
+        );
+        assert_eq!(
+            _implied_price(2500 * one_quote, one_base, &decimals, Rounding::Down).unwrap(),
+            price
+        );
+
+        // same decimals behave like plain price scaling
+        let decimals = AssetDecimals { base: 8, quote: 8 };
+        assert_eq!(
+            _equivalent(one_base, price, false, &decimals, Rounding::Down).unwrap(),
+            2500 * one_base
+        );
+    }
+
+    #[test]
+    fn test_equivalent_of_large_amounts() {
+        // 18 decimal amounts whose intermediate products overflow 128 bits
+        let decimals = AssetDecimals {
+            base: 18,
+            quote: 18,
+        };
+        let price = 3000 * _PRICE_DECIMAL;
+
+        let amount = 10u128.pow(12) * 10u128.pow(18);
+        assert!(amount.checked_mul(price).is_none());
+
+        assert_eq!(
+            _equivalent(amount, price, false, &decimals, Rounding::Down),
+            Ok(3000 * amount)
+        );
+        assert_eq!(
+            _equivalent(3000 * amount, price, true, &decimals, Rounding::Down),
+            Ok(amount)
+        );
+        assert_eq!(
+            _implied_price(3000 * amount, amount, &decimals, Rounding::Down),
+            Ok(price)
+        );
+
+        // results beyond 128 bits are errors instead of traps
+        assert_eq!(
+            _equivalent(u128::MAX, price, false, &decimals, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+
+        // a base scale above the price decimal
+        let decimals = AssetDecimals { base: 18, quote: 0 };
+        assert_eq!(
+            _equivalent(10u128.pow(18), price, false, &decimals, Rounding::Down),
+            Ok(3000)
+        );
+        let decimals = AssetDecimals { base: 0, quote: 18 };
+        assert_eq!(
+            _equivalent(2, price, false, &decimals, Rounding::Down),
+            Ok(6000 * 10u128.pow(18))
+        );
+        assert_eq!(
+            _equivalent(
+                6000 * 10u128.pow(18),
+                price,
+                true,
+                &decimals,
+                Rounding::Down
+            ),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_equivalent_rounding() {
+        let decimals = AssetDecimals::default();
+        let price = 3 * _PRICE_DECIMAL;
+
+        // 10 quote at a price of 3 is 3.33 base
+        assert_eq!(
+            _equivalent(10, price, true, &decimals, Rounding::Down),
+            Ok(3)
+        );
+        assert_eq!(_equivalent(10, price, true, &decimals, Rounding::Up), Ok(4));
+
+        // exact conversions are the same in both directions
+        assert_eq!(
+            _equivalent(3, price, false, &decimals, Rounding::Down),
+            Ok(9)
+        );
+        assert_eq!(_equivalent(3, price, false, &decimals, Rounding::Up), Ok(9));
+
+        // 3 base at a price of 0.5 is 1.5 quote
+        let price_of_half = _PRICE_DECIMAL / 2;
+        assert_eq!(
+            _equivalent(3, price_of_half, false, &decimals, Rounding::Down),
+            Ok(1)
+        );
+        assert_eq!(
+            _equivalent(3, price_of_half, false, &decimals, Rounding::Up),
+            Ok(2)
+        );
+
+        // rounding is kept through the division by the base scale
+        let decimals = AssetDecimals { base: 6, quote: 18 };
+        let amount = 10u128.pow(13);
+        assert_eq!(
+            _equivalent(amount, price, true, &decimals, Rounding::Down),
+            Ok(3)
+        );
+        assert_eq!(
+            _equivalent(amount, price, true, &decimals, Rounding::Up),
+            Ok(4)
+        );
+        assert_eq!(
+            _implied_price(10, 3, &AssetDecimals::default(), Rounding::Up),
+            Ok(3_333_333_334)
+        );
     }
 }
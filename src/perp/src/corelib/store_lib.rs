@@ -0,0 +1,83 @@
+use ic_stable_structures::{BoundedStorable, Memory, StableBTreeMap};
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Store Trait
+///
+/// Key value storage the order book is read from and written to,
+/// implemented for heap HashMaps (tests and dry runs) and stable BTreeMaps (canister state)
+///
+/// Note
+///  - Values are returned by copy ,so any update must be inserted back
+pub trait Store<K, V> {
+    fn _get(&self, key: &K) -> Option<V>;
+    fn _insert(&mut self, key: K, value: V);
+    fn _remove(&mut self, key: &K);
+    fn _contains(&self, key: &K) -> bool;
+}
+
+impl<K: Eq + Hash, V: Copy> Store<K, V> for HashMap<K, V> {
+    fn _get(&self, key: &K) -> Option<V> {
+        self.get(key).copied()
+    }
+
+    fn _insert(&mut self, key: K, value: V) {
+        self.insert(key, value);
+    }
+
+    fn _remove(&mut self, key: &K) {
+        self.remove(key);
+    }
+
+    fn _contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+}
+
+impl<K, V, M> Store<K, V> for StableBTreeMap<K, V, M>
+where
+    K: BoundedStorable + Ord + Clone,
+    V: BoundedStorable,
+    M: Memory,
+{
+    fn _get(&self, key: &K) -> Option<V> {
+        self.get(key)
+    }
+
+    fn _insert(&mut self, key: K, value: V) {
+        self.insert(key, value);
+    }
+
+    fn _remove(&mut self, key: &K) {
+        self.remove(key);
+    }
+
+    fn _contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+}
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+    use ic_stable_structures::DefaultMemoryImpl;
+
+    fn _round_trip<S: Store<u64, u128>>(store: &mut S) {
+        assert_eq!(store._get(&1), None);
+
+        store._insert(1, 10);
+        store._insert(1, 20);
+        assert_eq!(store._get(&1), Some(20));
+        assert!(store._contains(&1));
+
+        store._remove(&1);
+        assert!(!store._contains(&1));
+    }
+
+    #[test]
+    fn test_heap_and_stable_store() {
+        _round_trip(&mut HashMap::new());
+        _round_trip(&mut StableBTreeMap::init(DefaultMemoryImpl::default()));
+    }
+}
@@ -1,16 +1,14 @@
 use super::bitmap_lib::{_flip_bit, _next_initialised_tick};
+use super::math_lib::Rounding;
 use super::price_lib::_equivalent;
+use super::store_lib::Store;
 use super::tick_lib::*;
-use crate::types::TickDetails;
-
-use std::collections::HashMap;
+use crate::types::{AssetDecimals, MathError, TickDetails, TickGrid};
 
 //use ic_cdk::api::time;
 
 type Tick = u64;
 type Amount = u128;
-type MB = HashMap<u64, u128>;
-type TD = HashMap<u64, TickDetails>;
 
 struct SwapTickConstants {
     tick: Tick,
@@ -19,7 +17,7 @@ struct SwapTickConstants {
 
 /// SwapParams for initiating  a swap
 /// utilsed for opening position at market price
-pub struct SwapParams<'a> {
+pub struct SwapParams<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> {
     /// Swap Direction
     ///
     /// true if buying or false if selling
@@ -43,15 +41,23 @@ pub struct SwapParams<'a> {
     pub order_size: Amount,
     /// Multiplier BitMaps
     ///
-    /// HashMap  of integrals to their bitmaps
+    /// Store of integrals to their bitmaps
     pub integrals_bitmaps: &'a mut MB,
     /// Ticks Details
     ///
-    /// HashMasp  of ticks to their  respective tick_details
+    /// Store of ticks to their  respective tick_details
     pub ticks_details: &'a mut TD,
+    /// Grid
+    ///
+    /// The tick grid of the market
+    pub grid: &'a TickGrid,
+    /// Decimals
+    ///
+    /// The token decimals of the market's assets
+    pub decimals: &'a AssetDecimals,
 }
 
-impl<'a> SwapParams<'a> {
+impl<'a, MB: Store<u64, u128>, TD: Store<Tick, TickDetails>> SwapParams<'a, MB, TD> {
     /// Swap Function
     ///
     /// Swap is executed as a loop starting at the current tick till stopping tick is reached is exceeded
@@ -61,6 +67,11 @@ impl<'a> SwapParams<'a> {
     ///  - AmountRemaining :The amount of asset remaining dues to swap not being completely filled before stopping tick
     ///  - Current or Resulting Tick : This corresponds to the tick at which either asset was fully swapped
     /// or tick before stopping tick was exceeded
+    ///
+    /// Note
+    ///  - If the amounts at a tick can not be calculated (see math_lib) the swap stops before that tick ,
+    /// as if the stopping tick was reached there
+    ///  - The amount out at each tick is rounded down and the amount paid for clearing a tick is rounded up
     pub fn _swap(&mut self) -> (Amount, Amount, Tick, Vec<Tick>) {
         let mut amount_out = 0;
 
@@ -73,10 +84,10 @@ impl<'a> SwapParams<'a> {
         let mut loop_current_tick = self.init_tick;
 
         'swap_loop: loop {
-            let (integral, bit_position) = _int_and_dec(loop_current_tick);
+            let (integral, bit_position) = _int_and_dec(loop_current_tick, self.grid);
 
-            let bitmap = match self.integrals_bitmaps.get(&integral) {
-                Some(&res) => res,
+            let bitmap = match self.integrals_bitmaps._get(&integral) {
+                Some(res) => res,
                 None => {
                     // if integral has no bitmap means that means  no tick within that integral and the next integral  is
                     //initialised
@@ -86,7 +97,7 @@ impl<'a> SwapParams<'a> {
                     //breaks else
                     // updates current tick to the next default tick
 
-                    let next_default_tick = _next_default_tick(integral, self.buy);
+                    let next_default_tick = _next_default_tick(integral, self.buy, self.grid);
                     if _exceeded_stopping_tick(next_default_tick, self.stopping_tick, self.buy) {
                         break 'swap_loop;
                     };
@@ -102,13 +113,18 @@ impl<'a> SwapParams<'a> {
                 tick: loop_current_tick,
             };
 
+            let tick_result = if self.buy {
+                self._buy_at_tick(tick_params)
+            } else {
+                self._sell_at_tick(tick_params)
+            };
+
             let (value_out, boundary_closed);
 
-            if self.buy {
-                (value_out, amount_remaining, boundary_closed) = self._buy_at_tick(tick_params);
-            } else {
-                (value_out, amount_remaining, boundary_closed) = self._sell_at_tick(tick_params);
-            }
+            (value_out, amount_remaining, boundary_closed) = match tick_result {
+                Ok(res) => res,
+                Err(_) => break 'swap_loop,
+            };
 
             // if static liquidity was exhausted at that tick and val out is not equal to zero
 
@@ -120,20 +136,20 @@ impl<'a> SwapParams<'a> {
                 // if static liquidity was exhausted at that tick and val out is not equal to zero
 
                 if boundary_closed {
-                    self.ticks_details.remove(&loop_current_tick);
+                    self.ticks_details._remove(&loop_current_tick);
                     //add ticks to list of crossed ticks
                     crossed_ticks.push(loop_current_tick);
 
                     let flipped_bitmap = _flip_bit(bitmap, bit_position);
 
-                    let tick_zero = _tick_zero(integral);
+                    let tick_zero = _tick_zero(integral, self.grid);
                     // if flipping bitmap results in zero and tick zero(see bitmap_lib) is not contained in ticks_details hashmap
                     //delete btimap
-                    if flipped_bitmap == 0 && !self.ticks_details.contains_key(&tick_zero) {
-                        self.integrals_bitmaps.remove(&integral);
+                    if flipped_bitmap == 0 && !self.ticks_details._contains(&tick_zero) {
+                        self.integrals_bitmaps._remove(&integral);
                     } else {
                         // insert flipped bitmap
-                        self.integrals_bitmaps.insert(integral, flipped_bitmap);
+                        self.integrals_bitmaps._insert(integral, flipped_bitmap);
                     };
                 }
 
@@ -144,7 +160,7 @@ impl<'a> SwapParams<'a> {
 
             //println!()
             let next_initialised_tick =
-                _next_initialised_tick(bitmap, integral, bit_position, self.buy);
+                _next_initialised_tick(bitmap, integral, bit_position, self.buy, self.grid);
 
             if _exceeded_stopping_tick(next_initialised_tick, self.stopping_tick, self.buy) {
                 break;
@@ -167,36 +183,43 @@ impl<'a> SwapParams<'a> {
     ///  this is  zero if the swap is completedly fully at tick
     /// - Cleared : true if all liquidity at tick  was cleared
     /// - Boundary Closed : true if all static liquidity at tick (see TickDetails and LiquidityBoundary) is cleared
-    fn _buy_at_tick(&mut self, params: SwapTickConstants) -> (Amount, Amount, bool) {
+    ///
+    /// Note
+    ///  - The tick is left unchanged if an amount can not be calculated
+    fn _buy_at_tick(
+        &mut self,
+        params: SwapTickConstants,
+    ) -> Result<(Amount, Amount, bool), MathError> {
         let mut amount_out = 0;
 
         let mut amount_remaining = params.order_size;
 
         let boundary_closed;
 
-        let tick_price = _tick_to_price(params.tick);
+        let tick_price = _tick_to_price(params.tick, self.grid);
 
-        let equivalent =
-            |amount: Amount, buy: bool| -> Amount { _equivalent(amount, tick_price, buy) };
+        let equivalent = |amount: Amount, buy: bool, rounding: Rounding| {
+            _equivalent(amount, tick_price, buy, self.decimals, rounding)
+        };
 
-        let tick_details = match self.ticks_details.get_mut(&params.tick) {
+        let mut tick_details = match self.ticks_details._get(&params.tick) {
             Some(res) => res,
-            None => return (amount_out, amount_remaining, false),
+            None => return Ok((amount_out, amount_remaining, false)),
         };
 
         let init_tick_liq = tick_details.liq_bounds_token0._liquidity_within();
 
-        //value of all_liquidity in token1
-        let init_liq_equivalent = equivalent(init_tick_liq, false);
+        //value of all_liquidity in token1 ,owed by the buyer
+        let init_liq_equivalent = equivalent(init_tick_liq, false, Rounding::Up)?;
 
-        if init_liq_equivalent <= self.order_size {
+        if init_liq_equivalent <= amount_remaining {
             // all liquidity has been exhausted
             amount_out = init_tick_liq;
 
             amount_remaining -= init_liq_equivalent;
         } else {
             //liquidity remains
-            amount_out = equivalent(self.order_size, true);
+            amount_out = equivalent(amount_remaining, true, Rounding::Down)?;
 
             amount_remaining = 0;
         }
@@ -207,7 +230,9 @@ impl<'a> SwapParams<'a> {
         boundary_closed = tick_details.liq_bounds_token0._liquidity_within() == 0
             && tick_details.liq_bounds_token1._liquidity_within() == 0;
 
-        return (amount_out, amount_remaining, boundary_closed);
+        self.ticks_details._insert(params.tick, tick_details);
+
+        return Ok((amount_out, amount_remaining, boundary_closed));
     }
 
     /// Sell at tick function
@@ -221,35 +246,40 @@ impl<'a> SwapParams<'a> {
     /// - Cleared : true if all liquidity at tick  was cleared
     /// - Boundary Closed : true if all static liquidity at tick (see TickDetails and LiquidityBoundary) is cleared
 
-    fn _sell_at_tick(&mut self, tick_params: SwapTickConstants) -> (Amount, Amount, bool) {
+    fn _sell_at_tick(
+        &mut self,
+        tick_params: SwapTickConstants,
+    ) -> Result<(Amount, Amount, bool), MathError> {
         let mut amount_out = 0;
 
         let mut amount_remaining = tick_params.order_size;
 
         let boundary_closed;
 
-        let tick_price = _tick_to_price(tick_params.tick);
+        let tick_price = _tick_to_price(tick_params.tick, self.grid);
 
-        let equivalent =
-            |amount: Amount, buy: bool| -> Amount { _equivalent(amount, tick_price, buy) };
+        let equivalent = |amount: Amount, buy: bool, rounding: Rounding| {
+            _equivalent(amount, tick_price, buy, self.decimals, rounding)
+        };
 
         // tick details
-        let tick_details = match self.ticks_details.get_mut(&tick_params.tick) {
+        let mut tick_details = match self.ticks_details._get(&tick_params.tick) {
             Some(res) => res,
-            None => return (amount_out, amount_remaining, false),
+            None => return Ok((amount_out, amount_remaining, false)),
         };
 
         let init_tick_liq = tick_details.liq_bounds_token1._liquidity_within();
 
-        let init_liq_equivalent = equivalent(init_tick_liq, true);
+        // value of all liquidity in token0 ,owed by the seller
+        let init_liq_equivalent = equivalent(init_tick_liq, true, Rounding::Up)?;
 
-        if init_liq_equivalent <= self.order_size {
+        if init_liq_equivalent <= amount_remaining {
             amount_out = init_tick_liq;
 
             amount_remaining -= init_liq_equivalent;
         } else {
             //liquidity remains
-            amount_out = equivalent(self.order_size, false);
+            amount_out = equivalent(amount_remaining, false, Rounding::Down)?;
 
             amount_remaining = 0;
         }
@@ -259,7 +289,9 @@ impl<'a> SwapParams<'a> {
         boundary_closed = tick_details.liq_bounds_token1._liquidity_within() == 0
             && tick_details.liq_bounds_token0._liquidity_within() == 0;
 
-        return (amount_out, amount_remaining, boundary_closed);
+        self.ticks_details._insert(tick_params.tick, tick_details);
+
+        return Ok((amount_out, amount_remaining, boundary_closed));
     }
 }
 
@@ -274,6 +306,9 @@ mod test {
     use std::cell::RefCell;
     use std::collections::HashMap;
 
+    type MB = HashMap<u64, u128>;
+    type TD = HashMap<u64, TickDetails>;
+
     thread_local! {
         static INTEGRALS_BITMAPS:RefCell<MB> = RefCell::new(HashMap::new());
         static TICKS_DETAILS:RefCell<TD> = RefCell::new(HashMap::new());
@@ -284,7 +319,7 @@ mod test {
         let order_size = 10000000000;
         let starting_tick = 199_00_000;
 
-        let stopping_tick = _def_max_tick(starting_tick, true);
+        let stopping_tick = _def_max_tick(starting_tick, true, &TickGrid::default());
 
         let (amount_out, amount_remaining, resulting_tick, crossed_ticks) =
             _swap(order_size, true, starting_tick, stopping_tick);
@@ -322,7 +357,7 @@ mod test {
                 false
             );
 
-            let (int, _) = _int_and_dec(swapping_tick);
+            let (int, _) = _int_and_dec(swapping_tick, &TickGrid::default());
 
             assert_eq!(
                 INTEGRALS_BITMAPS.with_borrow(|int_bitmaps| { int_bitmaps.contains_key(&int) }),
@@ -353,7 +388,7 @@ mod test {
                 true
             );
 
-            let (int, _) = _int_and_dec(swapping_tick);
+            let (int, _) = _int_and_dec(swapping_tick, &TickGrid::default());
 
             assert_eq!(
                 INTEGRALS_BITMAPS.with_borrow(|int_bitmaps| { int_bitmaps.contains_key(&int) }),
@@ -391,16 +426,111 @@ mod test {
             );
 
             // assert that integral'ss bitmap is still available since
-            let (int, _) = _int_and_dec(tick1);
+            let (int, _) = _int_and_dec(tick1, &TickGrid::default());
             assert!(INTEGRALS_BITMAPS
                 .with_borrow(|integrals_bitmaps| { integrals_bitmaps.contains_key(&int) }))
         }
     }
 
+    #[test]
+    fn test_swap_across_ticks_with_partial_fill() {
+        // 300 base at a price of 1 ,100 at 1.5 and 1000 at 2
+        let (tick1, tick2, tick3) = (100_00_000, 150_00_000, 200_00_000);
+        _fill_tick(tick1, 300, false);
+        _fill_tick(tick2, 100, false);
+        _fill_tick(tick3, 1000, false);
+
+        // 300 quote clears the first tick ,150 the second and the remaining 550 buys 275 base at the third
+        let (amount_out, amount_remaining, resulting_tick, crossed_ticks) =
+            _swap(1000, true, tick1, tick3);
+
+        assert_eq!(amount_out, 675);
+        assert_eq!(amount_remaining, 0);
+        assert_eq!(resulting_tick, tick3);
+        assert_eq!(crossed_ticks, vec![tick1, tick2]);
+
+        // only the remaining amount was taken from the last tick
+        assert_eq!(
+            _get_tick_details(tick3)
+                .liq_bounds_token0
+                ._liquidity_within(),
+            725
+        );
+    }
+
+    #[test]
+    fn test_swap_with_different_decimals() {
+        // 8 decimal collateral against an 18 decimal base
+        let decimals = AssetDecimals { base: 18, quote: 8 };
+        let grid = TickGrid::default();
+        let one_base = 10u128.pow(18);
+        let one_quote = 10u128.pow(8);
+
+        let mut integrals_bitmaps = MB::new();
+        let mut ticks_details = TD::new();
+
+        // 5 base tokens at the 200% tick ,i.e 2 collateral tokens per base token
+        let tick = 200_00_000;
+        let mut tick_details = TickDetails::default();
+        tick_details.liq_bounds_token0._add_liquidity(5 * one_base);
+        ticks_details.insert(tick, tick_details);
+
+        let (int, dec) = _int_and_dec(tick, &grid);
+        integrals_bitmaps.insert(int, _flip_bit(0, dec));
+
+        let mut swap_params = SwapParams {
+            buy: true,
+            init_tick: tick,
+            stopping_tick: tick,
+            order_size: 4 * one_quote,
+            integrals_bitmaps: &mut integrals_bitmaps,
+            ticks_details: &mut ticks_details,
+            grid: &grid,
+            decimals: &decimals,
+        };
+
+        let (amount_out, amount_remaining, resulting_tick, _) = swap_params._swap();
+        assert_eq!(amount_out, 2 * one_base);
+        assert_eq!(amount_remaining, 0);
+        assert_eq!(resulting_tick, tick);
+
+        // buying more than the liquidity left at the tick leaves the rest unfilled
+        swap_params.order_size = 20 * one_quote;
+
+        let (amount_out, amount_remaining, _, crossed_ticks) = swap_params._swap();
+        assert_eq!(amount_out, 3 * one_base);
+        assert_eq!(amount_remaining, 14 * one_quote);
+        assert_eq!(crossed_ticks, vec![tick]);
+    }
+
     fn _get_tick_details(tick: Tick) -> TickDetails {
         TICKS_DETAILS.with_borrow(|ticks_details| ticks_details.get(&tick).unwrap().clone())
     }
 
+    #[test]
+    fn test_swap_rounding() {
+        // 1 base at a price of 2.0037 costs 3 quote to clear
+        let tick = 200_37_000;
+        _fill_tick(tick, 1, false);
+
+        let (amount_out, amount_remaining, _, crossed_ticks) = _swap(10, true, tick, tick);
+        assert_eq!((amount_out, amount_remaining), (1, 7));
+        assert_eq!(crossed_ticks, vec![tick]);
+
+        // 1 quote buys 0.499 base ,rounded down to nothing
+        _fill_tick(tick, 1_000, false);
+
+        let (amount_out, amount_remaining, _, _) = _swap(1, true, tick, tick);
+        assert_eq!((amount_out, amount_remaining), (0, 0));
+
+        // 1 quote at a price of 1.99 costs 1 base to clear
+        let tick = 199_00_000;
+        _fill_tick(tick, 1, true);
+
+        let (amount_out, amount_remaining, _, _) = _swap(5, false, tick, tick);
+        assert_eq!((amount_out, amount_remaining), (1, 4));
+    }
+
     fn _fill_tick(tick: Tick, amount_in: Amount, buy: bool) {
         let mut tick_details = TickDetails::default();
         if buy {
@@ -408,7 +538,7 @@ mod test {
         } else {
             tick_details.liq_bounds_token0._add_liquidity(amount_in);
         }
-        let (int, dec) = _int_and_dec(tick);
+        let (int, dec) = _int_and_dec(tick, &TickGrid::default());
         TICKS_DETAILS
             .with_borrow_mut(|ref_tick_details| ref_tick_details.insert(tick, tick_details));
         INTEGRALS_BITMAPS.with_borrow_mut(|ref_integral_bitmaps| {
@@ -438,6 +568,8 @@ mod test {
                     order_size,
                     integrals_bitmaps,
                     ticks_details,
+                    grid: &TickGrid::default(),
+                    decimals: &AssetDecimals::default(),
                 };
                 swap_params._swap()
             })
@@ -1,48 +1,117 @@
 use super::calc_lib::{_percentage128, _percentage64};
 use super::constants::*;
+use super::math_lib::{_mul_div, Rounding};
+use crate::types::{TickGrid, TickModel};
+
+/// Inverse Powers
+///
+/// 1.0001^-(2^i) as Q64 fixed point numbers for i in 0..18
+const INVERSE_POWERS: [u128; 18] = [
+    0xfff97272373d4132,
+    0xfff2e50f5f656932,
+    0xffe5caca7e10e4e6,
+    0xffcb9843d60f6159,
+    0xff973b41fa98c081,
+    0xff2ea16466c96a38,
+    0xfe5dee046a99a2a8,
+    0xfcbe86c7900a88ae,
+    0xf987a7253ac41317,
+    0xf3392b0822b70005,
+    0xe7159475a2c29b74,
+    0xd097f3bdfd2022b8,
+    0xa9f746462d870fdf,
+    0x70d869a156d2a1b8,
+    0x31be135f97d08fd9,
+    0x09aa508b5b7a84e1,
+    0x005d6af8dedb8119,
+    0x00002216e584f5fa,
+];
 
 /// Default Max Tick
 ///
 /// Gets the default max tick for a particular trade direction (buy or sell)
 ///
-/// This is currently implemented as a 5 percent incerase or decrease from the current tick
+/// This is currently implemented as a 5 percent incerase or decrease from the current price
 
-pub fn _def_max_tick(current_tick: u64, buy: bool) -> u64 {
-    if buy {
-        current_tick + _percentage64(5 * _ONE_PERCENT, current_tick)
-    } else {
-        current_tick - _percentage64(5 * _ONE_PERCENT, current_tick)
+pub fn _def_max_tick(current_tick: u64, buy: bool, grid: &TickGrid) -> u64 {
+    _offset_tick(current_tick, 5 * _ONE_PERCENT, buy, grid)
+}
+
+/// Offset Tick
+///
+/// Calculates the tick whose price is a percentage above or below the price of a tick
+///
+/// Note
+///  - In the linear model the offset is applied to the tick directly ,in the geometric model the resulting tick is rounded down to the grid
+///  - Offsets that overflow saturate at the max tick or price
+pub fn _offset_tick(tick: u64, percentage: u64, up: bool, grid: &TickGrid) -> u64 {
+    match grid.model {
+        TickModel::Linear => {
+            let offset = _percentage64(percentage, tick).unwrap_or(u64::MAX);
+            if up {
+                tick.saturating_add(offset)
+            } else {
+                tick.saturating_sub(offset)
+            }
+        }
+        TickModel::Geometric => {
+            let price = _tick_to_price(tick, grid);
+            let offset = _percentage128(percentage, price, Rounding::Down).unwrap_or(u128::MAX);
+            let offset_price = if up {
+                price.saturating_add(offset)
+            } else {
+                price.saturating_sub(offset)
+            };
+            _price_to_tick(offset_price, grid)
+        }
     }
 }
 
 /// Next Default Tick
 ///
-///
-pub fn _next_default_tick(integral: u64, buy: bool) -> u64 {
+/// Gets the tick zero of the next integral for a buy or the last tick of the previous integral for a sell
+pub fn _next_default_tick(integral: u64, buy: bool, grid: &TickGrid) -> u64 {
     if buy {
-        _tick_zero(integral + 1)
+        _tick_zero(integral + 1, grid)
     } else {
-        _tick_zero(integral - 1) + (99 * _ONE_BASIS_POINT)
+        _tick_zero(integral - 1, grid) + ((_TICKS_PER_INTEGRAL - 1) * grid.tick_spacing)
     }
 }
 
 /// Tick Zero
 ///
-/// The tick zero of an integral corresponds to the tick with that integral  and a  of 0 i.e whole percentages (1%,3% etc)
-pub fn _tick_zero(integral: u64) -> u64 {
-    integral * _ONE_PERCENT
+/// The tick zero of an integral corresponds to the tick with that integral  and a decimal of 0
+/// i.e whole percentages (1%,3% etc) on the default grid
+pub fn _tick_zero(integral: u64, grid: &TickGrid) -> u64 {
+    integral * grid.integral_width()
 }
 
 /// Mul and Bit
 ///
 /// This function is used to calculate the integral and decimal pert of a tick
 
-pub fn _int_and_dec(tick: u64) -> (u64, u64) {
-    let multiplier = tick / _ONE_PERCENT;
-    let bit_position = (tick % _ONE_PERCENT) / (_ONE_BASIS_POINT);
+pub fn _int_and_dec(tick: u64, grid: &TickGrid) -> (u64, u64) {
+    let integral_width = grid.integral_width();
+    let multiplier = tick / integral_width;
+    let bit_position = (tick % integral_width) / grid.tick_spacing;
     return (multiplier, bit_position);
 }
 
+/// Align To Grid
+///
+/// Rounds a tick to a multiple of the tick spacing ,up if round up is true and down otherwise
+pub fn _align_to_grid(tick: u64, grid: &TickGrid, round_up: bool) -> u64 {
+    let remainder = tick % grid.tick_spacing;
+    if remainder == 0 {
+        return tick;
+    }
+    if round_up {
+        tick - remainder + grid.tick_spacing
+    } else {
+        tick - remainder
+    }
+}
+
 /// Excceded Stopping Tick
 ///
 /// This functions checks that stoping tick is not exceeded in the particular swap direction
@@ -60,10 +129,159 @@ pub fn _exceeded_stopping_tick(current_tick: u64, stopping_tick: u64, buy: bool)
 /// Tick to Price
 ///
 /// Calculates the price for a particular tick
-/// Price is given as the percentage of a base price
+///
+/// Note
+///  - In the linear model price is given as the percentage of the base price of the grid ,
+/// a tick and a valid base price (see TickGrid) both fit within 64 bits so the product never overflows
+///  - In the geometric model price is the base price multiplied by 1.0001 for every basis point above the anchor tick
+/// and divided by 1.0001 for every basis point below it
+
+pub fn _tick_to_price(tick: u64, grid: &TickGrid) -> u128 {
+    match grid.model {
+        TickModel::Linear => (tick as u128 * grid.base_price) / u128::from(100 * _ONE_PERCENT),
+        TickModel::Geometric => _geometric_price(_geometric_steps(tick), grid.base_price),
+    }
+}
+
+/// Price to Tick
+///
+/// Calculates the tick for a particular price ,the inverse of tick to price
+///
+/// Note
+///  - the resulting tick is rounded down to the nearest tick on the grid
+///  - prices beyond the max tick are clamped to the max tick ,in the geometric model prices outside the range are clamped to the lowest or highest tick
+pub fn _price_to_tick(price: u128, grid: &TickGrid) -> u64 {
+    let tick = match grid.model {
+        TickModel::Linear => {
+            let tick = _mul_div(
+                price,
+                u128::from(100 * _ONE_PERCENT),
+                grid.base_price,
+                Rounding::Down,
+            );
+            tick.map_or(u64::MAX, |tick| tick.min(u64::MAX as u128) as u64)
+        }
+        TickModel::Geometric => {
+            let max_steps = _MAX_GEOMETRIC_STEPS as i64;
+            // binary search for the highest step priced at or below the price
+            let (mut low, mut high) = (-max_steps, max_steps);
+            while low < high {
+                let mid = low + (high - low + 1) / 2;
+                if _geometric_price(mid, grid.base_price) <= price {
+                    low = mid;
+                } else {
+                    high = mid - 1;
+                }
+            }
+            (_GEOMETRIC_ANCHOR_TICK as i64 + low * _ONE_BASIS_POINT as i64) as u64
+        }
+    };
+    return _align_to_grid(tick, grid, false);
+}
+
+/// Max Valid Tick
+///
+/// Gets the highest tick with a distinct price in the grid's tick model
+///
+/// Note
+///  - Geometric ticks beyond _MAX_GEOMETRIC_STEPS steps above the anchor tick are priced as the last step ,
+///  the lowest geometric tick (zero) is exactly _MAX_GEOMETRIC_STEPS steps below
+pub fn _max_valid_tick(grid: &TickGrid) -> u64 {
+    match grid.model {
+        TickModel::Linear => u64::MAX,
+        TickModel::Geometric => _GEOMETRIC_ANCHOR_TICK + _MAX_GEOMETRIC_STEPS * _ONE_BASIS_POINT,
+    }
+}
+
+/// Geometric Steps
+///
+/// Calculates the number of basis point steps from the anchor tick to a tick ,clamped to the geometric range
+fn _geometric_steps(tick: u64) -> i64 {
+    let max_steps = _MAX_GEOMETRIC_STEPS as i128;
+    let steps =
+        (tick as i128 - _GEOMETRIC_ANCHOR_TICK as i128).div_euclid(_ONE_BASIS_POINT as i128);
+    return steps.clamp(-max_steps, max_steps) as i64;
+}
+
+/// Geometric Price
+///
+/// Calculates base price * 1.0001^steps
+///
+/// Note
+///  - 1.0001^-|steps| is calculated as a Q64 number from the binary representation of |steps|,
+/// so the base price must fit within 64 bits for the intermediate products to fit within 128 bits
+fn _geometric_price(steps: i64, base_price: u128) -> u128 {
+    let magnitude = steps.unsigned_abs();
 
-pub fn _tick_to_price(tick: u64) -> u128 {
-    return _percentage128(tick, _BASE_PRICE);
+    let mut ratio: u128 = 1 << 64;
+    for (bit, power) in INVERSE_POWERS.iter().enumerate() {
+        if magnitude & (1 << bit) != 0 {
+            ratio = (ratio * power) >> 64;
+        }
+    }
+
+    if steps < 0 {
+        (base_price * ratio) >> 64
+    } else {
+        (base_price << 64) / ratio
+    }
+}
+
+/// Tick Divergence
+///
+/// Calculates the divergence of a tick from a reference tick as a percentage of the reference tick's price
+pub fn _tick_divergence(tick: u64, reference_tick: u64, grid: &TickGrid) -> u64 {
+    let (value, reference_value) = match grid.model {
+        TickModel::Linear => (tick as u128, reference_tick as u128),
+        TickModel::Geometric => (
+            _tick_to_price(tick, grid),
+            _tick_to_price(reference_tick, grid),
+        ),
+    };
+    if reference_value == 0 {
+        return 0;
+    }
+    let difference = value.abs_diff(reference_value);
+    let divergence = _mul_div(
+        difference,
+        u128::from(100 * _ONE_PERCENT),
+        reference_value,
+        Rounding::Down,
+    );
+    return divergence.map_or(u64::MAX, |divergence| {
+        divergence.min(u64::MAX as u128) as u64
+    });
+}
+
+/// Band Limited Tick
+///
+/// Truncates a stopping tick to the band around a reference (oracle) tick in the particular swap direction
+///
+/// Params
+///  - Stopping Tick :The stopping tick of the swap
+///  - Current Tick :The current state tick
+///  - Reference Tick :The tick the band is centered on
+///  - Band Width :The width of the band on either side as a percentage of the reference tick's price
+///  - Buy :The swap direction
+///  - Grid :The tick grid of the market
+///
+/// Note
+///  - The resulting tick never goes behind the current tick ,so a swap starting outside the band can only fill at the current tick
+pub fn _band_limited_tick(
+    stopping_tick: u64,
+    current_tick: u64,
+    reference_tick: u64,
+    band_width: u64,
+    buy: bool,
+    grid: &TickGrid,
+) -> u64 {
+    if buy {
+        let upper_bound = _offset_tick(reference_tick, band_width, true, grid).max(current_tick);
+        stopping_tick.min(upper_bound)
+    } else {
+        let lower_bound = _offset_tick(reference_tick, band_width, false, grid).min(current_tick);
+        stopping_tick.max(lower_bound)
+    }
 }
 
 #[cfg(test)]
@@ -81,16 +299,235 @@ mod unit_test {
             0.0000000000000002 * (10 as u128).pow(20) as f64
         );
 
-        let (mul, bit) = _int_and_dec(tick);
+        let (mul, bit) = _int_and_dec(tick, &TickGrid::default());
 
         assert_eq!(mul, 199);
         assert_eq!(bit, 20);
 
         let tick2 = 199_200_000;
 
-        let (mul2, bit2) = _int_and_dec(tick2);
+        let (mul2, bit2) = _int_and_dec(tick2, &TickGrid::default());
 
         assert_eq!(mul2, 1992);
         assert_eq!(bit2, 0);
     }
+
+    #[test]
+    fn test_price_to_tick() {
+        let grid = TickGrid::default();
+        let tick = 199_20_000;
+
+        assert_eq!(_price_to_tick(_tick_to_price(tick, &grid), &grid), tick);
+
+        // prices in between ticks are rounded down
+        assert_eq!(_price_to_tick(_tick_to_price(tick, &grid) + 1, &grid), tick);
+    }
+
+    #[test]
+    fn test_custom_grid() {
+        // ticks 0.1 basis point apart ,the 100% tick priced at 0.001
+        let grid = TickGrid {
+            tick_spacing: _ONE_BASIS_POINT / 10,
+            base_price: _PRICE_DECIMAL / 1000,
+            model: TickModel::Linear,
+        };
+
+        // each integral spans 0.1%
+        let tick = 199 * _ONE_PERCENT + 25 * grid.tick_spacing;
+        assert_eq!(_int_and_dec(tick, &grid), (1990, 25));
+        assert_eq!(_tick_zero(1990, &grid), 199 * _ONE_PERCENT);
+
+        assert_eq!(_next_default_tick(1990, true, &grid), 19_910_000);
+        assert_eq!(
+            _next_default_tick(1990, false, &grid),
+            199 * _ONE_PERCENT - grid.tick_spacing
+        );
+
+        // 199.025% of the base price
+        assert_eq!(_tick_to_price(tick, &grid), 1_990_250);
+        assert_eq!(_price_to_tick(_tick_to_price(tick, &grid), &grid), tick);
+        assert_eq!(_price_to_tick(1_990_250 + 9, &grid), tick);
+
+        assert_eq!(_align_to_grid(tick + 1, &grid, false), tick);
+        assert_eq!(
+            _align_to_grid(tick + 1, &grid, true),
+            tick + grid.tick_spacing
+        );
+        assert_eq!(_align_to_grid(tick, &grid, true), tick);
+    }
+
+    #[test]
+    fn test_band_limited_tick() {
+        let grid = TickGrid::default();
+        let oracle_tick = 200 * _ONE_PERCENT;
+        // 1% band ,i.e 2 integrals either side of 200%
+        let band_width = _ONE_PERCENT;
+
+        // stopping tick beyond band gets truncated
+        let buy_stop = _band_limited_tick(
+            210 * _ONE_PERCENT,
+            oracle_tick,
+            oracle_tick,
+            band_width,
+            true,
+            &grid,
+        );
+        assert_eq!(buy_stop, 202 * _ONE_PERCENT);
+
+        let sell_stop = _band_limited_tick(
+            190 * _ONE_PERCENT,
+            oracle_tick,
+            oracle_tick,
+            band_width,
+            false,
+            &grid,
+        );
+        assert_eq!(sell_stop, 198 * _ONE_PERCENT);
+
+        // stopping tick within band is unchanged
+        let within = _band_limited_tick(
+            201 * _ONE_PERCENT,
+            oracle_tick,
+            oracle_tick,
+            band_width,
+            true,
+            &grid,
+        );
+        assert_eq!(within, 201 * _ONE_PERCENT);
+
+        // current tick already outside the band can not move further
+        let outside = _band_limited_tick(
+            210 * _ONE_PERCENT,
+            205 * _ONE_PERCENT,
+            oracle_tick,
+            band_width,
+            true,
+            &grid,
+        );
+        assert_eq!(outside, 205 * _ONE_PERCENT);
+
+        assert_eq!(
+            _tick_divergence(202 * _ONE_PERCENT, oracle_tick, &grid),
+            _ONE_PERCENT
+        );
+    }
+
+    #[test]
+    fn test_geometric_tick_to_price() {
+        let linear = TickGrid::default();
+        let geometric = TickGrid {
+            model: TickModel::Geometric,
+            ..TickGrid::default()
+        };
+        let step = _ONE_BASIS_POINT;
+        let anchor = _GEOMETRIC_ANCHOR_TICK;
+
+        // the anchor tick is priced at the base price like the 100% tick of the linear model
+        assert_eq!(_tick_to_price(anchor, &geometric), _BASE_PRICE);
+        assert_eq!(_tick_to_price(100 * _ONE_PERCENT, &linear), _BASE_PRICE);
+
+        // one tick above is the same basis point step in both models
+        assert_eq!(
+            _tick_to_price(anchor + step, &geometric),
+            _tick_to_price(100 * _ONE_PERCENT + step, &linear)
+        );
+        // 1.0001^-1 ,1.0001^100 ,1.0001^10_000 (e) and 1.0001^-10_000 (1/e)
+        assert_eq!(_tick_to_price(anchor - step, &geometric), 999_900_009);
+        assert_eq!(
+            _tick_to_price(anchor + 100 * step, &geometric),
+            1_010_049_662
+        );
+        assert_eq!(
+            _tick_to_price(anchor + 10_000 * step, &geometric),
+            2_718_145_926
+        );
+        assert_eq!(
+            _tick_to_price(anchor - 10_000 * step, &geometric),
+            367_897_834
+        );
+
+        // compounding makes a hundred geometric ticks slightly more than a hundred linear ticks
+        assert!(
+            _tick_to_price(anchor + 100 * step, &geometric)
+                > _tick_to_price(101 * _ONE_PERCENT, &linear)
+        );
+
+        // every geometric tick is a one basis point step ,a linear tick is a smaller step the higher the tick
+        for steps in [-10_000, -1, 0, 1, 100_000, 199_999] {
+            let tick = (anchor as i64 + steps * step as i64) as u64;
+            // within rounding of the integer prices
+            assert!(_tick_divergence(tick + step, tick, &geometric).abs_diff(step) <= 1);
+        }
+        assert_eq!(
+            _tick_divergence(10 * _ONE_PERCENT + step, 10 * _ONE_PERCENT, &linear),
+            10 * step
+        );
+        assert_eq!(
+            _tick_divergence(1000 * _ONE_PERCENT + step, 1000 * _ONE_PERCENT, &linear),
+            step / 10
+        );
+    }
+
+    #[test]
+    fn test_max_valid_tick() {
+        let geometric = TickGrid {
+            model: TickModel::Geometric,
+            ..TickGrid::default()
+        };
+        let max_tick = _max_valid_tick(&geometric);
+
+        assert_eq!(_max_valid_tick(&TickGrid::default()), u64::MAX);
+        assert_eq!(max_tick, 2 * _GEOMETRIC_ANCHOR_TICK);
+
+        // ticks beyond the max tick are clamped to its price
+        assert!(
+            _tick_to_price(max_tick, &geometric)
+                > _tick_to_price(max_tick - _ONE_BASIS_POINT, &geometric)
+        );
+        assert_eq!(
+            _tick_to_price(max_tick + _ONE_BASIS_POINT, &geometric),
+            _tick_to_price(max_tick, &geometric)
+        );
+    }
+
+    #[test]
+    fn test_geometric_price_to_tick() {
+        let geometric = TickGrid {
+            model: TickModel::Geometric,
+            ..TickGrid::default()
+        };
+        let step = _ONE_BASIS_POINT;
+        let anchor = _GEOMETRIC_ANCHOR_TICK;
+
+        let mut tick = anchor - 100_000 * step;
+        while tick <= anchor + _MAX_GEOMETRIC_STEPS * step {
+            let price = _tick_to_price(tick, &geometric);
+            assert_eq!(_price_to_tick(price, &geometric), tick);
+            // prices in between ticks are rounded down
+            assert_eq!(_price_to_tick(price + 1, &geometric), tick);
+            assert_eq!(_price_to_tick(price - 1, &geometric), tick - step);
+            tick += 997 * step;
+        }
+
+        // prices outside the range are clamped
+        assert_eq!(_price_to_tick(0, &geometric), 0);
+        assert_eq!(
+            _price_to_tick(u128::MAX >> 1, &geometric),
+            anchor + _MAX_GEOMETRIC_STEPS * step
+        );
+
+        // coarser grids round down to the tick spacing
+        let coarse = TickGrid {
+            tick_spacing: 10 * step,
+            ..geometric
+        };
+        let price = _tick_to_price(anchor + 15 * step, &coarse);
+        assert_eq!(_price_to_tick(price, &coarse), anchor + 10 * step);
+
+        // offsets are relative to the price
+        let up = _offset_tick(anchor, 5 * _ONE_PERCENT, true, &geometric);
+        let down = _offset_tick(anchor, 5 * _ONE_PERCENT, false, &geometric);
+        assert_eq!(_tick_divergence(up, anchor, &geometric) / step, 499);
+        assert_eq!(_tick_divergence(down, anchor, &geometric) / step, 500);
+    }
 }
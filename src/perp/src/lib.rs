@@ -1,17 +1,29 @@
 use candid::{CandidType, Decode, Encode, Principal};
-use ic_cdk::{export_candid, storage};
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::stable;
+use ic_cdk::export_candid;
+use ic_cdk::storage;
 
 use sha2::{Digest, Sha256};
 
-use corelib::calc_lib::{_calc_interest, _percentage64};
-use corelib::constants::{_BASE_PRICE, _ONE_PERCENT};
+use corelib::book_lib::BookParams;
+use corelib::calc_lib::{_calc_backoff, _calc_debt, _calc_interest, _calc_margin_ratio};
+use corelib::constants::{_ONE_PERCENT, _ONE_X_LEVERAGE_BPS, _PRICE_DECIMAL};
+use corelib::math_lib::{_mul_div, Rounding};
 use corelib::order_lib::{CloseOrderParams, LimitOrder, OpenOrderParams};
-use corelib::price_lib::_equivalent;
+use corelib::price_lib::{_average_price, _equivalent, _implied_price, _price_impact};
 use corelib::swap_lib::SwapParams;
-use corelib::tick_lib::{_def_max_tick, _tick_to_price};
+use corelib::tick_lib::{
+    _align_to_grid, _band_limited_tick, _def_max_tick, _int_and_dec, _max_valid_tick, _offset_tick,
+    _price_to_tick, _tick_divergence, _tick_to_price,
+};
 use types::{
-    FundingRateTracker, GetExchangeRateRequest, GetExchangeRateResult, MarketDetails, StateDetails,
-    TickDetails, ID,
+    AssetDecimals, BookSide, Candle, CandleResolution, CircuitBreakerEvent, ExchangeRate,
+    FundingRateTracker, GetExchangeRateRequest, GetExchangeRateResult, MarketDetails, MarketError,
+    MarketEvent, MarketEventKind, MarketMode, MarketModeChecks, MathError, OpenInterestCapacity,
+    OpenInterestLimits, OracleDetails, OrderBook, OrderBookLevel, ParameterProposal,
+    PositionHealth, PriceBandDetails, RiskTier, RiskTiers, Role, RoleDetails, RoleMembers,
+    StateDetails, StateParameter, SwapQuote, TickDetails, TickGrid, ID,
 };
 
 use serde::{Deserialize, Serialize};
@@ -42,16 +54,91 @@ const _FUNDING_RATE_TRACKER_MEMORY: MemoryId = MemoryId::new(4);
 
 const _ADMIN_MEMORY: MemoryId = MemoryId::new(5);
 
-const _ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(6);
+/// Memory of the account error logs recorded before they carried an operation id ,migrated on upgrade
+const _LEGACY_ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(6);
+
+const _OPEN_INTEREST_LIMITS_MEMORY: MemoryId = MemoryId::new(7);
+
+const _PRICE_BAND_DETAILS_MEMORY: MemoryId = MemoryId::new(8);
+
+const _ORACLE_DETAILS_MEMORY: MemoryId = MemoryId::new(9);
+
+const _CIRCUIT_BREAKER_EVENTS_MEMORY: MemoryId = MemoryId::new(10);
+
+const _MARKET_EVENTS_MEMORY: MemoryId = MemoryId::new(11);
+
+const _ACCOUNT_EVENTS_MEMORY: MemoryId = MemoryId::new(12);
+
+const _CANDLES_MEMORY: MemoryId = MemoryId::new(13);
+
+const _TICKS_DETAILS_MEMORY: MemoryId = MemoryId::new(14);
+
+const _INTEGRAL_BITMAPS_MEMORY: MemoryId = MemoryId::new(15);
+
+const _RETRY_QUEUE_MEMORY: MemoryId = MemoryId::new(16);
+
+const _DEAD_LETTERS_MEMORY: MemoryId = MemoryId::new(17);
+
+const _RETRY_COUNTER_MEMORY: MemoryId = MemoryId::new(18);
+
+const _RESERVATION_COUNTER_MEMORY: MemoryId = MemoryId::new(19);
+
+const _ROLE_MEMBERS_MEMORY: MemoryId = MemoryId::new(20);
+
+const _PENDING_OWNER_MEMORY: MemoryId = MemoryId::new(21);
+
+/// Max number of role grants across all roles
+const MAX_ROLE_MEMBERS: usize = 50;
+
+const _PARAMETER_PROPOSALS_MEMORY: MemoryId = MemoryId::new(22);
+
+const _PROPOSAL_COUNTER_MEMORY: MemoryId = MemoryId::new(23);
+
+const _RISK_TIERS_MEMORY: MemoryId = MemoryId::new(24);
+
+const _ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(25);
+
+const _PENDING_COMMITS_MEMORY: MemoryId = MemoryId::new(26);
+
+/// Delay between proposing a state parameter change and when it can be executed
+const PARAMETER_CHANGE_DELAY: u64 = 24 * ONE_HOUR;
+
+/// Max number of pending state parameter changes
+const MAX_PENDING_PROPOSALS: u64 = 20;
 
 const ONE_HOUR: u64 = 3_600_000_000_000;
 
 const DEFAULT_SWAP_SLIPPAGE: u64 = 50_000; //0.5%
 
+const PRICE_CHECK_INTERVAL: u64 = 300; // 5 minutes
+
+const MAX_BOOK_LEVELS: u32 = 100;
+
+const BOOK_DEPTH_RANGE: u64 = 50 * _ONE_PERCENT; // 50%
+
+const MAX_EVENTS_PAGE: u32 = 500;
+
+const RETRY_INTERVAL: u64 = 60; // 1 minute
+
+const RETRY_BASE_DELAY: u64 = 60_000_000_000; // 1 minute
+
+const MAX_RETRY_DELAY: u64 = 6 * ONE_HOUR;
+
+/// Max attempts before a notification becomes a dead letter ,with the backoff the last attempt is made about 8.5 hours
+/// after the first
+const MAX_RETRY_ATTEMPTS: u32 = 10;
+
+const MAX_RETRIES_PER_ROUND: usize = 20;
+
+const MAX_NOTIFICATION_TICKS: usize = 500;
+
+const MAX_ERROR_LENGTH: usize = 200;
+
 thread_local! {
 
     static MEMORY_MANAGER:RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default())) ;
 
+    /// Owner of the market ,holds every role
     static ADMIN:RefCell<StableCell<ID,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
         s.borrow().get(_ADMIN_MEMORY)
     }),ID::from(Principal::anonymous())).unwrap());
@@ -71,6 +158,44 @@ thread_local! {
         s.borrow().get(_FUNDING_RATE_TRACKER_MEMORY)
     }),FundingRateTracker::default()).unwrap());
 
+    static RISK_TIERS:RefCell<StableCell<RiskTiers,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_RISK_TIERS_MEMORY)
+    }),RiskTiers::default()).unwrap());
+
+    static OPEN_INTEREST_LIMITS:RefCell<StableCell<OpenInterestLimits,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_OPEN_INTEREST_LIMITS_MEMORY)
+    }),OpenInterestLimits::default()).unwrap());
+
+    static PRICE_BAND_DETAILS:RefCell<StableCell<PriceBandDetails,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PRICE_BAND_DETAILS_MEMORY)
+    }),PriceBandDetails::default()).unwrap());
+
+    static ORACLE_DETAILS:RefCell<StableCell<OracleDetails,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_ORACLE_DETAILS_MEMORY)
+    }),OracleDetails::default()).unwrap());
+
+    static CIRCUIT_BREAKER_EVENTS:RefCell<StableBTreeMap<Time,CircuitBreakerEvent,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_CIRCUIT_BREAKER_EVENTS_MEMORY)
+    })));
+
+    static MARKET_EVENTS:RefCell<StableBTreeMap<u64,MarketEvent,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_MARKET_EVENTS_MEMORY)
+    })));
+
+    /// Account events index ,maps an account and event sequence to the event in market events
+    static ACCOUNT_EVENTS:RefCell<StableBTreeMap<(Subaccount,u64),(),Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_ACCOUNT_EVENTS_MEMORY)
+    })));
+
+    /// Candles ,keyed by resolution id and candle open time
+    static CANDLES:RefCell<StableBTreeMap<(u8,u64),Candle,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_CANDLES_MEMORY)
+    })));
+
     static ACCOUNTS_POSITION:RefCell<StableBTreeMap<Subaccount,PositionDetails,Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
         s.borrow().get(_USER_POSITION_MEMEORY)
@@ -82,11 +207,65 @@ thread_local! {
         s.borrow().get(_ACCOUNT_ERROR_LOGS_MEMORY)
     })));
 
-    static INTEGRAL_BITMAPS:RefCell<HashMap<u64,u128>> = RefCell::new(HashMap::new());
+    static LEGACY_ACCOUNTS_ERROR_LOGS:RefCell<StableBTreeMap<Subaccount,LegacyPositionUpdateErrorLog,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_LEGACY_ACCOUNT_ERROR_LOGS_MEMORY)
+    })));
+
+    static INTEGRAL_BITMAPS:RefCell<StableBTreeMap<u64,u128,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_INTEGRAL_BITMAPS_MEMORY)
+    })));
+
+    static TICKS_DETAILS :RefCell<StableBTreeMap<Tick,TickDetails,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_TICKS_DETAILS_MEMORY)
+    })));
+
+    /// Failed notifications waiting to be retried ,keyed by id
+    static RETRY_QUEUE:RefCell<StableBTreeMap<u64,RetryEntry,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_RETRY_QUEUE_MEMORY)
+    })));
+
+    /// Failed notifications that exceeded the max retry attempts ,keyed by id
+    static DEAD_LETTERS:RefCell<StableBTreeMap<u64,RetryEntry,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_DEAD_LETTERS_MEMORY)
+    })));
+
+    static RETRY_COUNTER:RefCell<StableCell<u64,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_RETRY_COUNTER_MEMORY)
+    }),0).unwrap());
+
+    /// Positions whose reservation has not been committed by the vault ,keyed by account
+    static PENDING_COMMITS:RefCell<StableBTreeMap<Subaccount,PendingCommit,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PENDING_COMMITS_MEMORY)
+    })));
+
+    static RESERVATION_COUNTER:RefCell<StableCell<u64,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_RESERVATION_COUNTER_MEMORY)
+    }),0).unwrap());
+
+    static ROLE_MEMBERS:RefCell<StableCell<RoleMembers,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_ROLE_MEMBERS_MEMORY)
+    }),RoleMembers::default()).unwrap());
+
+    /// Pending state parameter changes keyed by id
+    static PARAMETER_PROPOSALS:RefCell<StableBTreeMap<u64,ParameterProposal,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PARAMETER_PROPOSALS_MEMORY)
+    })));
 
-    static TICKS_DETAILS :RefCell<HashMap<Tick,TickDetails>> = RefCell::new(HashMap::new());
+    static PROPOSAL_COUNTER:RefCell<StableCell<u64,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PROPOSAL_COUNTER_MEMORY)
+    }),0).unwrap());
 
-    static ERRORS:RefCell<Vec<ErrorType>> = RefCell::new(Vec::new());
+    /// Principal proposed as the next owner ,anonymous if there is no pending transfer
+    static PENDING_OWNER:RefCell<StableCell<ID,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PENDING_OWNER_MEMORY)
+    }),ID::from(Principal::anonymous())).unwrap());
 
 }
 
@@ -94,6 +273,14 @@ thread_local! {
 fn init(market_details: MarketDetails) {
     let caller = ic_cdk::api::caller();
 
+    if !market_details.tick_grid.is_valid() {
+        ic_cdk::trap("Invalid tick grid");
+    }
+
+    if !market_details.decimals.is_valid() {
+        ic_cdk::trap("Invalid asset decimals");
+    }
+
     ADMIN.with(|ref_admin| ref_admin.borrow_mut().set(ID::from(caller)).unwrap());
     MARKET_DETAILS.with(|ref_market_details| {
         ref_market_details.borrow_mut().set(market_details).unwrap();
@@ -124,7 +311,255 @@ fn get_market_details() -> MarketDetails {
 
 #[ic_cdk::query(name = "getTickDetails")]
 fn get_tick_details(tick: Tick) -> TickDetails {
-    TICKS_DETAILS.with(|ref_tick_details| ref_tick_details.borrow().get(&tick).unwrap().clone())
+    TICKS_DETAILS.with(|ref_tick_details| ref_tick_details.borrow().get(&tick).unwrap())
+}
+
+/// Get Order Book
+///
+/// Returns the aggregated static liquidity per tick on either side of the current tick
+///
+/// Params
+///  - Levels :The maximum number of ticks with liquidity to return per side ,capped at MAX_BOOK_LEVELS
+///  - Side :The side(s) of the book to return
+///
+/// Note
+///  - Only ticks within BOOK_DEPTH_RANGE of the current tick are considered
+
+#[ic_cdk::query(name = "getOrderBook")]
+fn get_order_book(levels: u32, side: BookSide) -> OrderBook {
+    let current_tick = _get_state_details().current_tick;
+
+    let levels = levels.min(MAX_BOOK_LEVELS) as usize;
+
+    let grid = _get_tick_grid();
+
+    let (lowest_tick, highest_tick) = (
+        _offset_tick(current_tick, BOOK_DEPTH_RANGE, false, &grid),
+        _offset_tick(current_tick, BOOK_DEPTH_RANGE, true, &grid),
+    );
+
+    let to_levels = |depth: Vec<(Tick, Amount)>| -> Vec<OrderBookLevel> {
+        depth
+            .into_iter()
+            .map(|(tick, liquidity)| OrderBookLevel {
+                tick,
+                price: _tick_to_price(tick, &grid),
+                liquidity,
+            })
+            .collect()
+    };
+
+    TICKS_DETAILS.with_borrow(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow(|integrals_bitmaps| {
+            let book_params = BookParams {
+                integrals_bitmaps,
+                ticks_details,
+                grid: &grid,
+            };
+
+            let mut order_book = OrderBook {
+                current_tick,
+                ..Default::default()
+            };
+
+            if side != BookSide::Asks {
+                order_book.bids =
+                    to_levels(book_params._depth(current_tick, lowest_tick, levels, false));
+            }
+            if side != BookSide::Bids {
+                order_book.asks =
+                    to_levels(book_params._depth(current_tick, highest_tick, levels, true));
+            }
+            order_book
+        })
+    })
+}
+
+/// Quote Swap
+///
+/// Returns the expected result of swapping at the current state of the book without executing the swap
+///
+/// Params
+///  - Amount :The amount to swap ,quote (collateral) asset for a buy and base (perp) asset for a sell
+///  - Buy :The swap direction
+///  - Max Tick :The maximum executing tick ,defaults the same way as openPosition if none or out of range
+///
+/// Note
+///  - The stopping tick is truncated to the oracle price band just like market orders
+
+#[ic_cdk::query(name = "quoteSwap")]
+fn quote_swap(amount: Amount, buy: bool, max_tick: Option<Tick>) -> SwapQuote {
+    let current_tick = _get_state_details().current_tick;
+
+    let stopping_tick = _price_band_stopping_tick(
+        max_or_default_max(max_tick, current_tick, buy),
+        current_tick,
+        buy,
+    );
+
+    let (amount_out, amount_remaining, resulting_tick, _) =
+        _quote_swap(amount, buy, current_tick, stopping_tick);
+
+    let average_price = _average_price(
+        amount - amount_remaining,
+        amount_out,
+        buy,
+        &_get_asset_decimals(),
+    )
+    .unwrap_or(0);
+
+    SwapQuote {
+        amount_out,
+        amount_remaining,
+        resulting_tick,
+        average_price,
+        price_impact: _price_impact(
+            _tick_to_price(current_tick, &_get_tick_grid()),
+            average_price,
+        ),
+    }
+}
+
+/// Get Open Interest Limits
+///
+/// Returns the current open interest limits of the market
+
+#[ic_cdk::query(name = "getOpenInterestLimits")]
+fn get_open_interest_limits() -> OpenInterestLimits {
+    _get_open_interest_limits()
+}
+
+/// Get Risk Tiers
+///
+/// Returns the risk tiers mapping position notional to max leverage and maintenance margin
+
+#[ic_cdk::query(name = "getRiskTiers")]
+fn get_risk_tiers() -> RiskTiers {
+    _get_risk_tiers()
+}
+
+/// Get Open Interest Capacity
+///
+/// Returns the remaining notional that can be opened on each market direction and by the particular account
+///
+/// Note
+///  - A capacity of None means that capacity is not capped
+
+#[ic_cdk::query(name = "getOpenInterestCapacity")]
+fn get_open_interest_capacity(account: Subaccount) -> OpenInterestCapacity {
+    let limits = _get_open_interest_limits();
+
+    let funding_rate_tracker = _get_funding_rate_tracker();
+
+    let account_notional =
+        ACCOUNTS_POSITION.with_borrow(|reference| match reference.get(&account) {
+            Some(position) => position.collateral_value + position.debt_value,
+            None => 0,
+        });
+
+    OpenInterestCapacity {
+        long_capacity: limits.remaining_side_capacity(&funding_rate_tracker, true),
+        short_capacity: limits.remaining_side_capacity(&funding_rate_tracker, false),
+        account_capacity: limits.remaining_account_capacity(account_notional),
+    }
+}
+
+/// Get Price Band Details
+///
+/// Returns the oracle price band and circuit breaker parameters
+
+#[ic_cdk::query(name = "getPriceBandDetails")]
+fn get_price_band_details() -> PriceBandDetails {
+    _get_price_band_details()
+}
+
+/// Get Oracle Details
+///
+/// Returns the last oracle price (as a tick) and the current divergence status
+
+#[ic_cdk::query(name = "getOracleDetails")]
+fn get_oracle_details() -> OracleDetails {
+    _get_oracle_details()
+}
+
+/// Get Circuit Breaker Events
+///
+/// Returns all recorded trading halts caused by a sustained divergence between the oracle and the book
+
+#[ic_cdk::query(name = "getCircuitBreakerEvents")]
+fn get_circuit_breaker_events() -> Vec<CircuitBreakerEvent> {
+    CIRCUIT_BREAKER_EVENTS
+        .with_borrow(|reference| reference.iter().map(|(_, event)| event).collect())
+}
+
+/// Get Events
+///
+/// Returns market events in order of sequence starting at the start sequence
+///
+/// Params
+///  - Start :The sequence of the first event to return
+///  - Limit :The max number of events to return ,capped at MAX_EVENTS_PAGE
+
+#[ic_cdk::query(name = "getEvents")]
+fn get_events(start: u64, limit: u32) -> Vec<MarketEvent> {
+    MARKET_EVENTS.with_borrow(|reference| {
+        reference
+            .range(start..)
+            .take(limit.min(MAX_EVENTS_PAGE) as usize)
+            .map(|(_, event)| event)
+            .collect()
+    })
+}
+
+/// Get Account Events
+///
+/// Returns the market events of a particular account in order of sequence starting at the start sequence
+///
+/// Params
+///  - Account :The account of the position owner
+///  - Start :The least sequence of events to return
+///  - Limit :The max number of events to return ,capped at MAX_EVENTS_PAGE
+
+#[ic_cdk::query(name = "getAccountEvents")]
+fn get_account_events(account: Subaccount, start: u64, limit: u32) -> Vec<MarketEvent> {
+    let sequences: Vec<u64> = ACCOUNT_EVENTS.with_borrow(|reference| {
+        reference
+            .range((account, start)..)
+            .take_while(|((event_account, _), _)| *event_account == account)
+            .take(limit.min(MAX_EVENTS_PAGE) as usize)
+            .map(|((_, sequence), _)| sequence)
+            .collect()
+    });
+
+    MARKET_EVENTS.with_borrow(|reference| {
+        sequences
+            .iter()
+            .filter_map(|sequence| reference.get(sequence))
+            .collect()
+    })
+}
+
+/// Get Candles
+///
+/// Returns the candles of a particular resolution with open times between from and to ,in order of open time
+///
+/// Params
+///  - Resolution :The candle resolution
+///  - From :Timestamp within the first candle
+///  - To :Timestamp within the last candle
+
+#[ic_cdk::query(name = "getCandles")]
+fn get_candles(resolution: CandleResolution, from: u64, to: u64) -> Vec<Candle> {
+    if from > to {
+        return Vec::new();
+    }
+    let id = resolution.id();
+    CANDLES.with_borrow(|reference| {
+        reference
+            .range((id, resolution.open_time(from))..=(id, to))
+            .map(|(_, candle)| candle)
+            .collect()
+    })
 }
 
 /// Try Close Function
@@ -132,7 +567,7 @@ fn get_tick_details(tick: Tick) -> TickDetails {
 /// Checks if a particular account's position of limit order type has been fully filled
 
 #[ic_cdk::query(name = "tryClose")]
-fn try_close(account: [u8; 32]) -> bool {
+fn try_close(account: [u8; 32]) -> Result<bool, MarketError> {
     return convert_position(account);
 }
 
@@ -144,6 +579,24 @@ fn get_account_position(account: [u8; 32]) -> PositionDetails {
     return _get_account_position(&account);
 }
 
+/// Get Position Health
+///
+/// Returns the valuation of an account's position at the current tick
+///
+/// Note
+///  - Returns none if account has no position ,position is a limit order that has not been converted or the valuation
+///  can not be calculated
+#[ic_cdk::query(name = "getPositionHealth")]
+fn get_position_health(account: Subaccount) -> Option<PositionHealth> {
+    let position = ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&account))?;
+
+    if let PositionOrderType::Limit(_) = position.order_type {
+        return None;
+    }
+
+    return _calc_position_health(position).ok();
+}
+
 /// Open PositionDetails function
 ///
 /// opens a new position for user (given that user has no existing position)
@@ -151,7 +604,7 @@ fn get_account_position(account: [u8; 32]) -> PositionDetails {
 /// Params
 /// - Collateral Value :: The amount in collatreal token to utilise as collateral
 /// - Max Tick :: max executing tick ,also seen as max price fro the _swap ,if set to none or set outside the required range ,default max tick is used
-/// - Leverage :: The leverage for the required position multiplies by 10 i.e a 1.5 levarage is 1.5 * 10 = 15
+/// - Leverage Bps :: The leverage for the required position in basis points i.e a 1.5 leverage is 15_000 ,must be at least 1x (10_000)
 /// - Long :: Indicating if its a long position or not ,true if long and false otherwise
 /// - Order Type :: the type of order to create
 ///  _
@@ -160,61 +613,120 @@ fn get_account_position(account: [u8; 32]) -> PositionDetails {
 ///  - Position:the details of the position
 ///
 /// Note
-///  - If Order type is a limit order ,max tick coinsides with the reference tick for the limit order
+///  - If Order type is a limit order ,max tick coinsides with the reference tick for the limit order and must not be above the
+///  max valid tick of the tick model
 ///  - ANON TICKS are for future purposes and have no effect for now
+///  - The position is only created after the vault acknowledges the reservation of its collateral and debt ,the
+///  swap can not be undone afterwards so a failed commit is retried and the position is returned
+///  - The account is locked till the vault commits the reservation ,if the vault rejects the commit e.g because the
+///  reservation expired and the user's balance no longer covers the collateral ,the position is unwound (see _unwind_position)
 
 #[ic_cdk::update(name = "openPosition")]
 async fn open_position(
     collateral_value: Amount,
     max_tick: Option<Tick>,
-    leveragex10: u8,
+    leverage_bps: u64,
     long: bool,
     order_type: OrderType,
     _anon_tick1: Tick,
     _anon_tick2: Tick,
-) -> Result<PositionDetails, String> {
+) -> Result<PositionDetails, MarketError> {
     let user = ic_cdk::caller();
 
     let account = user._to_subaccount();
 
-    //aseerts that user has no position already
-    let failed_initial_check = _has_position_or_pending_error_log(&account);
+    let mut state_details = _get_state_details();
+
+    state_details.mode.check_open(long)?;
 
-    if failed_initial_check {
-        return Err("Account has pending error or unclosed position ".to_string());
+    //aseerts that user has no position already
+    if ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.contains_key(&account)) {
+        return Err(MarketError::PendingPositionUpdate);
     }
 
-    let mut state_details = _get_state_details();
+    if ACCOUNTS_POSITION.with_borrow(|reference| reference.contains_key(&account)) {
+        return Err(MarketError::PositionExists);
+    }
 
-    assert!(state_details.not_paused);
+    // if leverage is below 1x or greater than max leverage returns
+    if leverage_bps < _ONE_X_LEVERAGE_BPS || leverage_bps > state_details.max_leverage_bps {
+        return Err(MarketError::LeverageOutOfRange {
+            min_leverage_bps: _ONE_X_LEVERAGE_BPS,
+            max_leverage_bps: state_details.max_leverage_bps,
+        });
+    }
 
-    // if leverage is greater than max leverage or collateral value is less than min collateral
-    //returns
-    if leveragex10 >= state_details.max_leveragex10
-        || collateral_value < state_details.min_collateral
-    {
-        return Err("Max leverage exceeded or collateral is too small".to_string());
+    // if collateral value is less than min collateral returns
+    if collateral_value < state_details.min_collateral {
+        return Err(MarketError::CollateralTooSmall {
+            min_collateral: state_details.min_collateral,
+        });
     }
 
     let market_details = _get_market_details();
 
     let vault = Vault::init(market_details.vault_id);
 
-    // levarage is always given as a multiple of ten
-    let debt_value = (u128::from(leveragex10 - 10) * collateral_value) / 10;
+    let debt_value = _calc_debt(collateral_value, leverage_bps)?;
+
+    let notional = collateral_value
+        .checked_add(debt_value)
+        .ok_or(MathError::Overflow)?;
+
+    // if risk tiers are set ,leverage is capped by the tier of the position notional and the initial margin
+    // ratio must not be below the tier's maintenance margin
+    let risk_tiers = _get_risk_tiers();
+    if !risk_tiers.tiers.is_empty() {
+        let equity = i128::try_from(collateral_value).map_err(|_| MathError::Overflow)?;
+        match risk_tiers.tier_for(notional) {
+            Some(tier) if leverage_bps > tier.max_leverage_bps => {
+                return Err(MarketError::LeverageAboveTier {
+                    max_leverage_bps: tier.max_leverage_bps,
+                });
+            }
+            Some(tier) if _calc_margin_ratio(equity, notional) < tier.maintenance_margin => {
+                return Err(MarketError::MarginBelowMaintenance {
+                    maintenance_margin: tier.maintenance_margin,
+                });
+            }
+            Some(_) => {}
+            None => return Err(MarketError::NotionalAboveMaxTier),
+        }
+    }
+
+    // if position notional exceeds the open interest limits of the market direction or of a single account
+    // returns
+    if !_get_open_interest_limits().within_limits(&_get_funding_rate_tracker(), notional, long) {
+        return Err(MarketError::OpenInterestLimitExceeded);
+    }
+
+    let stopping_tick = max_or_default_max(max_tick, state_details.current_tick, long);
+
+    // market orders can not move the current tick outside the oracle price band
+    let stopping_tick = if let OrderType::Market = order_type {
+        _price_band_stopping_tick(stopping_tick, state_details.current_tick, long)
+    } else {
+        // limit orders can not be placed at a tick the tick model prices the same as a lower tick
+        let max_valid_tick = _max_valid_tick(&market_details.tick_grid);
+        if stopping_tick > max_valid_tick {
+            return Err(MarketError::TickOutOfRange {
+                max_tick: max_valid_tick,
+            });
+        }
+        stopping_tick
+    };
 
-    // Checks if user has sufficient balance and vault contains free liquidity greater or equal to debt_value and then calculate interest rate
+    // Reserves the collateral from the user's balance and the debt from the vault's free liquidity and then calculate interest rate
+    let reservation_id = _next_reservation_id();
 
     let (valid, interest_rate) = vault
-        .create_position_validity_check(user, collateral_value, debt_value)
+        .reserve(reservation_id, user, collateral_value, debt_value)
         .await;
 
     if valid == false {
-        return Err("Not enough liquidity for debt".to_string());
+        return Err(MarketError::ReservationRejected);
     };
 
-    let stopping_tick = max_or_default_max(max_tick, state_details.current_tick, long);
-
     match _open_position(
         account,
         long,
@@ -231,36 +743,80 @@ async fn open_position(
 
             _update_state_details(state_details);
 
+            _record_event(
+                Some(account),
+                MarketEventKind::PositionOpened {
+                    long,
+                    limit: matches!(order_type, OrderType::Limit),
+                    collateral_value: position.collateral_value,
+                    debt_value: position.debt_value,
+                    entry_tick: position.entry_tick,
+                },
+            );
+
             let watcher = Watcher::init(market_details.watcher_id);
 
             if let OrderType::Limit = order_type {
                 watcher.store_tick_order(stopping_tick, account);
             } else {
                 watcher.execute_ticks_orders(crossed_ticks);
+            }
 
-                if position.debt_value != debt_value
-                    || collateral_value != position.collateral_value
-                {
-                    let un_used_collateral = collateral_value - position.collateral_value;
-                    vault.manage_position_update(
+            // the account is locked till the vault commits the reservation
+            PENDING_COMMITS.with_borrow_mut(|reference| {
+                reference.insert(
+                    account,
+                    PendingCommit {
+                        operation_id: reservation_id,
                         user,
-                        collateral_value - position.collateral_value,
-                        ManageDebtParams::init(position.debt_value, debt_value, un_used_collateral),
-                    );
+                        entry_value: position
+                            .collateral_value
+                            .saturating_add(position.debt_value),
+                        unwinding: false,
+                    },
+                )
+            });
+
+            // returns the unused part of the reservation
+            match vault
+                .commit(
+                    account,
+                    reservation_id,
+                    position.collateral_value,
+                    position.debt_value,
+                )
+                .await
+            {
+                Ok(committed) => {
+                    _settle_commit(account, committed).await;
+                    if !committed {
+                        return Err(MarketError::CommitRejected);
+                    }
                 }
+                // the commit is retried from the retry queue ,the position is already open
+                Err(_) => {}
             }
 
             return Ok(position);
         }
         None => {
-            // send back
-            vault.manage_position_update(
-                user,
-                collateral_value,
-                ManageDebtParams::init(0, debt_value, 0),
+            _record_event(
+                Some(account),
+                MarketEventKind::PositionOpenFailed {
+                    long,
+                    collateral_value,
+                    debt_value,
+                },
             );
 
-            return Err("Failed to open position".to_string());
+            // send back
+            if let Err(reason) = vault.release(reservation_id).await {
+                return Err(MarketError::VaultUpdatePending {
+                    reason: format!("Failed to open position ,refund pending :{}", reason),
+                });
+            }
+
+            return Err(MarketError::OpenFailed);
         }
     }
 }
@@ -274,18 +830,39 @@ async fn open_position(
 ///
 /// Note
 ///  - if position_type is order ,the collateral is sent back and debt is sent back without interest
+///  - The swap can not be undone once executed ,so the account is locked with a pending error log from before the
+///  vault update until the vault acknowledges it
+///  - if the vault does not acknowledge the update ,the reject reason is returned and the account remains locked
+///  until the update is retried successfully
+///  - the account's error log keeps the position from before the close till the vault acknowledges the update ,an
+///  update the vault keeps rejecting e.g for repaying more debt than the vault holds needs a manual fix with amendAccountError
+///  - if an amount can not be calculated an arithmetic error is returned ,amounts are calculated before the swap
+///  wherever they do not depend on its result
 ///
 #[ic_cdk::update(name = "closePosition")]
-async fn close_position(max_tick: Option<Tick>) -> Amount {
+async fn close_position(max_tick: Option<Tick>) -> Result<Amount, MarketError> {
     let user = ic_cdk::caller();
 
     let account = user._to_subaccount();
 
-    let mut position = _get_account_position(&account);
-
     let mut state_details = _get_state_details();
 
-    assert!(state_details.not_paused);
+    state_details.mode.check_close()?;
+
+    // a position whose reservation is not committed could repay debt the vault does not hold
+    if ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.contains_key(&account))
+        || PENDING_COMMITS.with_borrow(|reference| reference.contains_key(&account))
+    {
+        return Err(MarketError::PendingPositionUpdate);
+    }
+
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&account)) {
+        Some(position) => position,
+        None => return Err(MarketError::NoPosition),
+    };
+
+    // kept in the account's error log till the vault acknowledges the update
+    let initial_position = position;
     //
     let market_details = _get_market_details();
 
@@ -298,42 +875,82 @@ async fn close_position(max_tick: Option<Tick>) -> Amount {
         PositionOrderType::Market => {
             let current_tick = state_details.current_tick;
 
-            let stopping_tick = max_or_default_max(max_tick, current_tick, !position.long);
+            let stopping_tick = _price_band_stopping_tick(
+                max_or_default_max(max_tick, current_tick, !position.long),
+                current_tick,
+                !position.long,
+            );
             // if position type is market ,means the position is already active
             let (collateral_value, resulting_tick, crossed_ticks, manage_debt_params) = if position
                 .long
             {
-                _close_market_long_position(account, &mut position, current_tick, stopping_tick)
+                _close_market_long_position(account, &mut position, current_tick, stopping_tick)?
             } else {
-                _close_market_short_position(account, &mut position, current_tick, stopping_tick)
+                _close_market_short_position(account, &mut position, current_tick, stopping_tick)?
             };
             // update current_tick
             state_details.current_tick = resulting_tick;
 
             _update_state_details(state_details);
 
+            let sequence = _record_event(
+                Some(account),
+                MarketEventKind::PositionClosed {
+                    long: position.long,
+                    amount_out: collateral_value,
+                    resulting_tick,
+                },
+            );
+
             // send out ticks
             watcher.execute_ticks_orders(crossed_ticks);
 
-            vault.manage_position_update(user, collateral_value, manage_debt_params);
+            vault
+                .manage_position_update(
+                    sequence,
+                    user,
+                    collateral_value,
+                    manage_debt_params,
+                    Some(initial_position),
+                )
+                .await
+                .map_err(|reason| MarketError::VaultUpdatePending { reason })?;
 
             // return profits
-            return collateral_value;
+            return Ok(collateral_value);
         }
         PositionOrderType::Limit(_) => {
             let (removed_collateral, manage_debt_params) = if position.long {
-                _close_limit_long_position(account, &mut position)
+                _close_limit_long_position(account, &mut position)?
             } else {
-                _close_limit_short_position(account, &mut position)
+                _close_limit_short_position(account, &mut position)?
             };
 
             if manage_debt_params.new_debt == 0 {
                 watcher.remove_tick_order(position.entry_tick, account)
             }
 
-            vault.manage_position_update(user, removed_collateral, manage_debt_params);
+            let sequence = _record_event(
+                Some(account),
+                MarketEventKind::PositionClosed {
+                    long: position.long,
+                    amount_out: removed_collateral,
+                    resulting_tick: state_details.current_tick,
+                },
+            );
+
+            vault
+                .manage_position_update(
+                    sequence,
+                    user,
+                    removed_collateral,
+                    manage_debt_params,
+                    Some(initial_position),
+                )
+                .await
+                .map_err(|reason| MarketError::VaultUpdatePending { reason })?;
 
-            return removed_collateral;
+            return Ok(removed_collateral);
         }
     };
 }
@@ -348,25 +965,35 @@ async fn close_position(max_tick: Option<Tick>) -> Amount {
 /// Note:
 ///  - This function can only be called by watcher
 ///  - This function does not check if canister is paused or not,to prevent watcher from encountering an error
+///  - Returns an arithmetic error if the order's amounts can not be calculated
 
 #[ic_cdk::update(name = "convertPosition")]
-fn convert_position(account: Subaccount) -> bool {
+fn convert_position(account: Subaccount) -> Result<bool, MarketError> {
     // assert that only watcher can call this function
 
     let mut position = _get_account_position(&account);
 
     if let PositionOrderType::Limit(order) = position.order_type {
         //since tivk is deleted ,order has been confirmed closed
-        let (_, amount_remaining) = _close_order(&order);
+        let (_, amount_remaining) = _close_order(&order)?;
 
         let valid_close = amount_remaining == 0;
-        _convert_limit_position(&mut position, 0);
+        _convert_limit_position(&mut position, 0)?;
         _insert_account_position(account, position);
 
-        return valid_close;
+        _record_event(
+            Some(account),
+            MarketEventKind::PositionConverted {
+                long: position.long,
+                entry_tick: position.entry_tick,
+                filled: valid_close,
+            },
+        );
+
+        return Ok(valid_close);
     }
 
-    return false;
+    return Ok(false);
 }
 
 ///
@@ -391,6 +1018,7 @@ fn convert_position(account: Subaccount) -> bool {
 ///  - - Crossed Ticks :A vector of all crossed ticks during swap
 /// Note
 ///  - If position can not be opened it returns none and both collateral and debt gets refunded back and swap is reverted afterwards
+///  - Amounts that can not be calculated also return none
 ///
 fn _open_position(
     account: Subaccount,
@@ -402,10 +1030,12 @@ fn _open_position(
     current_tick: Tick,
     max_tick: Tick,
 ) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    //
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
+    let grid = _get_tick_grid();
+    let decimals = _get_asset_decimals();
+    // order sizes and refunds are both rounded down
+    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Result<Amount, MathError> {
+        let tick_price = _tick_to_price(tick, &grid);
+        _equivalent(amount, tick_price, buy, &decimals, Rounding::Down)
     };
     let position: PositionDetails;
 
@@ -413,7 +1043,8 @@ fn _open_position(
 
     match order_type {
         OrderType::Limit => {
-            let entry_tick = max_tick;
+            // entry tick is moved onto the grid ,away from the current tick
+            let entry_tick = _align_to_grid(max_tick, &grid, !long);
             // limit order's can't be placed at current tick
             if long && entry_tick >= current_tick {
                 return None;
@@ -426,12 +1057,12 @@ fn _open_position(
                 (collateral_value, debt_value)
             } else {
                 (
-                    equivalent(collateral_value, entry_tick, true),
-                    equivalent(debt_value, entry_tick, true),
+                    equivalent(collateral_value, entry_tick, true).ok()?,
+                    equivalent(debt_value, entry_tick, true).ok()?,
                 )
             };
             //
-            let mut order = LimitOrder::new(collateral + debt, entry_tick, long);
+            let mut order = LimitOrder::new(collateral.checked_add(debt)?, entry_tick, long);
 
             _open_order(&mut order);
 
@@ -455,15 +1086,15 @@ fn _open_position(
                 (collateral_value, debt_value)
             } else {
                 (
-                    equivalent(collateral_value, current_tick, true),
-                    equivalent(debt_value, current_tick, true),
+                    equivalent(collateral_value, current_tick, true).ok()?,
+                    equivalent(debt_value, current_tick, true).ok()?,
                 )
             };
 
             // swap is executed
 
             let (amount_out, amount_remaining, resulting_tick, crossed_ticks) =
-                _swap(collateral + debt, long, current_tick, max_tick);
+                _swap(collateral.checked_add(debt)?, long, current_tick, max_tick);
 
             if amount_out == 0 {
                 return None;
@@ -473,11 +1104,13 @@ fn _open_position(
             let amount_remaining_value = if long {
                 amount_remaining
             } else {
-                equivalent(amount_remaining, current_tick, false)
+                equivalent(amount_remaining, current_tick, false).ok()?
             };
 
             let position_value = if long {
-                collateral_value + debt_value - amount_remaining_value
+                collateral_value
+                    .checked_add(debt_value)?
+                    .checked_sub(amount_remaining_value)?
             } else {
                 amount_out
             };
@@ -486,16 +1119,17 @@ fn _open_position(
 
             if amount_remaining_value >= debt_value {
                 unused_debt_value = debt_value;
-                unused_collateral_value = amount_remaining - debt_value
+                unused_collateral_value = amount_remaining_value.checked_sub(debt_value)?
             } else {
                 unused_debt_value = amount_remaining_value;
                 unused_collateral_value = 0
             }
 
-            let resulting_debt_value = debt_value - unused_debt_value;
-            let resulting_collateral_value = collateral_value - unused_collateral_value;
+            let resulting_debt_value = debt_value.checked_sub(unused_debt_value)?;
+            let resulting_collateral_value =
+                collateral_value.checked_sub(unused_collateral_value)?;
 
-            let volume_share = _calc_position_volume_share(position_value, long);
+            let volume_share = _calc_position_volume_share(position_value, long).ok()?;
 
             position = PositionDetails {
                 long,
@@ -516,166 +1150,60 @@ fn _open_position(
     return Some(open_position_result);
 }
 
-fn _open_market_long_position(
-    account: Subaccount,
-    long: bool,
-    collateral_value: Amount,
-    debt_value: Amount,
-    interest_rate: u32,
-    current_tick: Tick,
-    max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let (collateral, debt) = (collateral_value, debt_value);
-
-    let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks) =
-        _swap(collateral + debt, long, current_tick, max_tick);
-
-    if amount_out == 0 {
-        return None;
-    }
-
-    let position_value = collateral_value + debt_value - amount_remaining_value;
-
-    let (unused_collateral_value, unused_debt_value);
-
-    if amount_remaining_value >= debt_value {
-        unused_debt_value = debt_value;
-        unused_collateral_value = amount_remaining_value - debt_value
-    } else {
-        unused_debt_value = amount_remaining_value;
-        unused_collateral_value = 0
-    }
-
-    let resulting_debt_value = debt_value - unused_debt_value;
-    let resulting_collateral_value = collateral_value - unused_collateral_value;
-
-    let volume_share = _calc_position_volume_share(position_value, long);
-
-    let position = PositionDetails {
-        long,
-        entry_tick: resulting_tick,
-        collateral_value: resulting_collateral_value,
-        debt_value: resulting_debt_value, //actual debt
-        interest_rate,
-        volume_share,
-        order_type: PositionOrderType::Market,
-        timestamp: ic_cdk::api::time(), //change to time()
-    };
-    _insert_account_position(account, position);
-
-    return Some((position, resulting_tick, crossed_ticks));
-}
-
-fn _open_market_short_position(
-    account: Subaccount,
-    long: bool,
-    collateral_value: Amount,
-    debt_value: Amount,
-    interest_rate: u32,
-    current_tick: Tick,
-    max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
-    };
-    let (collateral, debt) = (
-        equivalent(collateral_value, current_tick, true),
-        equivalent(debt_value, current_tick, true),
-    );
-
-    // swap is executed
-
-    let (amount_out, amount_remaining, resulting_tick, crossed_ticks) =
-        _swap(collateral + debt, long, current_tick, max_tick);
-
-    if amount_out == 0 {
-        return None;
-    }
-
-    // the amount remaining value to be refunded to
-    let amount_remaining_value = equivalent(amount_remaining, current_tick, false);
-
-    let position_value = amount_out;
-
-    let (unused_collateral_value, unused_debt_value);
-
-    if amount_remaining_value >= debt_value {
-        unused_debt_value = debt_value;
-        unused_collateral_value = amount_remaining - debt_value
-    } else {
-        unused_debt_value = amount_remaining_value;
-        unused_collateral_value = 0
-    }
-
-    let resulting_debt_value = debt_value - unused_debt_value;
-    let resulting_collateral_value = collateral_value - unused_collateral_value;
-
-    let volume_share = _calc_position_volume_share(position_value, long);
-
-    let position = PositionDetails {
-        long,
-        entry_tick: resulting_tick,
-        collateral_value: resulting_collateral_value,
-        debt_value: resulting_debt_value, //actual debt
-        interest_rate,
-        volume_share,
-        order_type: PositionOrderType::Market,
-        timestamp: ic_cdk::api::time(), //change to time()
-    };
-    _insert_account_position(account, position);
-    return Some((position, resulting_tick, crossed_ticks));
-}
-
-/// Close Long PositionDetails
-///
-///closes a user's  long position if position can be fully closed and  repays debt
-///
-/// Params
-/// - User :The user (position owner)
-/// - PositionDetails :The PositionDetails
-/// - Current Tick :The current tick of market's state
-/// - Stopping Tick : The max tick,corresponds to max price
-/// - Vault : Vault canister
-///
-/// Returns
-///  - Current Collateral :The amount to send to position owner after paying debt ,this amount is zero if debt is not fully paid
-///  - Resulting Tick :The resulting tick from swapping
-///  - Crosssed Ticks :An array of ticks that have been crossed during swapping
-///   
-/// Note
-///  - If position can not be closed fully ,the position is partially closed (updated) and debt is paid back either fully or partially
-fn _close_market_long_position(
+/// Close Long PositionDetails
+///
+///closes a user's  long position if position can be fully closed and  repays debt
+///
+/// Params
+/// - User :The user (position owner)
+/// - PositionDetails :The PositionDetails
+/// - Current Tick :The current tick of market's state
+/// - Stopping Tick : The max tick,corresponds to max price
+/// - Vault : Vault canister
+///
+/// Returns
+///  - Current Collateral :The amount to send to position owner after paying debt ,this amount is zero if debt is not fully paid
+///  - Resulting Tick :The resulting tick from swapping
+///  - Crosssed Ticks :An array of ticks that have been crossed during swapping
+///   
+/// Note
+///  - If position can not be closed fully ,the position is partially closed (updated) and debt is paid back either fully or partially
+fn _close_market_long_position(
     account: Subaccount,
     position: &mut PositionDetails,
     current_tick: Tick,
     stopping_tick: Tick,
-) -> (Amount, Tick, Vec<Tick>, ManageDebtParams) {
-    //
-    let entry_price = _tick_to_price(position.entry_tick);
-    let equivalent_at_entry_price =
-        |amount: Amount, buy: bool| -> Amount { _equivalent(amount, entry_price, buy) };
+) -> Result<(Amount, Tick, Vec<Tick>, ManageDebtParams), MathError> {
     //
-    let position_realised_value = _calc_position_realised_val(position.volume_share, true);
-    // amount to swap
-
-    let realised_position_size = equivalent_at_entry_price(position_realised_value, true);
-
-    let (amount_out_value, amount_remaining, resulting_tick, crossed_ticks) =
-        _swap(realised_position_size, false, current_tick, stopping_tick);
+    let entry_price = _tick_to_price(position.entry_tick, &_get_tick_grid());
+    let decimals = _get_asset_decimals();
+    // the size swapped and the value of the size remaining are both rounded down
+    let equivalent_at_entry_price = |amount: Amount, buy: bool| -> Result<Amount, MathError> {
+        _equivalent(amount, entry_price, buy, &decimals, Rounding::Down)
+    };
 
     let interest_value = _calc_interest(
         position.debt_value,
         position.interest_rate,
         position.timestamp,
-    );
+    )?;
+    // amount to swap ,calculated before the volume share is removed so an error leaves the funding rate tracker unchanged
+    let realised_position_size = equivalent_at_entry_price(
+        _get_funding_rate_tracker().volume_share_value(position.volume_share, true)?,
+        true,
+    )?;
+
+    _calc_position_realised_val(position.volume_share, true)?;
+
+    let (amount_out_value, amount_remaining, resulting_tick, crossed_ticks) =
+        _swap(realised_position_size, false, current_tick, stopping_tick);
 
     let profit;
 
     let manage_debt_params;
 
     if amount_remaining > 0 {
-        let amount_remaining_value = equivalent_at_entry_price(amount_remaining, false);
+        let amount_remaining_value = equivalent_at_entry_price(amount_remaining, false)?;
         //
         (profit, manage_debt_params) = _update_market_position_after_swap(
             position,
@@ -683,18 +1211,16 @@ fn _close_market_long_position(
             amount_out_value,
             amount_remaining_value,
             interest_value,
-        );
+        )?;
 
         _insert_account_position(account, position.clone());
     } else {
-        (profit, manage_debt_params) = (
-            amount_out_value - (position.debt_value + interest_value),
-            ManageDebtParams::init(0, position.debt_value, interest_value),
-        );
+        (profit, manage_debt_params) =
+            _settle_position_debt(amount_out_value, position.debt_value, interest_value);
         _remove_account_position(&account);
     }
 
-    return (profit, resulting_tick, crossed_ticks, manage_debt_params);
+    return Ok((profit, resulting_tick, crossed_ticks, manage_debt_params));
 }
 
 ///
@@ -707,24 +1233,30 @@ fn _close_market_short_position(
     position: &mut PositionDetails,
     current_tick: Tick,
     stopping_tick: Tick,
-) -> (Amount, Tick, Vec<Tick>, ManageDebtParams) {
-    let position_realised_value = _calc_position_realised_val(position.volume_share, false);
+) -> Result<(Amount, Tick, Vec<Tick>, ManageDebtParams), MathError> {
+    let interest_value = _calc_interest(
+        position.debt_value,
+        position.interest_rate,
+        position.timestamp,
+    )?;
+
+    let position_realised_value = _calc_position_realised_val(position.volume_share, false)?;
 
     let realised_position_size = position_realised_value;
 
     let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks) =
         _swap(realised_position_size, true, current_tick, stopping_tick);
 
-    let init_price = _tick_to_price(current_tick);
+    let init_price = _tick_to_price(current_tick, &_get_tick_grid());
 
     // amount out value is calculated as the amount of collateral token used up in the swap
-    let amount_out_value = _equivalent(amount_out, init_price, false); // position_realised_value - amount_remaining_value;
-
-    let interest_value = _calc_interest(
-        position.debt_value,
-        position.interest_rate,
-        position.timestamp,
-    );
+    let amount_out_value = _equivalent(
+        amount_out,
+        init_price,
+        false,
+        &_get_asset_decimals(),
+        Rounding::Down,
+    )?; // position_realised_value - amount_remaining_value;
 
     let profit;
     let manage_debt_params: ManageDebtParams;
@@ -736,19 +1268,17 @@ fn _close_market_short_position(
             amount_out_value,
             amount_remaining_value,
             interest_value,
-        );
+        )?;
 
         _insert_account_position(account, position.clone());
     } else {
-        (profit, manage_debt_params) = (
-            amount_out_value - (position.debt_value + interest_value),
-            ManageDebtParams::init(0, position.debt_value, interest_value),
-        );
+        (profit, manage_debt_params) =
+            _settle_position_debt(amount_out_value, position.debt_value, interest_value);
         // deletes user position
         _remove_account_position(&account);
     }
 
-    return (profit, resulting_tick, crossed_ticks, manage_debt_params);
+    return Ok((profit, resulting_tick, crossed_ticks, manage_debt_params));
 }
 
 /// Close Limit Position
@@ -764,11 +1294,11 @@ fn _close_market_short_position(
 fn _close_limit_long_position(
     account: Subaccount,
     position: &mut PositionDetails,
-) -> (Amount, ManageDebtParams) {
+) -> Result<(Amount, ManageDebtParams), MathError> {
     match position.order_type {
         //
         PositionOrderType::Limit(order) => {
-            let (amount_received, amount_remaining_value) = _close_order(&order);
+            let (amount_received, amount_remaining_value) = _close_order(&order)?;
 
             let (removed_collateral, manage_debt_params);
 
@@ -781,14 +1311,14 @@ fn _close_limit_long_position(
                 _remove_account_position(&account);
             } else {
                 (removed_collateral, manage_debt_params) =
-                    _convert_limit_position(position, amount_remaining_value);
+                    _convert_limit_position(position, amount_remaining_value)?;
                 //
                 _insert_account_position(account, position.clone());
             };
 
-            return (removed_collateral, manage_debt_params);
+            return Ok((removed_collateral, manage_debt_params));
         }
-        PositionOrderType::Market => (0, ManageDebtParams::default()),
+        PositionOrderType::Market => Ok((0, ManageDebtParams::default())),
     }
 }
 
@@ -803,10 +1333,10 @@ fn _close_limit_long_position(
 fn _close_limit_short_position(
     account: Subaccount,
     position: &mut PositionDetails,
-) -> (Amount, ManageDebtParams) {
+) -> Result<(Amount, ManageDebtParams), MathError> {
     match position.order_type {
         PositionOrderType::Limit(order) => {
-            let (amount_received, amount_remaining) = _close_order(&order);
+            let (amount_received, amount_remaining) = _close_order(&order)?;
 
             let (removed_collateral, manage_debt_params);
 
@@ -818,18 +1348,24 @@ fn _close_limit_short_position(
                 _remove_account_position(&account);
                 //
             } else {
-                let entry_price = _tick_to_price(position.entry_tick);
-
-                let amount_remaining_value = _equivalent(amount_remaining, entry_price, false);
+                let entry_price = _tick_to_price(position.entry_tick, &_get_tick_grid());
+
+                let amount_remaining_value = _equivalent(
+                    amount_remaining,
+                    entry_price,
+                    false,
+                    &_get_asset_decimals(),
+                    Rounding::Down,
+                )?;
                 (removed_collateral, manage_debt_params) =
-                    _convert_limit_position(position, amount_remaining_value);
+                    _convert_limit_position(position, amount_remaining_value)?;
                 // updates users positiion
                 _insert_account_position(account, position.clone());
             };
 
-            return (removed_collateral, manage_debt_params);
+            return Ok((removed_collateral, manage_debt_params));
         }
-        PositionOrderType::Market => return (0, ManageDebtParams::default()),
+        PositionOrderType::Market => return Ok((0, ManageDebtParams::default())),
     }
 }
 
@@ -859,7 +1395,7 @@ fn _update_market_position_after_swap(
     amount_out_value: Amount,
     amount_remaining_value: Amount,
     interest_value: Amount,
-) -> (Amount, ManageDebtParams) {
+) -> Result<(Amount, ManageDebtParams), MathError> {
     let initial_debt = position.debt_value;
 
     let total_fee_value = initial_debt + interest_value;
@@ -888,7 +1424,7 @@ fn _update_market_position_after_swap(
         profit = amount_out_value - total_fee_value;
     }
 
-    let new_volume_share = _calc_position_volume_share(amount_remaining_value, position.long);
+    let new_volume_share = _calc_position_volume_share(amount_remaining_value, position.long)?;
     //
     position.volume_share = new_volume_share;
     position.entry_tick = resulting_tick;
@@ -898,7 +1434,34 @@ fn _update_market_position_after_swap(
         position.timestamp = ic_cdk::api::time()
     }
 
-    return (profit, manage_debt_params);
+    return Ok((profit, manage_debt_params));
+}
+
+/// Settle Position Debt
+///
+/// Pays back the debt and interest of a fully closed position from the amount out
+///
+/// Returns
+///  - Profit :The amount left for the position owner after paying debt and interest
+///  - Manage Debt Params :for repaying the debt ,any debt the amount out can not cover is bad debt
+fn _settle_position_debt(
+    amount_out_value: Amount,
+    debt_value: Amount,
+    interest_value: Amount,
+) -> (Amount, ManageDebtParams) {
+    if amount_out_value >= debt_value + interest_value {
+        return (
+            amount_out_value - (debt_value + interest_value),
+            ManageDebtParams::init(0, debt_value, interest_value),
+        );
+    }
+
+    let interest_received_value = amount_out_value.saturating_sub(debt_value);
+
+    let mut manage_debt_params = ManageDebtParams::init(0, debt_value, interest_received_value);
+    manage_debt_params.bad_debt = debt_value.saturating_sub(amount_out_value);
+
+    return (0, manage_debt_params);
 }
 
 ///
@@ -918,7 +1481,7 @@ fn _update_market_position_after_swap(
 fn _convert_limit_position(
     position: &mut PositionDetails,
     amount_remaining_value: Amount,
-) -> (Amount, ManageDebtParams) {
+) -> Result<(Amount, ManageDebtParams), MathError> {
     let remaining_order_value =
         position.collateral_value + position.debt_value - amount_remaining_value; // value of amount out
     let initial_debt = position.debt_value;
@@ -934,7 +1497,7 @@ fn _convert_limit_position(
 
         position.debt_value -= amount_remaining_value;
     }
-    let volume_share = _calc_position_volume_share(remaining_order_value, position.long);
+    let volume_share = _calc_position_volume_share(remaining_order_value, position.long)?;
 
     position.volume_share = volume_share;
     position.order_type = PositionOrderType::Market;
@@ -942,7 +1505,7 @@ fn _convert_limit_position(
 
     let manage_debt_params = ManageDebtParams::init(position.debt_value, initial_debt, 0);
 
-    return (removed_collateral, manage_debt_params);
+    return Ok((removed_collateral, manage_debt_params));
 }
 
 ///
@@ -955,12 +1518,14 @@ fn _convert_limit_position(
 /// - Reference Tick :: The  tick to place order
 
 fn _open_order(order: &mut LimitOrder) {
+    let grid = _get_tick_grid();
     TICKS_DETAILS.with_borrow_mut(|ticks_details| {
         INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
             let mut open_order_params = OpenOrderParams {
                 order,
                 integrals_bitmaps,
                 ticks_details,
+                grid: &grid,
             };
             open_order_params.open_order();
         })
@@ -982,13 +1547,17 @@ fn _open_order(order: &mut LimitOrder) {
 ///  - Amont Out :: This corresponds to the asset to be bought i.e perp(base) asset for a buy order or quote asset for a sell order
 ///  - Amount Remaining :: This amount remaining corrseponds to the amount of asset at that tick that is still unfilled
 ///
-fn _close_order(order: &LimitOrder) -> (Amount, Amount) {
+fn _close_order(order: &LimitOrder) -> Result<(Amount, Amount), MathError> {
+    let grid = _get_tick_grid();
+    let decimals = _get_asset_decimals();
     TICKS_DETAILS.with_borrow_mut(|ticks_details| {
         INTEGRAL_BITMAPS.with_borrow_mut(|multipliers_bitmaps| {
             let mut close_order_params = CloseOrderParams {
                 order,
                 multipliers_bitmaps,
                 ticks_details,
+                grid: &grid,
+                decimals: &decimals,
             };
             close_order_params.close_order()
         })
@@ -1014,6 +1583,8 @@ fn _swap(
     init_tick: Tick,
     stopping_tick: Tick,
 ) -> (Amount, Amount, Tick, Vec<Tick>) {
+    let grid = _get_tick_grid();
+    let decimals = _get_asset_decimals();
     TICKS_DETAILS.with_borrow_mut(|ticks_details| {
         INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
             let mut swap_params = SwapParams {
@@ -1023,35 +1594,165 @@ fn _swap(
                 order_size,
                 integrals_bitmaps,
                 ticks_details,
+                grid: &grid,
+                decimals: &decimals,
             };
-            swap_params._swap()
+            let swap_result = swap_params._swap();
+
+            let (amount_out, amount_remaining, resulting_tick, crossed_ticks) = &swap_result;
+
+            // volume in base (perp) asset
+            let volume = if buy {
+                *amount_out
+            } else {
+                order_size - amount_remaining
+            };
+
+            if volume > 0 {
+                // crossed ticks are in the order they were swapped at ,so the first tick and the resulting tick
+                // are the price range of the swap
+                let first_tick = crossed_ticks.first().unwrap_or(resulting_tick);
+                _update_candles(
+                    _tick_to_price(*first_tick, &grid),
+                    _tick_to_price(*resulting_tick, &grid),
+                    volume,
+                );
+            }
+
+            swap_result
         })
     })
 }
 
+/// Update Candles
+///
+/// Updates the current candle of every resolution with the prices and volume of an executed swap
+///
+/// Params
+///  - First Price :The price of the first tick the swap was executed at
+///  - Last Price :The price of the last tick the swap was executed at
+///  - Volume :The amount of base (perp) asset swapped
+///
+/// Note
+///  - Candles older than the max candles of a resolution are removed when a new candle is opened
+fn _update_candles(first_price: Amount, last_price: Amount, volume: Amount) {
+    let now = ic_cdk::api::time();
+
+    CANDLES.with_borrow_mut(|reference| {
+        for resolution in CandleResolution::ALL {
+            let id = resolution.id();
+            let open_time = resolution.open_time(now);
+
+            match reference.get(&(id, open_time)) {
+                Some(mut candle) => {
+                    candle.update(first_price, 0);
+                    candle.update(last_price, volume);
+                    reference.insert((id, open_time), candle);
+                }
+                None => {
+                    let mut candle = Candle::new(open_time, first_price, 0);
+                    candle.update(last_price, volume);
+                    reference.insert((id, open_time), candle);
+
+                    let cutoff =
+                        open_time.saturating_sub(resolution.max_candles() * resolution.duration());
+
+                    let expired: Vec<(u8, u64)> = reference
+                        .range((id, 0)..(id, cutoff))
+                        .map(|(key, _)| key)
+                        .collect();
+
+                    for key in expired {
+                        reference.remove(&key);
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Quote Swap Function
+///
+/// Runs the swap on a heap copy of the part of the book between the init tick and the stopping tick ,leaving the book unchanged
+///
+/// Params and Returns are the same as the Swap Function
+fn _quote_swap(
+    order_size: Amount,
+    buy: bool,
+    init_tick: Tick,
+    stopping_tick: Tick,
+) -> (Amount, Amount, Tick, Vec<Tick>) {
+    let (lower_tick, upper_tick) = (init_tick.min(stopping_tick), init_tick.max(stopping_tick));
+
+    let grid = _get_tick_grid();
+
+    let (lower_integral, _) = _int_and_dec(lower_tick, &grid);
+    let (upper_integral, _) = _int_and_dec(upper_tick, &grid);
+
+    let mut ticks_details: HashMap<Tick, TickDetails> =
+        TICKS_DETAILS.with_borrow(|reference| reference.range(lower_tick..=upper_tick).collect());
+
+    let mut integrals_bitmaps: HashMap<u64, u128> = INTEGRAL_BITMAPS
+        .with_borrow(|reference| reference.range(lower_integral..=upper_integral).collect());
+
+    let mut swap_params = SwapParams {
+        buy,
+        init_tick,
+        stopping_tick,
+        order_size,
+        integrals_bitmaps: &mut integrals_bitmaps,
+        ticks_details: &mut ticks_details,
+        grid: &grid,
+        decimals: &_get_asset_decimals(),
+    };
+    swap_params._swap()
+}
+
 /// Max or Default Max Tick
 ///
 /// retrieves the max tick if valid else returns the default max tick
 
 fn max_or_default_max(max_tick: Option<Tick>, current_tick: Tick, buy: bool) -> Tick {
+    let grid = _get_tick_grid();
     match max_tick {
         Some(tick) => {
-            if buy && tick < _def_max_tick(current_tick, true) {
+            if buy && tick < _def_max_tick(current_tick, true, &grid) {
                 return tick;
             };
-            if !buy && tick > _def_max_tick(current_tick, false) {
+            if !buy && tick > _def_max_tick(current_tick, false, &grid) {
                 return tick;
             }
         }
         None => {
-            if buy {
-                return current_tick + _percentage64(DEFAULT_SWAP_SLIPPAGE, current_tick);
-            } else {
-                return current_tick - _percentage64(DEFAULT_SWAP_SLIPPAGE, current_tick);
-            }
+            return _offset_tick(current_tick, DEFAULT_SWAP_SLIPPAGE, buy, &grid);
         }
     }
-    return _def_max_tick(current_tick, buy);
+    return _def_max_tick(current_tick, buy, &grid);
+}
+
+/// Price Band Stopping Tick
+///
+/// Truncates the stopping tick of a swap to the oracle price band
+///
+/// Note
+///  - The stopping tick is returned unchanged if no band is set or no oracle price has been fetched yet
+fn _price_band_stopping_tick(stopping_tick: Tick, current_tick: Tick, buy: bool) -> Tick {
+    let price_band_details = _get_price_band_details();
+
+    let oracle_tick = _get_oracle_details().oracle_tick;
+
+    if price_band_details.band_width == 0 || oracle_tick == 0 {
+        return stopping_tick;
+    }
+
+    _band_limited_tick(
+        stopping_tick,
+        current_tick,
+        oracle_tick,
+        price_band_details.band_width,
+        buy,
+        &_get_tick_grid(),
+    )
 }
 
 ///
@@ -1059,6 +1760,14 @@ fn _get_market_details() -> MarketDetails {
     MARKET_DETAILS.with(|ref_market_details| ref_market_details.borrow().get().clone())
 }
 ///
+fn _get_tick_grid() -> TickGrid {
+    MARKET_DETAILS.with(|ref_market_details| ref_market_details.borrow().get().tick_grid)
+}
+///
+fn _get_asset_decimals() -> AssetDecimals {
+    MARKET_DETAILS.with(|ref_market_details| ref_market_details.borrow().get().decimals)
+}
+///
 ///
 fn _get_state_details() -> StateDetails {
     STATE_DETAILS.with(|ref_state_detaills| *ref_state_detaills.borrow().get())
@@ -1068,6 +1777,27 @@ fn _update_state_details(new_state: StateDetails) {
     STATE_DETAILS.with(|ref_state_details| ref_state_details.borrow_mut().set(new_state).unwrap());
 }
 
+///
+fn _get_open_interest_limits() -> OpenInterestLimits {
+    OPEN_INTEREST_LIMITS.with_borrow(|reference| *reference.get())
+}
+///
+fn _get_price_band_details() -> PriceBandDetails {
+    PRICE_BAND_DETAILS.with_borrow(|reference| *reference.get())
+}
+///
+fn _get_risk_tiers() -> RiskTiers {
+    RISK_TIERS.with_borrow(|reference| reference.get().clone())
+}
+
+fn _get_oracle_details() -> OracleDetails {
+    ORACLE_DETAILS.with_borrow(|reference| *reference.get())
+}
+///
+fn _get_funding_rate_tracker() -> FundingRateTracker {
+    FUNDING_RATE_TRACKER.with_borrow(|reference| *reference.get())
+}
+
 /// Get Account Position
 ///
 /// Returns Account Position or Panics if account has no position
@@ -1104,31 +1834,35 @@ fn _insert_account_error_log(account: Subaccount, error_log: PositionUpdateError
     ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.insert(account, error_log));
 }
 
+/// Update Account Error Reason
+///
+/// Records the reject reason of the last failed attempt in an account's error log
+fn _update_account_error_reason(account: &Subaccount, reason: String) {
+    ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| {
+        if let Some(mut error_log) = reference.get(account) {
+            error_log.reason = Some(reason);
+            reference.insert(*account, error_log);
+        }
+    });
+}
+
 fn _remove_account_error_log(account: &Subaccount) {
     ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.remove(account));
 }
 ///
-/// Has No Position Or Pending Error Log
-///
-/// This function checks that an acccount currently has no opened position or any pending error log
-fn _has_position_or_pending_error_log(account: &Subaccount) -> bool {
-    ACCOUNTS_POSITION.with_borrow(|reference| reference.contains_key(account))
-        || ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.contains_key(account))
-}
-///
 ///Calculate Position Realised value
 ///
 ///Calculates the Realised value for a position's volume share in a particular market direction,Long or Short   
 ///
 /// Note:This function also adjust's the volume share
-fn _calc_position_realised_val(volume_share: Amount, long: bool) -> Amount {
+fn _calc_position_realised_val(volume_share: Amount, long: bool) -> Result<Amount, MathError> {
     FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
         let mut funding_rate_tracker = tr.get().clone();
         //
-        let value = funding_rate_tracker.remove_volume(volume_share, long);
+        let value = funding_rate_tracker.remove_volume(volume_share, long)?;
         //
         tr.set(funding_rate_tracker).unwrap();
-        value
+        Ok(value)
     })
 }
 
@@ -1136,63 +1870,159 @@ fn _calc_position_realised_val(volume_share: Amount, long: bool) -> Amount {
 /// Calculate Position Volume Share
 ///
 /// Calculates the volume share for a particular poistion volume in a market direction ,Long or Short
-fn _calc_position_volume_share(position_value: Amount, long: bool) -> Amount {
+fn _calc_position_volume_share(position_value: Amount, long: bool) -> Result<Amount, MathError> {
     FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
         let mut funding_rate_tracker = tr.get().clone();
         //
-        let value = funding_rate_tracker.add_volume(position_value, long);
+        let value = funding_rate_tracker.add_volume(position_value, long)?;
         //
         tr.set(funding_rate_tracker).unwrap();
-        value
+        Ok(value)
     })
 }
 ///
-/// Calculate Position PNL
+/// Calculate Position Health
+///
+/// Values a market position at the current tick
 ///
-/// Calculates the current pnl in percentage  for a particular position
-fn _calculate_position_pnl(position: PositionDetails) -> i128 {
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
+/// Note
+///  - Unlike closing a position ,this does not remove the position's volume share from the funding rate tracker
+///  - Short positions are valued in the perp asset and converted to the collateral asset at the current price
+fn _calc_position_health(position: PositionDetails) -> Result<PositionHealth, MathError> {
+    let grid = _get_tick_grid();
+    let decimals = _get_asset_decimals();
+    // position values are rounded down and amounts owed by the position are rounded up
+    let equivalent = |amount: Amount, tick: Tick, buy: bool, rounding: Rounding| {
+        let tick_price = _tick_to_price(tick, &grid);
+        _equivalent(amount, tick_price, buy, &decimals, rounding)
     };
 
     let state_details = _get_state_details();
 
-    let position_realised_value = _calc_position_realised_val(position.volume_share, position.long);
+    let position_realised_value =
+        _get_funding_rate_tracker().volume_share_value(position.volume_share, position.long)?;
 
-    if position.long {
-        let init_position_value = (position.debt_value + position.collateral_value) as i128;
+    let init_position_value = position.debt_value + position.collateral_value;
+
+    let maintenance_margin = _get_risk_tiers()
+        .tier_for(init_position_value)
+        .map_or(0, |tier| tier.maintenance_margin);
+
+    let accrued_funding = position_realised_value as i128 - init_position_value as i128;
 
-        let position_realised_size = equivalent(position_realised_value, position.entry_tick, true);
+    if position.long {
+        let position_realised_size = equivalent(
+            position_realised_value,
+            position.entry_tick,
+            true,
+            Rounding::Down,
+        )?;
 
-        let position_current_value =
-            equivalent(position_realised_size, state_details.current_tick, false) as i128;
+        let position_current_value = equivalent(
+            position_realised_size,
+            state_details.current_tick,
+            false,
+            Rounding::Down,
+        )?;
 
         let fee = _calc_interest(
             position.debt_value,
             position.interest_rate,
             position.timestamp,
-        ) as i128;
+        )?;
+
+        let pnl_value = position_current_value as i128 - fee as i128 - init_position_value as i128;
 
-        return ((position_current_value - fee - init_position_value)
-            * (100 * _ONE_PERCENT as i128))
-            / init_position_value;
+        let equity = position_current_value as i128 - (position.debt_value + fee) as i128;
+
+        let liquidation_price = if position.debt_value == 0 {
+            0
+        } else {
+            _implied_price(
+                position.debt_value + fee,
+                position_realised_size,
+                &decimals,
+                Rounding::Up,
+            )?
+        };
+
+        let margin_ratio = _calc_margin_ratio(equity, position_current_value);
+
+        return Ok(PositionHealth {
+            pnl: (pnl_value * (100 * _ONE_PERCENT as i128))
+                .checked_div(init_position_value as i128)
+                .ok_or(MathError::DivisionByZero)?,
+            pnl_value,
+            accrued_interest: fee,
+            accrued_funding,
+            margin_ratio,
+            liquidation_price,
+            maintenance_margin,
+            below_maintenance: margin_ratio < maintenance_margin,
+        });
     } else {
         let init_position_size = equivalent(
-            position.debt_value + position.collateral_value,
+            init_position_value,
             position.entry_tick,
             true,
-        ) as i128;
+            Rounding::Down,
+        )?;
+
+        let debt_size = equivalent(position.debt_value, position.entry_tick, true, Rounding::Up)?;
 
-        let debt_size = equivalent(position.debt_value, position.entry_tick, true);
+        let fee = _calc_interest(debt_size, position.interest_rate, position.timestamp)?;
 
-        let fee = _calc_interest(debt_size, position.interest_rate, position.timestamp) as i128;
+        let position_current_size = equivalent(
+            position_realised_value,
+            state_details.current_tick,
+            true,
+            Rounding::Down,
+        )?;
+
+        let pnl_size = position_current_size as i128 - fee as i128 - init_position_size as i128;
+
+        let equity_size = position_current_size as i128 - (debt_size + fee) as i128;
+
+        // converts a signed perp asset amount to the collateral asset
+        let size_to_value = |size: i128| -> Result<i128, MathError> {
+            let value = equivalent(
+                size.unsigned_abs(),
+                state_details.current_tick,
+                false,
+                Rounding::Down,
+            )? as i128;
+            if size < 0 {
+                Ok(-value)
+            } else {
+                Ok(value)
+            }
+        };
 
-        let position_current_size =
-            equivalent(position_realised_value, state_details.current_tick, true) as i128;
+        let liquidation_price = if debt_size == 0 {
+            0
+        } else {
+            _implied_price(
+                position_realised_value,
+                debt_size + fee,
+                &decimals,
+                Rounding::Down,
+            )?
+        };
 
-        return ((position_current_size - fee - init_position_size) * (100 * _ONE_PERCENT as i128))
-            / init_position_size;
+        let margin_ratio = _calc_margin_ratio(equity_size, position_current_size);
+
+        return Ok(PositionHealth {
+            pnl: (pnl_size * (100 * _ONE_PERCENT as i128))
+                .checked_div(init_position_size as i128)
+                .ok_or(MathError::DivisionByZero)?,
+            pnl_value: size_to_value(pnl_size)?,
+            accrued_interest: equivalent(fee, state_details.current_tick, false, Rounding::Up)?,
+            accrued_funding,
+            margin_ratio,
+            liquidation_price,
+            maintenance_margin,
+            below_maintenance: margin_ratio < maintenance_margin,
+        });
     }
 }
 
@@ -1200,6 +2030,28 @@ fn _calculate_position_pnl(position: PositionDetails) -> i128 {
 ///
 /// Settles Funding Rate by calling the XRC cansiter .fetching the Price ,calculating the premium and distributing the  fund to the right market direction,Long or Short
 async fn settle_funding_rate() {
+    if let Some(rate_result) = fetch_oracle_rate().await {
+        let state_details = _get_state_details();
+
+        let current_price = _tick_to_price(state_details.current_tick, &_get_tick_grid());
+
+        let perp_price = match 10u128.checked_pow(rate_result.metadata.decimals) {
+            Some(scale) => _mul_div(current_price, scale, _PRICE_DECIMAL, Rounding::Down),
+            None => Err(MathError::Overflow),
+        };
+
+        let spot_price = rate_result.rate as u128;
+
+        if let Ok(perp_price) = perp_price {
+            _settle_funding_rate(perp_price, spot_price);
+        }
+    }
+}
+
+/// Fetch Oracle Rate
+///
+/// Fetches the current exchange rate of the market pair from the XRC canister and records it as the oracle price
+async fn fetch_oracle_rate() -> Option<ExchangeRate> {
     let market_details = _get_market_details();
 
     let xrc = XRC::init(market_details.xrc_id);
@@ -1212,32 +2064,131 @@ async fn settle_funding_rate() {
 
     match xrc._get_exchange_rate(request).await {
         Ok(rate_result) => {
-            let state_details = _get_state_details();
+            let oracle_price = match 10u128.checked_pow(rate_result.metadata.decimals) {
+                Some(scale) => _mul_div(
+                    rate_result.rate as u128,
+                    _PRICE_DECIMAL,
+                    scale,
+                    Rounding::Down,
+                ),
+                None => Err(MathError::Overflow),
+            };
+
+            match oracle_price {
+                Ok(oracle_price) => {
+                    _update_oracle_tick(_price_to_tick(oracle_price, &market_details.tick_grid));
+                    Some(rate_result)
+                }
+                Err(_) => None,
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+/// Update Oracle Tick
+///
+/// Records the oracle tick and restricts the market to reduce only (circuit breaker) if the current tick has diverged from the oracle tick
+/// beyond the divergence threshold for longer than the max divergence duration
+///
+/// Note
+///  - The circuit breaker only blocks new exposure ,positions can still be closed ,a paused market is left paused
+fn _update_oracle_tick(oracle_tick: Tick) {
+    let price_band_details = _get_price_band_details();
 
-            let current_price = _tick_to_price(state_details.current_tick);
+    let mut state_details = _get_state_details();
 
-            let perp_price =
-                (current_price * 10u128.pow(rate_result.metadata.decimals)) / _BASE_PRICE;
+    let now = ic_cdk::api::time();
 
-            let spot_price = rate_result.rate as u128;
+    let divergence = _tick_divergence(state_details.current_tick, oracle_tick, &_get_tick_grid());
 
-            _settle_funding_rate(perp_price, spot_price);
-        }
-        Err(_) => {
-            return;
-        }
+    let diverged = price_band_details.divergence_threshold != 0
+        && divergence > price_band_details.divergence_threshold;
+
+    let mut oracle_details = _get_oracle_details();
+
+    let sustained = oracle_details.update(
+        oracle_tick,
+        diverged,
+        price_band_details.max_divergence_duration,
+        now,
+    );
+
+    if sustained
+        && state_details.mode != MarketMode::ReduceOnly
+        && state_details.mode != MarketMode::Paused
+    {
+        state_details.mode = MarketMode::ReduceOnly;
+        _update_state_details(state_details);
+
+        let event = CircuitBreakerEvent {
+            timestamp: now,
+            oracle_tick,
+            current_tick: state_details.current_tick,
+            divergence,
+            divergence_start: oracle_details.divergence_start.unwrap_or(now),
+        };
+        CIRCUIT_BREAKER_EVENTS.with_borrow_mut(|reference| reference.insert(now, event));
     }
+
+    ORACLE_DETAILS.with_borrow_mut(|reference| reference.set(oracle_details).unwrap());
 }
 
+/// Settle Funding Rate (Private)
+///
+/// Note
+///  - Funding is skipped for the interval if the amount to settle can not be calculated
 fn _settle_funding_rate(perp_price: u128, spot_price: u128) {
     let funding_rate = _calculate_funding_rate_premium(perp_price, spot_price);
-    FUNDING_RATE_TRACKER.with_borrow_mut(|reference| {
-        let mut funding_rate_tracker = reference.get().clone();
 
-        funding_rate_tracker.settle_funding_rate(funding_rate.abs() as u64, funding_rate > 0);
+    let mut funding_rate_tracker = _get_funding_rate_tracker();
 
-        reference.set(funding_rate_tracker).unwrap();
-    })
+    if funding_rate_tracker
+        .settle_funding_rate(funding_rate.abs() as u64, funding_rate > 0)
+        .is_err()
+    {
+        return;
+    }
+
+    FUNDING_RATE_TRACKER.with_borrow_mut(|reference| reference.set(funding_rate_tracker).unwrap());
+
+    _record_event(
+        None,
+        MarketEventKind::FundingSettled {
+            funding_rate: funding_rate.unsigned_abs(),
+            long_pays: funding_rate > 0,
+            net_volume_long: funding_rate_tracker.net_volume_long,
+            net_volume_short: funding_rate_tracker.net_volume_short,
+        },
+    );
+}
+
+/// Record Event
+///
+/// Appends an event to the market event log and indexes it by account if any
+///
+/// Returns
+///  - Sequence :The sequence of the event ,also utilised as the operation id of the vault update caused by the event
+fn _record_event(account: Option<Subaccount>, kind: MarketEventKind) -> u64 {
+    let sequence = MARKET_EVENTS.with_borrow_mut(|reference| {
+        let sequence = reference.len();
+        reference.insert(
+            sequence,
+            MarketEvent {
+                sequence,
+                timestamp: ic_cdk::api::time(),
+                account,
+                kind,
+            },
+        );
+        sequence
+    });
+
+    if let Some(account) = account {
+        ACCOUNT_EVENTS.with_borrow_mut(|reference| reference.insert((account, sequence), ()));
+    }
+
+    return sequence;
 }
 
 fn _calculate_funding_rate_premium(perp_price: u128, spot_price: u128) -> i64 {
@@ -1249,54 +2200,407 @@ fn _calculate_funding_rate_premium(perp_price: u128, spot_price: u128) -> i64 {
 /////////////////////////////////////////
 /// System Functions
 ////////////////////////////////////////
-#[ic_cdk::pre_upgrade]
-fn pre_upgrade() {
-    let multiplier_bitmaps =
-        INTEGRAL_BITMAPS.with_borrow(|ref_mul_bitmaps| ref_mul_bitmaps.clone());
-    //
-    let ticks_details = TICKS_DETAILS.with_borrow(|ref_ticks_details| ref_ticks_details.clone());
-    //
-    storage::stable_save((multiplier_bitmaps, ticks_details)).expect("error storing data");
-}
 
+/// Post Upgrade
+///
+/// Copies the order book of a market upgraded from a version that kept the book on the heap into the stable book
+///
+/// Params
+///  - Market Details :The market details to set ,only utilised when upgrading from such a version
+///
+/// Note
+///  - Such a version saved the book with stable_save at the start of the stable memory in its pre upgrade ,so the book
+///  must be restored before the memory manager is initialised
+///  - The saved book overwrites the memory manager's header ,so the other stable state of such a version is
+///  reinitialised and the market details must be passed again ,the caller becomes the owner as in init
 #[ic_cdk::post_upgrade]
-fn post_upgrade() {
-    let multiplier_bitmaps: HashMap<u64, u128>;
+fn post_upgrade(market_details: Option<MarketDetails>) {
+    if let Some((integrals_bitmaps, ticks_details)) = _restore_heap_book() {
+        match market_details {
+            Some(market_details) => init(market_details),
+            None => ic_cdk::trap("Market details are required to upgrade from a heap book"),
+        }
 
-    let ticks_details: HashMap<Tick, TickDetails>;
-    (multiplier_bitmaps, ticks_details) = storage::stable_restore().unwrap();
-    INTEGRAL_BITMAPS.with(|ref_mul_bitmaps| *ref_mul_bitmaps.borrow_mut() = multiplier_bitmaps);
+        INTEGRAL_BITMAPS.with_borrow_mut(|reference| {
+            for (integral, bitmap) in integrals_bitmaps {
+                reference.insert(integral, bitmap);
+            }
+        });
+        TICKS_DETAILS.with_borrow_mut(|reference| {
+            for (tick, tick_details) in ticks_details {
+                reference.insert(tick, tick_details);
+            }
+        });
+    }
 
-    TICKS_DETAILS.with(|ref_ticks_details| {
-        *ref_ticks_details.borrow_mut() = ticks_details;
-    })
+    _migrate_account_error_logs();
+}
+
+/// Migrate Account Error Logs
+///
+/// Moves the account error logs recorded before vault updates carried an operation id to the account error logs
+///
+/// Note
+///  - Each migrated log is given the sequence of a LegacyPositionUpdate event as its operation id
+fn _migrate_account_error_logs() {
+    let legacy_logs: Vec<(Subaccount, LegacyPositionUpdateErrorLog)> =
+        LEGACY_ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.iter().collect());
+
+    for (account, legacy_log) in legacy_logs {
+        let operation_id = _record_event(
+            Some(account),
+            MarketEventKind::LegacyPositionUpdate {
+                profit: legacy_log.profit,
+            },
+        );
+
+        let debt_params = ManageDebtParams::init(
+            legacy_log.debt_params.new_debt,
+            legacy_log.debt_params.initial_debt,
+            legacy_log.debt_params.interest_received,
+        );
+
+        _insert_account_error_log(
+            account,
+            PositionUpdateErrorLog {
+                operation_id,
+                user: legacy_log.user,
+                profit: legacy_log.profit,
+                debt_params,
+                reason: None,
+                position: None,
+            },
+        );
+
+        LEGACY_ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.remove(&account));
+    }
+}
+
+/// Restore Heap Book
+///
+/// Returns the integral bitmaps and ticks details saved by stable_save ,None if the stable memory does not start with
+/// a candid encoded book i.e it is already managed by the memory manager
+fn _restore_heap_book() -> Option<(HashMap<u64, u128>, HashMap<Tick, TickDetails>)> {
+    if stable::stable64_size() == 0 {
+        return None;
+    }
+
+    let mut magic = [0u8; 4];
+    stable::stable64_read(0, &mut magic);
+    if &magic != b"DIDL" {
+        return None;
+    }
+
+    storage::stable_restore().ok()
 }
 
 //////////////////////////////////////////
 /// Admin Functions
 /////////////////////////////////////////
 ///
-fn admin_guard() -> Result<(), String> {
-    ADMIN.with_borrow(|admin_ref| {
-        let admin = admin_ref.get();
-        if ic_cdk::caller() == admin.principal_id {
-            return Ok(());
+fn owner_guard() -> Result<(), String> {
+    if ic_cdk::caller() == _get_owner() {
+        return Ok(());
+    }
+    return Err("Caller is not the owner".to_string());
+}
+
+fn risk_manager_guard() -> Result<(), String> {
+    _role_guard(Role::RiskManager)
+}
+
+fn pauser_guard() -> Result<(), String> {
+    _role_guard(Role::Pauser)
+}
+
+fn oracle_operator_guard() -> Result<(), String> {
+    _role_guard(Role::OracleOperator)
+}
+
+/// Allows the owner and principals granted the role
+fn _role_guard(role: Role) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == _get_owner()
+        || ROLE_MEMBERS.with_borrow(|reference| reference.get().has_role(caller, role))
+    {
+        return Ok(());
+    }
+    return Err(format!("Caller does not have the {:?} role", role));
+}
+
+fn _get_owner() -> Principal {
+    ADMIN.with_borrow(|reference| reference.get().principal_id)
+}
+
+/// Get Roles
+///
+/// Returns the owner ,the pending owner if any and the principals holding each role
+#[ic_cdk::query(name = "getRoles")]
+fn get_roles() -> RoleDetails {
+    let pending_owner = PENDING_OWNER.with_borrow(|reference| reference.get().principal_id);
+    RoleDetails {
+        owner: _get_owner(),
+        pending_owner: if pending_owner == Principal::anonymous() {
+            None
         } else {
-            return Err("Invalid".to_string());
-        };
-    })
+            Some(pending_owner)
+        },
+        members: ROLE_MEMBERS.with_borrow(|reference| reference.get().members.clone()),
+    }
+}
+
+/// Grant Role
+///
+/// Grants a role to a principal ,the change is recorded in the market event log
+#[ic_cdk::update(guard = "owner_guard", name = "grantRole")]
+fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    let mut role_members = ROLE_MEMBERS.with_borrow(|reference| reference.get().clone());
+
+    if role_members.members.len() >= MAX_ROLE_MEMBERS {
+        return Err("Max role members reached".to_string());
+    }
+
+    if role_members.grant(principal, role) {
+        ROLE_MEMBERS.with_borrow_mut(|reference| reference.set(role_members).unwrap());
+        _record_event(None, MarketEventKind::RoleGranted { principal, role });
+    }
+
+    return Ok(());
 }
 
-#[ic_cdk::update(guard = "admin_guard", name = "updateStateDetails")]
-async fn update_state_details(new_state_details: StateDetails) {
+/// Revoke Role
+///
+/// Revokes a role from a principal ,the change is recorded in the market event log
+#[ic_cdk::update(guard = "owner_guard", name = "revokeRole")]
+fn revoke_role(principal: Principal, role: Role) {
+    let mut role_members = ROLE_MEMBERS.with_borrow(|reference| reference.get().clone());
+
+    if role_members.revoke(principal, role) {
+        ROLE_MEMBERS.with_borrow_mut(|reference| reference.set(role_members).unwrap());
+        _record_event(None, MarketEventKind::RoleRevoked { principal, role });
+    }
+}
+
+/// Propose Owner
+///
+/// First step of transferring ownership ,the new owner has to accept the transfer with acceptOwnership
+///
+/// Note
+///  - Proposing another principal replaces the pending owner
+#[ic_cdk::update(guard = "owner_guard", name = "proposeOwner")]
+fn propose_owner(new_owner: Principal) {
+    PENDING_OWNER.with_borrow_mut(|reference| reference.set(ID::from(new_owner)).unwrap());
+    _record_event(None, MarketEventKind::OwnershipProposed { new_owner });
+}
+
+/// Accept Ownership
+///
+/// Second step of transferring ownership ,called by the pending owner
+#[ic_cdk::update(name = "acceptOwnership")]
+fn accept_ownership() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    let pending_owner = PENDING_OWNER.with_borrow(|reference| reference.get().principal_id);
+
+    if pending_owner == Principal::anonymous() || caller != pending_owner {
+        return Err("Caller is not the pending owner".to_string());
+    }
+
+    let previous_owner = _get_owner();
+
+    ADMIN.with_borrow_mut(|reference| reference.set(ID::from(caller)).unwrap());
+    PENDING_OWNER
+        .with_borrow_mut(|reference| reference.set(ID::from(Principal::anonymous())).unwrap());
+
+    _record_event(
+        None,
+        MarketEventKind::OwnershipTransferred {
+            previous_owner,
+            new_owner: caller,
+        },
+    );
+
+    return Ok(());
+}
+
+/// Set Market Mode
+///
+/// Sets the trading actions the market allows without affecting the rest of the state details
+///
+/// Note
+///  - Changing the mode is exempt from the parameter change delay so the market can be halted in an emergency
+#[ic_cdk::update(guard = "pauser_guard", name = "setMarketMode")]
+fn set_market_mode(mode: MarketMode) {
+    let mut state_details = _get_state_details();
+    state_details.mode = mode;
+    _update_state_details(state_details);
+
+    _record_event(None, MarketEventKind::MarketModeChanged { mode });
+}
+
+/// Update State Details
+///
+/// Sets the initial state details of the market
+///
+/// Note
+///  - Once set ,state details can only be changed field by field through timelocked parameter proposals
+///  - Each field is validated as its parameter would be (see StateParameter::validate)
+#[ic_cdk::update(guard = "owner_guard", name = "updateStateDetails")]
+async fn update_state_details(new_state_details: StateDetails) -> Result<(), String> {
+    if _get_state_details() != StateDetails::default() {
+        return Err("State details already set ,propose a parameter change".to_string());
+    }
+    let grid = _get_tick_grid();
+    for parameter in StateParameter::all(&new_state_details) {
+        parameter.validate(&grid)?;
+    }
     _update_state_details(new_state_details);
+    return Ok(());
+}
+
+/// Get Pending Parameter Changes
+///
+/// Returns all proposed state parameter changes that have not been executed or cancelled
+#[ic_cdk::query(name = "getPendingParameterChanges")]
+fn get_pending_parameter_changes() -> Vec<ParameterProposal> {
+    PARAMETER_PROPOSALS.with_borrow(|reference| reference.iter().map(|(_, p)| p).collect())
 }
 
-#[ic_cdk::update(guard = "admin_guard", name = "startTimer")]
+/// Propose Parameter Change
+///
+/// Proposes a change of a single state parameter that can be executed after PARAMETER_CHANGE_DELAY
+///
+/// Returns
+///  - Id :The id of the proposal
+///
+/// Note
+///  - The parameter is validated when proposed and again when executed
+#[ic_cdk::update(guard = "risk_manager_guard", name = "proposeParameterChange")]
+fn propose_parameter_change(parameter: StateParameter) -> Result<u64, String> {
+    parameter.validate(&_get_tick_grid())?;
+
+    if PARAMETER_PROPOSALS.with_borrow(|reference| reference.len()) >= MAX_PENDING_PROPOSALS {
+        return Err("Max pending parameter changes reached".to_string());
+    }
+
+    let id = PROPOSAL_COUNTER.with_borrow_mut(|reference| {
+        let id = *reference.get();
+        reference.set(id + 1).unwrap();
+        id
+    });
+
+    let now = ic_cdk::api::time();
+
+    let proposal = ParameterProposal {
+        id,
+        parameter,
+        proposer: ic_cdk::caller(),
+        proposed_at: now,
+        executable_at: now + PARAMETER_CHANGE_DELAY,
+    };
+
+    PARAMETER_PROPOSALS.with_borrow_mut(|reference| reference.insert(id, proposal));
+
+    _record_event(
+        None,
+        MarketEventKind::ParameterChangeProposed { id, parameter },
+    );
+
+    return Ok(id);
+}
+
+/// Cancel Parameter Change
+///
+/// Cancels a pending state parameter change before it is executed
+#[ic_cdk::update(guard = "risk_manager_guard", name = "cancelParameterChange")]
+fn cancel_parameter_change(id: u64) -> Result<(), String> {
+    if PARAMETER_PROPOSALS
+        .with_borrow_mut(|reference| reference.remove(&id))
+        .is_none()
+    {
+        return Err("Proposal not found".to_string());
+    }
+
+    _record_event(None, MarketEventKind::ParameterChangeCancelled { id });
+
+    return Ok(());
+}
+
+/// Execute Parameter Change
+///
+/// Applies a pending state parameter change once its delay has passed
+#[ic_cdk::update(guard = "risk_manager_guard", name = "executeParameterChange")]
+fn execute_parameter_change(id: u64) -> Result<(), String> {
+    let proposal = PARAMETER_PROPOSALS
+        .with_borrow(|reference| reference.get(&id))
+        .ok_or("Proposal not found".to_string())?;
+
+    if ic_cdk::api::time() < proposal.executable_at {
+        return Err("Parameter change delay has not passed".to_string());
+    }
+
+    proposal.parameter.validate(&_get_tick_grid())?;
+
+    PARAMETER_PROPOSALS.with_borrow_mut(|reference| reference.remove(&id));
+
+    let mut state_details = _get_state_details();
+    proposal.parameter.apply(&mut state_details);
+    _update_state_details(state_details);
+
+    _record_event(
+        None,
+        MarketEventKind::ParameterChangeExecuted {
+            id,
+            parameter: proposal.parameter,
+        },
+    );
+
+    return Ok(());
+}
+
+/// Update Open Interest Limits
+///
+/// Updates the open interest limits without affecting the rest of the state details
+#[ic_cdk::update(guard = "risk_manager_guard", name = "updateOpenInterestLimits")]
+async fn update_open_interest_limits(new_limits: OpenInterestLimits) {
+    OPEN_INTEREST_LIMITS.with_borrow_mut(|reference| reference.set(new_limits).unwrap());
+}
+
+/// Update Risk Tiers
+///
+/// Replaces the risk tiers ,tiers must be ordered by increasing max notional
+///
+/// Note
+///  - Tiers only apply to positions opened afterwards ,existing positions keep their leverage but are measured against the new maintenance margins
+#[ic_cdk::update(guard = "risk_manager_guard", name = "updateRiskTiers")]
+fn update_risk_tiers(tiers: Vec<RiskTier>) -> Result<(), String> {
+    let risk_tiers = RiskTiers { tiers };
+    risk_tiers.validate()?;
+    RISK_TIERS.with_borrow_mut(|reference| reference.set(risk_tiers).unwrap());
+    return Ok(());
+}
+
+/// Update Price Band Details
+///
+/// Updates the oracle price band and circuit breaker parameters
+#[ic_cdk::update(guard = "oracle_operator_guard", name = "updatePriceBandDetails")]
+async fn update_price_band_details(new_details: PriceBandDetails) {
+    PRICE_BAND_DETAILS.with_borrow_mut(|reference| reference.set(new_details).unwrap());
+}
+
+#[ic_cdk::update(guard = "owner_guard", name = "startTimer")]
 async fn start_timer() {
     ic_cdk_timers::set_timer_interval(Duration::from_secs(3600), || {
         ic_cdk::spawn(async { settle_funding_rate().await });
     });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(PRICE_CHECK_INTERVAL), || {
+        ic_cdk::spawn(async {
+            fetch_oracle_rate().await;
+        });
+    });
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(RETRY_INTERVAL), || {
+        ic_cdk::spawn(async { _process_retry_queue().await });
+    });
 }
 
 /////////////////////////
@@ -1314,42 +2618,495 @@ fn trusted_canister_guard() -> Result<(), String> {
     }
 }
 
-#[ic_cdk::update(name = "retryError")]
-async fn retry_error(index: usize) {
-    let error = ERRORS.with_borrow(|reference| reference.get(index).unwrap().clone());
+/// Get Retry Queue
+///
+/// Returns all failed notifications still being retried and those moved to the dead letters
 
-    let details = _get_market_details();
-    //
-    match error {
-        ErrorType::ExecuteTicksOrderError(err) => err.retry(details),
-        ErrorType::StoreTickOrderError(err) => err.retry(details),
-        ErrorType::RemoveTickOrderError(err) => err.retry(details),
+#[ic_cdk::query(name = "getRetryQueue")]
+fn get_retry_queue() -> RetryQueue {
+    RetryQueue {
+        pending: RETRY_QUEUE.with_borrow(|reference| reference.iter().map(|(_, e)| e).collect()),
+        dead_letters: DEAD_LETTERS
+            .with_borrow(|reference| reference.iter().map(|(_, e)| e).collect()),
+    }
+}
+
+/// Retry Error
+///
+/// Retries a particular failed notification immediately ,moving it back to the retry queue if it was a dead letter
+///
+/// Returns
+///  - Err if there is no pending notification or dead letter with the id
+
+#[ic_cdk::update(name = "retryError")]
+async fn retry_error(id: u64) -> Result<(), String> {
+    let entry = match _take_dead_letter(id) {
+        Some(entry) => entry,
+        None => match RETRY_QUEUE.with_borrow(|reference| reference.get(&id)) {
+            Some(entry) => entry,
+            None => return Err(format!("No failed notification with id {}", id)),
+        },
     };
+
+    _retry_entry(entry, &_get_market_details()).await;
+
+    return Ok(());
 }
 
+/// Retry Account Error
+///
+/// Retries the failed position updates of a particular account immediately
+
 #[ic_cdk::update(name = "retryAccountError")]
 async fn retry_account_error(user: Principal) {
     let account = user._to_subaccount();
 
-    let account_error_log = _get_account_error_log(&account);
+    let is_account_update =
+        |entry: &RetryEntry| entry.notification.position_update_account() == Some(account);
+
+    let mut entries: Vec<RetryEntry> = RETRY_QUEUE.with_borrow(|reference| {
+        reference
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(is_account_update)
+            .collect()
+    });
+
+    let dead_letters: Vec<u64> = DEAD_LETTERS.with_borrow(|reference| {
+        reference
+            .iter()
+            .filter(|(_, entry)| is_account_update(entry))
+            .map(|(id, _)| id)
+            .collect()
+    });
+    for id in dead_letters {
+        entries.extend(_take_dead_letter(id));
+    }
+
+    // error logs recorded before the retry queue have no entry
+    if entries.is_empty() {
+        if let Some(error_log) =
+            ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.get(&account))
+        {
+            entries.push(_enqueue_notification(Notification::ManagePositionUpdate {
+                operation_id: error_log.operation_id,
+                user: error_log.user,
+                profit: error_log.profit,
+                debt_params: error_log.debt_params,
+            }));
+        }
+    }
 
     let details = _get_market_details();
-    account_error_log.retry(details);
+    for entry in entries {
+        _retry_entry(entry, &details).await;
+    }
+}
+
+/// Get Account Error Log
+///
+/// Returns the pending position update of an account ,None if the account has no pending update
+#[ic_cdk::query(name = "getAccountErrorLog")]
+fn get_account_error_log(account: Subaccount) -> Option<PositionUpdateErrorLog> {
+    ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.get(&account))
+}
+
+/// Amend Account Error
+///
+/// Replaces the profit and debt params of an account's pending position update and retries it immediately
+///
+/// Returns
+///  - Err if the account has no pending position update
+///
+/// Note
+///  - Utilised for updates the vault keeps rejecting ,e.g repaying more debt than the vault holds for the market ,the
+///  position before the update is kept in the account's error log (see getAccountErrorLog) to work out the amended update
+///  - The update keeps its operation id ,the vault has not applied a rejected update
+#[ic_cdk::update(guard = "risk_manager_guard", name = "amendAccountError")]
+async fn amend_account_error(
+    user: Principal,
+    profit: Amount,
+    debt_params: ManageDebtParams,
+) -> Result<(), String> {
+    let account = user._to_subaccount();
+
+    let mut error_log = ACCOUNTS_ERROR_LOGS
+        .with_borrow(|reference| reference.get(&account))
+        .ok_or("Account has no pending position update".to_string())?;
+
+    // the previous update is replaced by the amended one
+    let is_account_update =
+        |entry: &RetryEntry| entry.notification.position_update_account() == Some(account);
+    RETRY_QUEUE.with_borrow_mut(|reference| _remove_entries(reference, is_account_update));
+    DEAD_LETTERS.with_borrow_mut(|reference| _remove_entries(reference, is_account_update));
+
+    error_log.profit = profit;
+    error_log.debt_params = debt_params;
+    _insert_account_error_log(account, error_log.clone());
+
+    let entry = _enqueue_notification(Notification::ManagePositionUpdate {
+        operation_id: error_log.operation_id,
+        user,
+        profit,
+        debt_params,
+    });
+
+    _retry_entry(entry, &_get_market_details()).await;
+
+    return Ok(());
 }
 
+/// Success Notification
+///
+/// Confirms that a failed notification has been executed by the receiving canister
+///
+/// Params
+///  - Account :The account of the position update ,utilised by the vault
+///  - Id :The id of the failed notification ,utilised by the watcher
 #[ic_cdk::update(name = "successNotification", guard = "trusted_canister_guard")]
-async fn success_notif(account: Subaccount, error_index: usize) {
+async fn success_notif(account: Subaccount, id: u64) {
     let market_details = _get_market_details();
 
     let caller = ic_cdk::caller();
 
     if caller == market_details.vault_id {
         _remove_account_error_log(&account);
+
+        let is_account_update =
+            |entry: &RetryEntry| entry.notification.position_update_account() == Some(account);
+        RETRY_QUEUE.with_borrow_mut(|reference| _remove_entries(reference, is_account_update));
+        DEAD_LETTERS.with_borrow_mut(|reference| _remove_entries(reference, is_account_update));
         return;
     }
 
     if caller == market_details.watcher_id {
-        ERRORS.with_borrow_mut(|reference| reference.remove(error_index));
+        RETRY_QUEUE.with_borrow_mut(|reference| reference.remove(&id));
+        DEAD_LETTERS.with_borrow_mut(|reference| reference.remove(&id));
+    }
+}
+
+/// Returns a new operation id for a vault reservation
+fn _next_reservation_id() -> u64 {
+    RESERVATION_COUNTER.with_borrow_mut(|reference| {
+        let id = *reference.get();
+        reference.set(id + 1).unwrap();
+        id
+    })
+}
+
+/// Enqueue Notification
+///
+/// Adds a failed notification to the retry queue to be retried by the retry timer
+///
+/// Note
+///  - Execute ticks orders notifications with more than MAX_NOTIFICATION_TICKS ticks are split into multiple entries
+fn _enqueue_notification(notification: Notification) -> RetryEntry {
+    if let Notification::ExecuteTicksOrders { ticks } = &notification {
+        if ticks.len() > MAX_NOTIFICATION_TICKS {
+            let mut chunks = ticks.chunks(MAX_NOTIFICATION_TICKS);
+            let first = chunks.next().unwrap().to_vec();
+            for chunk in chunks {
+                _enqueue_notification(Notification::ExecuteTicksOrders {
+                    ticks: chunk.to_vec(),
+                });
+            }
+            return _enqueue_notification(Notification::ExecuteTicksOrders { ticks: first });
+        }
+    }
+
+    let id = RETRY_COUNTER.with_borrow_mut(|reference| {
+        let id = *reference.get();
+        reference.set(id + 1).unwrap();
+        id
+    });
+
+    let entry = RetryEntry {
+        id,
+        notification,
+        attempts: 0,
+        next_attempt: ic_cdk::api::time(),
+        last_error: String::new(),
+    };
+
+    RETRY_QUEUE.with_borrow_mut(|reference| reference.insert(id, entry.clone()));
+
+    return entry;
+}
+
+/// Process Retry Queue
+///
+/// Retries every failed notification whose next attempt is due ,up to MAX_RETRIES_PER_ROUND per round
+async fn _process_retry_queue() {
+    let now = ic_cdk::api::time();
+
+    let due: Vec<RetryEntry> = RETRY_QUEUE.with_borrow(|reference| {
+        reference
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.next_attempt <= now)
+            .take(MAX_RETRIES_PER_ROUND)
+            .collect()
+    });
+
+    let details = _get_market_details();
+    for entry in due {
+        _retry_entry(entry, &details).await;
+    }
+
+    // positions not fully closed by their last unwind
+    let unwinding: Vec<Subaccount> = PENDING_COMMITS.with_borrow(|reference| {
+        reference
+            .iter()
+            .filter(|(_, pending_commit)| pending_commit.unwinding)
+            .map(|(account, _)| account)
+            .take(MAX_RETRIES_PER_ROUND)
+            .collect()
+    });
+
+    for account in unwinding {
+        _unwind_position(account).await;
+    }
+}
+
+/// Settle Commit
+///
+/// Unlocks the account once the vault commits the reservation of its position ,or unwinds the position if the vault
+/// rejects the commit
+async fn _settle_commit(account: Subaccount, committed: bool) {
+    if committed {
+        PENDING_COMMITS.with_borrow_mut(|reference| reference.remove(&account));
+        return;
+    }
+
+    PENDING_COMMITS.with_borrow_mut(|reference| {
+        if let Some(mut pending_commit) = reference.get(&account) {
+            pending_commit.unwinding = true;
+            reference.insert(account, pending_commit);
+        }
+    });
+
+    _unwind_position(account).await;
+}
+
+/// Unwind Position
+///
+/// Closes a position whose commit the vault rejected ,the vault has refunded its collateral and holds none of its debt
+///
+/// Note
+///  - The owner receives nothing ,the difference between the value returned by the close and the entry value of the
+///  position is booked by the vault as interest received or as bad debt
+///  - A position that is not fully closed stays locked and the rest is unwound in the next retry round
+///  - Nothing is unwound while the account has a pending position update
+async fn _unwind_position(account: Subaccount) {
+    let mut pending_commit = match PENDING_COMMITS.with_borrow(|reference| reference.get(&account))
+    {
+        Some(pending_commit) if pending_commit.unwinding => pending_commit,
+        _ => return,
+    };
+
+    if ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.contains_key(&account)) {
+        return;
+    }
+
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&account)) {
+        Some(position) => position,
+        None => {
+            PENDING_COMMITS.with_borrow_mut(|reference| reference.remove(&account));
+            return;
+        }
+    };
+
+    let initial_position = position;
+
+    let market_details = _get_market_details();
+
+    let watcher = Watcher::init(market_details.watcher_id);
+
+    let mut state_details = _get_state_details();
+
+    let close_result = match position.order_type {
+        PositionOrderType::Market => {
+            let current_tick = state_details.current_tick;
+
+            let stopping_tick = _price_band_stopping_tick(
+                max_or_default_max(None, current_tick, !position.long),
+                current_tick,
+                !position.long,
+            );
+
+            let close_result = if position.long {
+                _close_market_long_position(account, &mut position, current_tick, stopping_tick)
+            } else {
+                _close_market_short_position(account, &mut position, current_tick, stopping_tick)
+            };
+
+            match close_result {
+                Ok((amount_out, resulting_tick, crossed_ticks, manage_debt_params)) => {
+                    state_details.current_tick = resulting_tick;
+                    _update_state_details(state_details);
+
+                    watcher.execute_ticks_orders(crossed_ticks);
+
+                    Ok((amount_out, manage_debt_params))
+                }
+                Err(error) => Err(error),
+            }
+        }
+        PositionOrderType::Limit(_) => {
+            let close_result = if position.long {
+                _close_limit_long_position(account, &mut position)
+            } else {
+                _close_limit_short_position(account, &mut position)
+            };
+
+            if let Ok((_, manage_debt_params)) = close_result {
+                if manage_debt_params.new_debt == 0 {
+                    watcher.remove_tick_order(position.entry_tick, account)
+                }
+            }
+
+            close_result
+        }
+    };
+
+    // the position is left unchanged and unwound in the next retry round
+    let (amount_out, manage_debt_params) = match close_result {
+        Ok(result) => result,
+        Err(_) => return,
+    };
+
+    // the vault holds neither the collateral nor the debt of the position ,so the value returned by the close is
+    // netted against the entry value
+    let returned_value = amount_out
+        .saturating_add(
+            manage_debt_params
+                .initial_debt
+                .saturating_sub(manage_debt_params.new_debt),
+        )
+        .saturating_add(manage_debt_params.interest_received);
+    let deducted_value = manage_debt_params
+        .bad_debt
+        .saturating_add(pending_commit.entry_value);
+
+    let unwind_params = ManageDebtParams {
+        new_debt: 0,
+        initial_debt: 0,
+        interest_received: returned_value.saturating_sub(deducted_value),
+        bad_debt: deducted_value.saturating_sub(returned_value),
+    };
+
+    if ACCOUNTS_POSITION.with_borrow(|reference| reference.contains_key(&account)) {
+        pending_commit.entry_value = 0;
+        PENDING_COMMITS.with_borrow_mut(|reference| reference.insert(account, pending_commit));
+    } else {
+        PENDING_COMMITS.with_borrow_mut(|reference| reference.remove(&account));
+    }
+
+    let sequence = _record_event(
+        Some(account),
+        MarketEventKind::PositionUnwound {
+            long: position.long,
+            returned_value,
+            resulting_tick: state_details.current_tick,
+        },
+    );
+
+    let vault = Vault::init(market_details.vault_id);
+
+    // a failed update is retried from the retry queue
+    let _ = vault
+        .manage_position_update(
+            sequence,
+            pending_commit.user,
+            0,
+            unwind_params,
+            Some(initial_position),
+        )
+        .await;
+}
+
+/// Retry Entry
+///
+/// Sends a failed notification and waits for it to be executed
+///
+/// Note
+///  - The next attempt is scheduled with an exponential backoff before the call is made ,so the entry is not retried concurrently
+///  - The entry is removed if the call succeeds or moved to the dead letters once it reaches MAX_RETRY_ATTEMPTS
+async fn _retry_entry(mut entry: RetryEntry, details: &MarketDetails) {
+    entry.attempts += 1;
+    entry.next_attempt =
+        ic_cdk::api::time() + _calc_backoff(RETRY_BASE_DELAY, entry.attempts, MAX_RETRY_DELAY);
+
+    RETRY_QUEUE.with_borrow_mut(|reference| reference.insert(entry.id, entry.clone()));
+
+    let result = entry.notification.send(details).await;
+
+    // entry could have been confirmed by a success notification while waiting
+    if !RETRY_QUEUE.with_borrow(|reference| reference.contains_key(&entry.id)) {
+        return;
+    }
+
+    match result {
+        Ok(()) => {
+            RETRY_QUEUE.with_borrow_mut(|reference| reference.remove(&entry.id));
+
+            if let Some(account) = entry.notification.position_update_account() {
+                _remove_account_error_log(&account);
+            }
+        }
+        Err(error) => {
+            entry.last_error = _truncate_error(error);
+
+            if let Some(account) = entry.notification.position_update_account() {
+                _update_account_error_reason(&account, entry.last_error.clone());
+            }
+
+            RETRY_QUEUE.with_borrow_mut(|reference| {
+                if entry.attempts >= MAX_RETRY_ATTEMPTS {
+                    reference.remove(&entry.id);
+                    DEAD_LETTERS.with_borrow_mut(|dead_letters| {
+                        dead_letters.insert(entry.id, entry.clone())
+                    });
+                } else {
+                    reference.insert(entry.id, entry.clone());
+                }
+            });
+        }
+    }
+}
+
+/// Truncate Error
+///
+/// Truncates a reject reason to MAX_ERROR_LENGTH characters before it is stored
+fn _truncate_error(error: String) -> String {
+    error.chars().take(MAX_ERROR_LENGTH).collect()
+}
+
+/// Take Dead Letter
+///
+/// Removes an entry from the dead letters and adds it back to the retry queue with its attempts reset
+fn _take_dead_letter(id: u64) -> Option<RetryEntry> {
+    let mut entry = DEAD_LETTERS.with_borrow_mut(|reference| reference.remove(&id))?;
+
+    entry.attempts = 0;
+
+    RETRY_QUEUE.with_borrow_mut(|reference| reference.insert(id, entry.clone()));
+
+    return Some(entry);
+}
+
+fn _remove_entries(
+    entries: &mut StableBTreeMap<u64, RetryEntry, Memory>,
+    filter: impl Fn(&RetryEntry) -> bool,
+) {
+    let ids: Vec<u64> = entries
+        .iter()
+        .filter(|(_, entry)| filter(entry))
+        .map(|(id, _)| id)
+        .collect();
+
+    for id in ids {
+        entries.remove(&id);
     }
 }
 
@@ -1381,7 +3138,7 @@ struct PositionDetails {
     collateral_value: Amount,
     /// Debt
     ///
-    /// the amount borrowed as leveragex10
+    /// the amount borrowed for the leverage
     ///
     /// Note:debt is in perp Asset when shorting and in collateral_value asset when longing
     debt_value: Amount,
@@ -1449,6 +3206,8 @@ struct ManageDebtParams {
     new_debt: Amount,
     initial_debt: Amount,
     interest_received: Amount,
+    /// part of the repaid debt (initial debt - new debt) that could not be paid back
+    bad_debt: Amount,
 }
 
 impl ManageDebtParams {
@@ -1457,6 +3216,7 @@ impl ManageDebtParams {
             new_debt,
             initial_debt,
             interest_received,
+            bad_debt: 0,
         }
     }
 }
@@ -1465,34 +3225,32 @@ impl ManageDebtParams {
 ///   Possible error during inter canister calls and retry api
 ////////////////////////////
 
-/// Retrying Trait
-///
-/// Trait for all Errors related to inter canister calls
-trait Retrying {
-    /// Retry  Function
-    ///
-    /// This is used to retry the  failed inter canister call
-    fn retry(&self, details: MarketDetails);
-}
-
 /// ManageDebtError
 ///
-/// This error occurs for failed intercanister calls
+/// This error occurs for failed intercanister calls to the vault ,the account can not open a new position till it is cleared
+///
+/// Note
+///  - The log is also recorded while a position update awaits the vault's acknowledgement ,so the account's position
+///  is only final once the vault acknowledges the update
 
-#[derive(Clone, Copy, Deserialize, CandidType)]
+#[derive(Clone, Deserialize, CandidType)]
 struct PositionUpdateErrorLog {
+    /// Operation Id
+    ///
+    /// The unique id of the vault update ,reused when retrying
+    operation_id: u64,
     user: Principal,
     profit: Amount,
     debt_params: ManageDebtParams,
-}
-impl Retrying for PositionUpdateErrorLog {
-    fn retry(&self, details: MarketDetails) {
-        let _ = ic_cdk::notify(
-            details.vault_id,
-            "managePositionUpdate",
-            (self.user, self.profit, self.debt_params),
-        );
-    }
+    /// Reason
+    ///
+    /// The reject reason returned by the last attempt ,None while the first attempt awaits the vault
+    reason: Option<String>,
+    /// Position
+    ///
+    /// The account's position before the update ,kept till the vault acknowledges the update so an update the vault
+    /// rejects can be amended (see amendAccountError) ,None for logs migrated from the legacy error logs
+    position: Option<PositionDetails>,
 }
 
 impl Storable for PositionUpdateErrorLog {
@@ -1506,66 +3264,240 @@ impl Storable for PositionUpdateErrorLog {
 }
 
 impl BoundedStorable for PositionUpdateErrorLog {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 1000;
+}
+
+/// Legacy Position Update Error Log
+///
+/// Account error log recorded before vault updates carried an operation id ,only read when migrating
+#[derive(Clone, Copy, Deserialize, CandidType)]
+struct LegacyPositionUpdateErrorLog {
+    user: Principal,
+    profit: Amount,
+    debt_params: LegacyManageDebtParams,
+}
+
+#[derive(Clone, Copy, Deserialize, CandidType)]
+struct LegacyManageDebtParams {
+    new_debt: Amount,
+    initial_debt: Amount,
+    interest_received: Amount,
+}
+
+impl Storable for LegacyPositionUpdateErrorLog {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for LegacyPositionUpdateErrorLog {
     const IS_FIXED_SIZE: bool = true;
 
     const MAX_SIZE: u32 = 130;
 }
 
-#[derive(Clone)]
-enum ErrorType {
-    ExecuteTicksOrderError(ExecuteTicksError),
-    StoreTickOrderError(StoreTickOrderError),
-    RemoveTickOrderError(RemoveTickOrderError),
+/// Notification
+///
+/// Inter canister notifications to the watcher or the vault that are retried when they fail
+#[derive(CandidType, Deserialize, Clone)]
+enum Notification {
+    ExecuteTicksOrders {
+        ticks: Vec<Tick>,
+    },
+    StoreTickOrder {
+        tick: Tick,
+        account: Subaccount,
+    },
+    RemoveTickOrder {
+        tick: Tick,
+        account: Subaccount,
+    },
+    ManagePositionUpdate {
+        operation_id: u64,
+        user: Principal,
+        profit: Amount,
+        debt_params: ManageDebtParams,
+    },
+    CommitReservation {
+        account: Subaccount,
+        operation_id: u64,
+        collateral: Amount,
+        debt: Amount,
+    },
+    ReleaseReservation {
+        operation_id: u64,
+    },
 }
 
-#[derive(Clone)]
-struct ExecuteTicksError {
-    ticks: Vec<Tick>,
+impl Notification {
+    /// Send Function
+    ///
+    /// Sends the notification and waits for the receiving canister to execute it
+    async fn send(&self, details: &MarketDetails) -> Result<(), String> {
+        let result: CallResult<()> = match self {
+            Notification::ExecuteTicksOrders { ticks } => {
+                ic_cdk::call(details.watcher_id, "executeTicksOrders", (ticks.clone(),)).await
+            }
+            Notification::StoreTickOrder { tick, account } => {
+                ic_cdk::call(details.watcher_id, "storeTickOrder", (*tick, *account)).await
+            }
+            Notification::RemoveTickOrder { tick, account } => {
+                ic_cdk::call(details.watcher_id, "removeTickOrder", (*tick, *account)).await
+            }
+            Notification::ManagePositionUpdate {
+                operation_id,
+                user,
+                profit,
+                debt_params,
+            } => {
+                let result = ic_cdk::call(
+                    details.vault_id,
+                    "managePositionUpdate",
+                    (*operation_id, *user, *profit, *debt_params),
+                )
+                .await;
+                return _position_update_result(result);
+            }
+            Notification::CommitReservation {
+                account,
+                operation_id,
+                collateral,
+                debt,
+            } => {
+                let result = ic_cdk::call(
+                    details.vault_id,
+                    "commit",
+                    (*operation_id, *collateral, *debt),
+                )
+                .await;
+                let committed = _commit_result(result)?;
+                _settle_commit(*account, committed).await;
+                return Ok(());
+            }
+            Notification::ReleaseReservation { operation_id } => {
+                let result = ic_cdk::call(details.vault_id, "release", (*operation_id,)).await;
+                return _reservation_result(result);
+            }
+        };
+
+        result.map_err(|(code, message)| format!("{:?} {}", code, message))
+    }
+
+    /// Returns the account of a position update notification
+    fn position_update_account(&self) -> Option<Subaccount> {
+        if let Notification::ManagePositionUpdate { user, .. } = self {
+            return Some(user._to_subaccount());
+        }
+        None
+    }
 }
 
-impl Retrying for ExecuteTicksError {
-    fn retry(&self, details: MarketDetails) {
-        let _ = ic_cdk::notify(
-            details.watcher_id,
-            "executeTicksOrders",
-            (self.ticks.clone(),),
-        );
+/// Maps the result of a position update call ,an update rejected by the vault is an error
+fn _position_update_result(result: CallResult<(Result<(), String>,)>) -> Result<(), String> {
+    match result {
+        Ok((result,)) => result,
+        Err((code, message)) => Err(format!("{:?} {}", code, message)),
     }
 }
 
-#[derive(Clone, Copy)]
-struct StoreTickOrderError {
-    account: Subaccount,
-    tick: Tick,
+/// Maps the result of a commit call ,false if the vault rejected the commit
+fn _commit_result(result: CallResult<(bool,)>) -> Result<bool, String> {
+    match result {
+        Ok((committed,)) => Ok(committed),
+        Err((code, message)) => Err(format!("{:?} {}", code, message)),
+    }
 }
 
-impl Retrying for StoreTickOrderError {
-    fn retry(&self, details: MarketDetails) {
-        let _ = ic_cdk::notify(
-            details.watcher_id,
-            "storeTicksOrder
-            ",
-            (self.account, self.tick),
-        );
+/// Maps the result of a release call ,a reservation that was not released is an error
+fn _reservation_result(result: CallResult<(bool,)>) -> Result<(), String> {
+    match result {
+        Ok((true,)) => Ok(()),
+        Ok((false,)) => Err("Reservation not found or already settled differently".to_string()),
+        Err((code, message)) => Err(format!("{:?} {}", code, message)),
     }
 }
 
-#[derive(Clone, Copy)]
-struct RemoveTickOrderError {
-    account: Subaccount,
-    tick: Tick,
+/// Pending Commit
+///
+/// A position whose reservation has not been committed by the vault
+///
+/// Note
+///  - The account is locked till the vault commits the reservation or the position is unwound
+#[derive(Clone, Copy, Deserialize, CandidType)]
+struct PendingCommit {
+    /// Operation Id
+    ///
+    /// The operation id of the reservation
+    operation_id: u64,
+    user: Principal,
+    /// Entry Value
+    ///
+    /// The collateral and debt committed for the position ,deducted from the first unwind update as the vault holds
+    /// neither once it rejects the commit
+    entry_value: Amount,
+    /// true once the vault rejected the commit and the position is being unwound
+    unwinding: bool,
 }
 
-impl Retrying for RemoveTickOrderError {
-    fn retry(&self, details: MarketDetails) {
-        let _ = ic_cdk::notify(
-            details.watcher_id,
-            "removeTickOrder",
-            (self.account, self.tick),
-        );
+impl Storable for PendingCommit {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
     }
 }
 
+impl BoundedStorable for PendingCommit {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 150;
+}
+
+/// Retry Entry
+///
+/// A failed notification in the retry queue or dead letters
+#[derive(CandidType, Deserialize, Clone)]
+struct RetryEntry {
+    id: u64,
+    notification: Notification,
+    /// Number of retries made so far
+    attempts: u32,
+    /// Time at or after which the retry timer makes the next attempt
+    next_attempt: Time,
+    /// Error returned by the last attempt
+    last_error: String,
+}
+
+impl Storable for RetryEntry {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for RetryEntry {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 6000;
+}
+
+#[derive(CandidType, Deserialize)]
+struct RetryQueue {
+    pending: Vec<RetryEntry>,
+    dead_letters: Vec<RetryEntry>,
+}
+
 /// Exchange Rate Canister
 ///
 /// Utilised for fetching the price of current exchnage rate (spot price) of the market pair
@@ -1625,10 +3557,7 @@ impl Watcher {
     pub fn store_tick_order(&self, tick: Tick, account: Subaccount) {
         if let Ok(()) = ic_cdk::notify(self.canister_id, "storeTickOrder", (tick, account)) {
         } else {
-            ERRORS.with_borrow_mut(|reference| {
-                let error = ErrorType::StoreTickOrderError(StoreTickOrderError { account, tick });
-                reference.push(error);
-            })
+            _enqueue_notification(Notification::StoreTickOrder { tick, account });
         }
     }
 
@@ -1644,10 +3573,7 @@ impl Watcher {
     pub fn remove_tick_order(&self, tick: Tick, account: Subaccount) {
         if let Ok(()) = ic_cdk::notify(self.canister_id, "removeTickOrder", (tick, account)) {
         } else {
-            ERRORS.with_borrow_mut(|reference| {
-                let error = ErrorType::RemoveTickOrderError(RemoveTickOrderError { account, tick });
-                reference.push(error);
-            })
+            _enqueue_notification(Notification::RemoveTickOrder { tick, account });
         }
     }
 
@@ -1664,10 +3590,7 @@ impl Watcher {
 
         if let Ok(()) = ic_cdk::notify(self.canister_id, "executeTicksOrders", (ticks.clone(),)) {
         } else {
-            ERRORS.with_borrow_mut(|reference| {
-                let error = ErrorType::ExecuteTicksOrderError(ExecuteTicksError { ticks });
-                reference.push(error);
-            })
+            _enqueue_notification(Notification::ExecuteTicksOrders { ticks });
         }
     }
 }
@@ -1690,47 +3613,83 @@ impl Vault {
     ///
     /// Utilised when position is updated or closed
     /// Utilised when for updating user_balance,repayment of debt
-    pub fn manage_position_update(
+    ///
+    /// Returns
+    ///  - Result :Ok if the vault acknowledged the update or the vault's reject reason
+    ///
+    /// Note
+    ///  - The operation id is the sequence of the market event that caused the update ,the vault ignores
+    ///  operation ids it has already applied so failed updates can be retried safely with the same operation id
+    ///  - The account's error log is recorded before the call and only removed once the vault acknowledges the update ,
+    ///  it keeps the position before the update till then
+    pub async fn manage_position_update(
         &self,
+        operation_id: u64,
         user: Principal,
         profit: Amount,
         manage_debt_params: ManageDebtParams,
-    ) {
-        if let Ok(()) = ic_cdk::notify(
+        initial_position: Option<PositionDetails>,
+    ) -> Result<(), String> {
+        let account = user._to_subaccount();
+
+        // the account is pending till the vault acknowledges the update
+        _insert_account_error_log(
+            account,
+            PositionUpdateErrorLog {
+                operation_id,
+                user,
+                profit,
+                debt_params: manage_debt_params,
+                reason: None,
+                position: initial_position,
+            },
+        );
+
+        let result = ic_cdk::call(
             self.canister_id,
             "managePositionUpdate",
-            (user, profit, manage_debt_params),
-        ) {
-        } else {
-            let error_log = PositionUpdateErrorLog {
+            (operation_id, user, profit, manage_debt_params),
+        )
+        .await;
+
+        if let Err(reason) = _position_update_result(result) {
+            let reason = _truncate_error(reason);
+
+            _update_account_error_reason(&account, reason.clone());
+
+            _enqueue_notification(Notification::ManagePositionUpdate {
+                operation_id,
                 user,
                 profit,
                 debt_params: manage_debt_params,
-            };
-            _insert_account_error_log(user._to_subaccount(), error_log);
+            });
+
+            return Err(reason);
         }
+
+        _remove_account_error_log(&account);
+
+        return Ok(());
     }
 
-    /// Create Position Validity Check
-    ///
-    /// Checks if position can be opened by checking that uswer has sufficient balance and amount to use as debt is available as free liquidity
+    /// Reserve Function
     ///
-    /// User:The Owner of Account that opened position
-    /// Collateral Delta:The Amount of asset used as collateral for opening position
-    /// Debt : The Amount of asset taken as debt
+    /// Holds the collateral from the user's balance and the debt from the vault's free liquidity until the reservation is committed or released
     ///
-    /// Note :After checking that the condition holds ,the user balance is reduced by collateral amount and the free liquidity available is reduced by debt amount
-
-    pub async fn create_position_validity_check(
+    /// Returns
+    ///  - Valid :true if the reservation was made
+    ///  - Interest Rate :The interest rate charged on the debt
+    pub async fn reserve(
         &self,
+        operation_id: u64,
         user: Principal,
         collateral: Amount,
         debt: Amount,
     ) -> (bool, u32) {
         if let Ok((valid, interest_rate)) = ic_cdk::call(
             self.canister_id,
-            "createPositionValidityCheck",
-            (user, collateral, debt),
+            "reserve",
+            (operation_id, user, collateral, debt),
         )
         .await
         {
@@ -1739,6 +3698,56 @@ impl Vault {
             return (false, 0);
         }
     }
+
+    /// Commit Function
+    ///
+    /// Commits the reservation with the collateral and debt actually utilised ,the rest is returned by the vault
+    ///
+    /// Returns
+    ///  - Committed :true if the vault committed the reservation ,false if the vault rejected the commit
+    ///
+    /// Note
+    ///  - if the call fails ,the commit is added to the retry queue
+    pub async fn commit(
+        &self,
+        account: Subaccount,
+        operation_id: u64,
+        collateral: Amount,
+        debt: Amount,
+    ) -> Result<bool, String> {
+        let result =
+            ic_cdk::call(self.canister_id, "commit", (operation_id, collateral, debt)).await;
+
+        let committed = _commit_result(result);
+
+        if committed.is_err() {
+            _enqueue_notification(Notification::CommitReservation {
+                account,
+                operation_id,
+                collateral,
+                debt,
+            });
+        }
+
+        return committed;
+    }
+
+    /// Release Function
+    ///
+    /// Releases the whole reservation back to the user and the vault
+    ///
+    /// Note
+    ///  - if the release fails ,it is added to the retry queue
+    pub async fn release(&self, operation_id: u64) -> Result<(), String> {
+        let result = ic_cdk::call(self.canister_id, "release", (operation_id,)).await;
+
+        if let Err(reason) = _reservation_result(result) {
+            _enqueue_notification(Notification::ReleaseReservation { operation_id });
+            return Err(reason);
+        }
+
+        return Ok(());
+    }
 }
 
 trait UniqueSubAccount {
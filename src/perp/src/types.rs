@@ -1,29 +1,19 @@
-use crate::corelib::calc_lib::{_calc_shares, _calc_shares_value, _percentage128};
+use crate::corelib::calc_lib::{
+    _calc_margin_ratio, _calc_shares, _calc_shares_value, _percentage128,
+};
+use crate::corelib::constants::_ONE_X_LEVERAGE_BPS;
+use crate::corelib::math_lib::{_checked_add, Rounding};
+use crate::corelib::tick_lib::_max_valid_tick;
 use candid::{CandidType, Decode, Encode, Principal};
 use ic_stable_structures::{storable::BoundedStorable, Storable};
 
 use serde::Deserialize;
 use std::borrow::Cow;
-pub type Tick = u64;
-pub type Amount = u128;
 
-/// The enum defining the different asset classes.
-#[derive(CandidType, Clone, Debug, Deserialize, PartialEq)]
-pub enum AssetClass {
-    /// The cryptocurrency asset class.
-    Cryptocurrency,
-    /// The fiat currency asset class.
-    FiatCurrency,
-}
-
-/// Exchange rates are derived for pairs of assets captured in this struct.
-#[derive(CandidType, Clone, Debug, Default, Deserialize, PartialEq)]
-pub struct Asset {
-    /// The symbol/code of the asset.
-    pub symbol: String,
-    /// The asset class.
-    pub class: AssetClass,
-}
+pub use market_types::{
+    Amount, Asset, AssetClass, AssetDecimals, MarketDetails, MarketMode, StateDetails, Tick,
+    TickGrid, TickModel,
+};
 
 /// The type the user sends when requesting a rate.
 ///
@@ -136,44 +126,86 @@ pub struct FundingRateTracker {
 }
 
 impl FundingRateTracker {
-    pub fn add_volume(&mut self, delta: Amount, long: bool) -> Amount {
+    /// Note
+    ///  - The tracker is only updated if the calculation succeeds ,same for remove volume and settle funding rate
+    pub fn add_volume(&mut self, delta: Amount, long: bool) -> Result<Amount, MathError> {
         if long {
-            let volume_share = _calc_shares(delta, self.total_long_shares, self.net_volume_long);
-            self.total_long_shares += volume_share;
-            self.net_volume_long += delta;
-            return volume_share;
+            let volume_share = _calc_shares(delta, self.total_long_shares, self.net_volume_long)?;
+            (self.total_long_shares, self.net_volume_long) = (
+                _checked_add(self.total_long_shares, volume_share)?,
+                _checked_add(self.net_volume_long, delta)?,
+            );
+            return Ok(volume_share);
         } else {
-            let volume_share = _calc_shares(delta, self.total_short_shares, self.net_volume_short);
-            self.total_short_shares += volume_share;
-            self.net_volume_short += delta;
-            return volume_share;
+            let volume_share = _calc_shares(delta, self.total_short_shares, self.net_volume_short)?;
+            (self.total_short_shares, self.net_volume_short) = (
+                _checked_add(self.total_short_shares, volume_share)?,
+                _checked_add(self.net_volume_short, delta)?,
+            );
+            return Ok(volume_share);
         }
     }
 
-    pub fn remove_volume(&mut self, delta: Amount, long: bool) -> Amount {
+    pub fn remove_volume(&mut self, delta: Amount, long: bool) -> Result<Amount, MathError> {
+        let value = self.volume_share_value(delta, long)?;
         if long {
-            let value = _calc_shares_value(delta, self.total_long_shares, self.net_volume_long);
             self.net_volume_long -= value;
             self.total_long_shares -= delta;
-            return value;
+            return Ok(value);
         } else {
-            let value = _calc_shares_value(delta, self.total_short_shares, self.net_volume_short);
             self.net_volume_short -= value;
             self.total_short_shares -= delta;
-            return value;
+            return Ok(value);
+        }
+    }
+
+    /// Volume Share Value
+    ///
+    /// Returns the current value of a volume share in a market direction without removing it
+    pub fn volume_share_value(
+        &self,
+        volume_share: Amount,
+        long: bool,
+    ) -> Result<Amount, MathError> {
+        if volume_share == 0 {
+            return Ok(0);
+        }
+        if long {
+            return _calc_shares_value(volume_share, self.total_long_shares, self.net_volume_long);
+        } else {
+            return _calc_shares_value(
+                volume_share,
+                self.total_short_shares,
+                self.net_volume_short,
+            );
         }
     }
 
-    pub fn settle_funding_rate(&mut self, funding_rate: u64, positive: bool) {
+    /// Settle Funding Rate
+    ///
+    /// Moves the funding rate percentage of the paying direction's volume to the other direction
+    ///
+    /// Note
+    ///  - The amount to settle is owed by the paying direction so it is rounded up ,capped at the paying direction's volume
+    pub fn settle_funding_rate(
+        &mut self,
+        funding_rate: u64,
+        positive: bool,
+    ) -> Result<(), MathError> {
         if positive {
-            let amount_to_settle = _percentage128(funding_rate, self.net_volume_long);
-            self.net_volume_short += amount_to_settle;
+            let amount_to_settle =
+                _percentage128(funding_rate, self.net_volume_long, Rounding::Up)?
+                    .min(self.net_volume_long);
+            self.net_volume_short = _checked_add(self.net_volume_short, amount_to_settle)?;
             self.net_volume_long -= amount_to_settle;
         } else {
-            let amount_to_settle = _percentage128(funding_rate, self.net_volume_short);
-            self.net_volume_long += amount_to_settle;
+            let amount_to_settle =
+                _percentage128(funding_rate, self.net_volume_short, Rounding::Up)?
+                    .min(self.net_volume_short);
+            self.net_volume_long = _checked_add(self.net_volume_long, amount_to_settle)?;
             self.net_volume_short -= amount_to_settle
         }
+        Ok(())
     }
 }
 
@@ -226,50 +258,370 @@ impl BoundedStorable for ID {
     const MAX_SIZE: u32 = 50;
 }
 
-impl Default for AssetClass {
-    fn default() -> Self {
-        AssetClass::Cryptocurrency
+/// Market Mode Checks
+///
+/// The trading actions a market mode allows ,as the errors the trading endpoints return
+pub trait MarketModeChecks {
+    /// Returns an error if the mode does not allow opening a position in the direction
+    fn check_open(&self, long: bool) -> Result<(), MarketError>;
+
+    /// Returns an error if the mode does not allow closing a position or cancelling a limit order
+    fn check_close(&self) -> Result<(), MarketError>;
+}
+
+impl MarketModeChecks for MarketMode {
+    fn check_open(&self, long: bool) -> Result<(), MarketError> {
+        match self {
+            MarketMode::Active => Ok(()),
+            MarketMode::ReduceOnly => Err(MarketError::ReduceOnly),
+            MarketMode::LongsHalted if long => Err(MarketError::SideHalted { long }),
+            MarketMode::ShortsHalted if !long => Err(MarketError::SideHalted { long }),
+            MarketMode::LongsHalted | MarketMode::ShortsHalted => Ok(()),
+            MarketMode::Paused => Err(MarketError::MarketPaused),
+        }
+    }
+
+    fn check_close(&self) -> Result<(), MarketError> {
+        match self {
+            MarketMode::Paused => Err(MarketError::MarketPaused),
+            _ => Ok(()),
+        }
     }
 }
 
-///Market Details
-#[derive(Clone, Deserialize, CandidType, Debug)]
-pub struct MarketDetails {
-    /// The details of the  perpetual asset also seen as the base asset  
-    pub base_asset: Asset,
+/// Market Error
+///
+/// Reasons a trading endpoint rejects a call
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum MarketError {
+    /// Market is paused
+    MarketPaused,
+    /// Market only allows closing positions
+    ReduceOnly,
+    /// Opening positions in the direction is halted
+    SideHalted {
+        long: bool,
+    },
+    /// Account already has a position
+    PositionExists,
+    /// Account has no position to close
+    NoPosition,
+    /// Account has a vault update or a reservation commit waiting to be acknowledged
+    PendingPositionUpdate,
+    /// Leverage is below 1x or above the max leverage ,both bounds are in basis points
+    LeverageOutOfRange {
+        min_leverage_bps: u64,
+        max_leverage_bps: u64,
+    },
+    /// Collateral is below the min collateral
+    CollateralTooSmall {
+        min_collateral: Amount,
+    },
+    /// Leverage is above the max leverage of the position's risk tier
+    LeverageAboveTier {
+        max_leverage_bps: u64,
+    },
+    /// Position notional is above the max notional of the last risk tier
+    NotionalAboveMaxTier,
+    /// Initial margin ratio is below the maintenance margin of the position's risk tier
+    MarginBelowMaintenance {
+        maintenance_margin: u64,
+    },
+    OpenInterestLimitExceeded,
+    /// Vault rejected the reservation ,user balance or free liquidity is insufficient
+    ReservationRejected,
+    /// Position could not be opened at the given tick
+    OpenFailed,
+    /// Vault rejected the commit of the position's reservation ,the position is unwound
+    CommitRejected,
+    /// Limit order tick is above the highest tick priced by the tick model
+    TickOutOfRange {
+        max_tick: Tick,
+    },
+    /// Vault did not acknowledge an update ,it is retried from the retry queue
+    VaultUpdatePending {
+        reason: String,
+    },
+    /// An amount calculation overflowed or divided by zero
+    Arithmetic(MathError),
+}
 
-    /// the details of the collateral token  in asset  all margin is paid
-    pub quote_asset: Asset,
-    /// Vault ID
+impl From<MathError> for MarketError {
+    fn from(error: MathError) -> Self {
+        MarketError::Arithmetic(error)
+    }
+}
+
+/// Math Error
+///
+/// Reasons an amount calculation fails (see math_lib)
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MathError {
+    /// Denominator was zero
+    DivisionByZero,
+    /// Result does not fit within 128 bits
+    Overflow,
+}
+
+/// State Parameter
+///
+/// A single state details field and its new value ,changed through a timelocked proposal
+///
+/// Note
+///  - The pause state is not a parameter ,pausing is done immediately by the pauser
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateParameter {
+    CurrentTick(Tick),
+    MaxLeverageBps(u64),
+    MinCollateral(Amount),
+}
+
+impl StateParameter {
+    /// Sets the parameter's field in the state details to the new value
+    pub fn apply(&self, state_details: &mut StateDetails) {
+        match *self {
+            StateParameter::CurrentTick(tick) => state_details.current_tick = tick,
+            StateParameter::MaxLeverageBps(leverage_bps) => {
+                state_details.max_leverage_bps = leverage_bps
+            }
+            StateParameter::MinCollateral(amount) => state_details.min_collateral = amount,
+        }
+    }
+
+    /// Checks that the new value is valid on the market's tick grid
     ///
-    /// The canister ID of the vault canister
-    pub vault_id: Principal,
-    /// Watcher ID
+    /// Note
+    ///  - The current tick must be a grid tick within the valid tick range and the max leverage can not be below 1x
+    pub fn validate(&self, grid: &TickGrid) -> Result<(), String> {
+        match *self {
+            StateParameter::CurrentTick(tick) => {
+                if tick % grid.tick_spacing != 0 || tick > _max_valid_tick(grid) {
+                    return Err("Current tick must be a valid grid tick".to_string());
+                }
+            }
+            StateParameter::MaxLeverageBps(leverage_bps) => {
+                if leverage_bps < _ONE_X_LEVERAGE_BPS {
+                    return Err("Max leverage can not be below 1x".to_string());
+                }
+            }
+            StateParameter::MinCollateral(_) => {}
+        }
+        return Ok(());
+    }
+
+    /// Returns every parameter of the state details
+    pub fn all(state_details: &StateDetails) -> [StateParameter; 3] {
+        [
+            StateParameter::CurrentTick(state_details.current_tick),
+            StateParameter::MaxLeverageBps(state_details.max_leverage_bps),
+            StateParameter::MinCollateral(state_details.min_collateral),
+        ]
+    }
+}
+
+/// Parameter Proposal
+///
+/// A pending change of a state parameter
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct ParameterProposal {
+    pub id: u64,
+    pub parameter: StateParameter,
+    pub proposer: Principal,
+    pub proposed_at: u64,
+    /// Executable At
+    ///
+    /// time from which the proposal can be executed
+    pub executable_at: u64,
+}
+
+impl Storable for ParameterProposal {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for ParameterProposal {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 300;
+}
+
+/// Risk Tier
+///
+/// The max leverage and maintenance margin of positions up to a notional value
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RiskTier {
+    /// Max Notional
+    ///
+    /// the largest position notional value (collateral + debt) in this tier
+    pub max_notional: Amount,
+    /// Max Leverage
     ///
-    /// The cansiter ID of the watcher canister
-    pub watcher_id: Principal,
+    /// the max leverage of positions in this tier in basis points
+    pub max_leverage_bps: u64,
+    /// Maintenance Margin
+    ///
+    /// the min equity as a percentage of the position value before the position can be liquidated
+    pub maintenance_margin: u64,
+}
+
+/// Risk Tiers
+///
+/// Tiers ordered by increasing max notional ,larger positions get lower leverage and higher maintenance margin
+///
+/// Note:
+///  - With no tiers ,only the state details max leverage applies and no maintenance margin is enforced
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskTiers {
+    pub tiers: Vec<RiskTier>,
+}
+
+impl RiskTiers {
+    /// Max number of tiers
+    pub const MAX_TIERS: usize = 10;
+
+    /// Returns the first tier whose max notional covers the notional ,none if notional exceeds every tier
+    pub fn tier_for(&self, notional: Amount) -> Option<RiskTier> {
+        self.tiers
+            .iter()
+            .find(|tier| notional <= tier.max_notional)
+            .copied()
+    }
+
+    /// Checks that tiers are ordered by increasing max notional ,with non increasing max leverage and non decreasing maintenance margin
+    ///
+    /// Note
+    ///  - Each tier's maintenance margin must be below the initial margin at its max leverage (1 / max leverage)
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tiers.len() > Self::MAX_TIERS {
+            return Err("Too many risk tiers".to_string());
+        }
+        for tier in &self.tiers {
+            let initial_margin =
+                _calc_margin_ratio(_ONE_X_LEVERAGE_BPS as i128, tier.max_leverage_bps as Amount);
+            if tier.maintenance_margin >= initial_margin {
+                return Err(
+                    "Maintenance margin must be below the initial margin at max leverage"
+                        .to_string(),
+                );
+            }
+        }
+        for pair in self.tiers.windows(2) {
+            let (lower, upper) = (pair[0], pair[1]);
+            if upper.max_notional <= lower.max_notional {
+                return Err("Max notional must increase across tiers".to_string());
+            }
+            if upper.max_leverage_bps > lower.max_leverage_bps {
+                return Err("Max leverage can not increase across tiers".to_string());
+            }
+            if upper.maintenance_margin < lower.maintenance_margin {
+                return Err("Maintenance margin can not decrease across tiers".to_string());
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl Storable for RiskTiers {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
 
-    pub xrc_id: Principal,
+impl BoundedStorable for RiskTiers {
+    const IS_FIXED_SIZE: bool = false;
 
-    /// token decimal of collateral token
-    pub collateral_decimal: u8,
+    const MAX_SIZE: u32 = 500;
 }
 
-impl Default for MarketDetails {
-    fn default() -> MarketDetails {
-        return MarketDetails {
-            base_asset: Asset::default(),
-            quote_asset: Asset::default(),
-            vault_id: Principal::anonymous(),
-            xrc_id: Principal::anonymous(),
-            watcher_id: Principal::anonymous(),
-            collateral_decimal: 0,
+/// Open Interest Limits
+///
+/// Caps on the open interest of the market ,all values are denominated in the collateral asset
+///
+/// Note:
+///  - A limit set to zero means that particular limit is not enforced
+#[derive(CandidType, Default, Debug, PartialEq, Eq, Copy, Deserialize, Clone)]
+pub struct OpenInterestLimits {
+    /// Max Long Open Interest
+    ///
+    /// the maximum net volume of all long positions (see FundingRateTracker)
+    pub max_long_open_interest: Amount,
+    /// Max Short Open Interest
+    ///
+    /// the maximum net volume of all short positions (see FundingRateTracker)
+    pub max_short_open_interest: Amount,
+    /// Max Account Notional
+    ///
+    /// the maximum notional (collateral + debt) of a single account's position
+    pub max_account_notional: Amount,
+}
+
+impl OpenInterestLimits {
+    /// Remaining Side Capacity
+    ///
+    /// Returns the amount of notional that can still be opened in a market direction or None if that side is not capped
+    pub fn remaining_side_capacity(
+        &self,
+        funding_rate_tracker: &FundingRateTracker,
+        long: bool,
+    ) -> Option<Amount> {
+        let (max_open_interest, open_interest) = if long {
+            (
+                self.max_long_open_interest,
+                funding_rate_tracker.net_volume_long,
+            )
+        } else {
+            (
+                self.max_short_open_interest,
+                funding_rate_tracker.net_volume_short,
+            )
+        };
+        _remaining(max_open_interest, open_interest)
+    }
+
+    /// Remaining Account Capacity
+    ///
+    /// Returns the amount of notional an account can still open given the notional of it's current position
+    /// or None if accounts are not capped
+    pub fn remaining_account_capacity(&self, account_notional: Amount) -> Option<Amount> {
+        _remaining(self.max_account_notional, account_notional)
+    }
+
+    /// Within Limits
+    ///
+    /// Checks that opening a position with the given notional does not exceed any of the limits
+    pub fn within_limits(
+        &self,
+        funding_rate_tracker: &FundingRateTracker,
+        notional: Amount,
+        long: bool,
+    ) -> bool {
+        let within = |capacity: Option<Amount>| match capacity {
+            Some(remaining) => notional <= remaining,
+            None => true,
         };
+        within(self.remaining_side_capacity(funding_rate_tracker, long))
+            && within(self.remaining_account_capacity(0))
+    }
+}
+
+fn _remaining(limit: Amount, current: Amount) -> Option<Amount> {
+    if limit == 0 {
+        return None;
     }
+    Some(limit.saturating_sub(current))
 }
 
-impl Storable for MarketDetails {
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+impl Storable for OpenInterestLimits {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
 
@@ -278,39 +630,396 @@ impl Storable for MarketDetails {
     }
 }
 
-///State Details comprises for useful parameters that change more frequently
-/// compared to market details  that is  set on initialization
+/// Open Interest Capacity
+///
+/// The remaining capacity for opening positions ,None means that capacity is not capped
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct OpenInterestCapacity {
+    pub long_capacity: Option<Amount>,
+    pub short_capacity: Option<Amount>,
+    pub account_capacity: Option<Amount>,
+}
 
+/// Price Band Details
+///
+/// Parameters for bounding swaps around the oracle price and halting the market
+/// when the oracle price and the book price diverge for too long
+///
+/// Note:
+///  - percentages are given in the same unit as ticks i.e 1% is 100_000
+///  - a band width or divergence threshold of zero disables that particular check
 #[derive(CandidType, Default, Debug, PartialEq, Eq, Copy, Deserialize, Clone)]
-pub struct StateDetails {
-    /// Determine ifmarket is paused or not
-    pub not_paused: bool,
-    /// Current Tick
+pub struct PriceBandDetails {
+    /// Band Width
     ///
+    /// the maximum distance of a swap's resulting tick from the oracle tick as a percentage of the oracle tick
+    pub band_width: u64,
+    /// Divergence Threshold
     ///
-    pub current_tick: Tick,
-    /// Max Leverage
+    /// the distance between the current tick and the oracle tick above which the market is considered diverged
+    pub divergence_threshold: u64,
+    /// Max Divergence Duration
     ///
-    /// the maximum leverage allowed for any position * 10
+    /// the duration (in nanoseconds) a divergence can be sustained before trading is halted
+    pub max_divergence_duration: u64,
+}
+
+impl Storable for PriceBandDetails {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Oracle Details
+///
+/// Tracks the last price fetched from the exchange rate canister
+#[derive(CandidType, Default, Debug, PartialEq, Eq, Copy, Deserialize, Clone)]
+pub struct OracleDetails {
+    /// Oracle Tick
     ///
-    /// typically leverage is set multiplied by 10 ,so a leverage of 2x would be written as 20  
-    pub max_leveragex10: u8,
-    /// Minimum Collateral
+    /// the tick corresponding to the last oracle price ,zero if no price has been fetched yet
+    pub oracle_tick: Tick,
+    /// Last Updated
     ///
-    /// minimum collateral or minimum margin to open a position
+    /// timestamp of the last oracle price update
+    pub last_updated: u64,
+    /// Divergence Start
     ///
-    /// Note:
+    /// the timestamp at which the current divergence between the book and the oracle started if any
+    pub divergence_start: Option<u64>,
+}
+
+impl OracleDetails {
+    /// Update function
+    ///
+    /// updates the oracle tick and tracks for how long the book has been diverged from the oracle
+    ///
+    /// Returns
+    ///  - Sustained :true if the divergence has been sustained for longer than the max divergence duration
+    pub fn update(
+        &mut self,
+        oracle_tick: Tick,
+        diverged: bool,
+        max_divergence_duration: u64,
+        now: u64,
+    ) -> bool {
+        self.oracle_tick = oracle_tick;
+        self.last_updated = now;
+
+        if !diverged {
+            self.divergence_start = None;
+            return false;
+        }
+
+        let start = *self.divergence_start.get_or_insert(now);
+
+        return now - start >= max_divergence_duration;
+    }
+}
+
+impl Storable for OracleDetails {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Circuit Breaker Event
+///
+/// Recorded when the market is set to reduce only due to a sustained divergence between the oracle and the book
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct CircuitBreakerEvent {
+    pub timestamp: u64,
+    pub oracle_tick: Tick,
+    pub current_tick: Tick,
+    /// divergence of the current tick from the oracle tick as a percentage
+    pub divergence: u64,
+    pub divergence_start: u64,
+}
+
+impl Storable for CircuitBreakerEvent {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for CircuitBreakerEvent {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 150;
+}
+
+/// Market Event Kind
+///
+/// The action recorded by a market event
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MarketEventKind {
+    /// Position opened ,either filled with a market order or placed as a limit order
+    PositionOpened {
+        long: bool,
+        limit: bool,
+        collateral_value: Amount,
+        debt_value: Amount,
+        entry_tick: Tick,
+    },
+    /// Position could not be opened ,collateral and debt are sent back
+    PositionOpenFailed {
+        long: bool,
+        collateral_value: Amount,
+        debt_value: Amount,
+    },
+    /// Position closed fully or partially ,amount out is the amount sent back to the position owner
+    PositionClosed {
+        long: bool,
+        amount_out: Amount,
+        resulting_tick: Tick,
+    },
+    /// Position closed by the market after the vault rejected the commit of its reservation ,returned value is the
+    /// value the close returned to the vault
+    PositionUnwound {
+        long: bool,
+        returned_value: Amount,
+        resulting_tick: Tick,
+    },
+    /// Limit position converted to a market position ,filled is false if order was only partially filled
+    PositionConverted {
+        long: bool,
+        entry_tick: Tick,
+        filled: bool,
+    },
+    /// Funding settled between both market directions
+    FundingSettled {
+        /// funding rate as a percentage
+        funding_rate: u64,
+        /// true if longs paid shorts
+        long_pays: bool,
+        net_volume_long: Amount,
+        net_volume_short: Amount,
+    },
+    /// Role granted to a principal by the owner
+    RoleGranted { principal: Principal, role: Role },
+    /// Role revoked from a principal by the owner
+    RoleRevoked { principal: Principal, role: Role },
+    /// Ownership transfer proposed by the current owner ,pending acceptance
+    OwnershipProposed { new_owner: Principal },
+    /// Ownership transfer accepted by the proposed owner
+    OwnershipTransferred {
+        previous_owner: Principal,
+        new_owner: Principal,
+    },
+    /// Market mode changed by the pauser
+    MarketModeChanged { mode: MarketMode },
+    /// State parameter change proposed ,executable after the timelock delay
+    ParameterChangeProposed { id: u64, parameter: StateParameter },
+    /// Pending state parameter change cancelled
+    ParameterChangeCancelled { id: u64 },
+    /// State parameter change executed
+    ParameterChangeExecuted { id: u64, parameter: StateParameter },
+    /// Failed position update recorded before vault updates carried an operation id ,the sequence of this event is
+    /// the operation id it is retried with
+    LegacyPositionUpdate { profit: Amount },
+}
+
+/// Market Event
+///
+/// An entry in the append only market event log
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct MarketEvent {
+    /// Sequence
+    ///
+    /// position of the event in the log ,starting at zero
+    pub sequence: u64,
+    pub timestamp: u64,
+    /// Account
+    ///
+    /// the account of the position owner ,none for funding settlements and role changes
+    pub account: Option<[u8; 32]>,
+    pub kind: MarketEventKind,
+}
+
+impl Storable for MarketEvent {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for MarketEvent {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 600;
+}
+
+/// Role
+///
+/// Permissions that can be granted to a principal for the admin endpoints ,the owner holds every role
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Updates state details and open interest limits
+    RiskManager,
+    /// Pauses and unpauses the market
+    Pauser,
+    /// Updates the oracle price band and circuit breaker
+    OracleOperator,
+}
+
+/// Role Members
+///
+/// The principals holding each role
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct RoleMembers {
+    pub members: Vec<(Principal, Role)>,
+}
+
+impl RoleMembers {
+    /// Returns true if principal has been granted the role
+    pub fn has_role(&self, principal: Principal, role: Role) -> bool {
+        self.members.contains(&(principal, role))
+    }
+
+    /// Grants the role to principal ,returns false if it was already granted
+    pub fn grant(&mut self, principal: Principal, role: Role) -> bool {
+        if self.has_role(principal, role) {
+            return false;
+        }
+        self.members.push((principal, role));
+        return true;
+    }
+
+    /// Revokes the role from principal ,returns false if it was not granted
+    pub fn revoke(&mut self, principal: Principal, role: Role) -> bool {
+        let initial_len = self.members.len();
+        self.members.retain(|member| *member != (principal, role));
+        return self.members.len() != initial_len;
+    }
+}
+
+impl Storable for RoleMembers {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for RoleMembers {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 3000;
+}
+
+/// Role Details
+///
+/// The owner ,pending owner and role members of the market
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RoleDetails {
+    pub owner: Principal,
+    /// Pending Owner
     ///
-    /// -this amount  is actuallly  reduced by the reduction i.e (10::pow(token_decimal - 6))
-    pub min_collateral: Amount,
+    /// principal proposed by the owner that has not accepted ownership yet
+    pub pending_owner: Option<Principal>,
+    pub members: Vec<(Principal, Role)>,
+}
 
-    /// Base Token Multiplier
+/// Candle Resolution
+///
+/// The duration covered by a single candle
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandleResolution {
+    OneMinute,
+    OneHour,
+    OneDay,
+}
+
+impl CandleResolution {
+    pub const ALL: [CandleResolution; 3] = [
+        CandleResolution::OneMinute,
+        CandleResolution::OneHour,
+        CandleResolution::OneDay,
+    ];
+
+    /// Duration of a candle in nanoseconds
+    pub fn duration(&self) -> u64 {
+        const ONE_MINUTE: u64 = 60_000_000_000;
+        match self {
+            CandleResolution::OneMinute => ONE_MINUTE,
+            CandleResolution::OneHour => 60 * ONE_MINUTE,
+            CandleResolution::OneDay => 24 * 60 * ONE_MINUTE,
+        }
+    }
+
+    /// Max number of candles kept for the resolution ,older candles are removed
+    pub fn max_candles(&self) -> u64 {
+        match self {
+            CandleResolution::OneMinute => 1440, // one day
+            CandleResolution::OneHour => 720,    // thirty days
+            CandleResolution::OneDay => 365,     // one year
+        }
+    }
+
+    /// Returns the open time of the candle containing the timestamp
+    pub fn open_time(&self, timestamp: u64) -> u64 {
+        timestamp - (timestamp % self.duration())
+    }
+
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// Candle
+///
+/// Open ,high ,low and close prices and volume of executed swaps within a candle's duration
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub open_time: u64,
+    pub open: Amount,
+    pub high: Amount,
+    pub low: Amount,
+    pub close: Amount,
+    /// Volume
     ///
-    /// base token multiple for cases of perp_assets with lower value than the underlying collateral asset
-    pub base_token_multiple: u8,
+    /// the amount of base (perp) asset swapped
+    pub volume: Amount,
 }
 
-impl Storable for StateDetails {
+impl Candle {
+    pub fn new(open_time: u64, price: Amount, volume: Amount) -> Self {
+        Candle {
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume,
+        }
+    }
+
+    pub fn update(&mut self, price: Amount, volume: Amount) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += volume;
+    }
+}
+
+impl Storable for Candle {
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
         Decode!(bytes.as_ref(), Self).unwrap()
     }
@@ -320,6 +1029,103 @@ impl Storable for StateDetails {
     }
 }
 
+impl BoundedStorable for Candle {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 200;
+}
+
+/// Book Side
+///
+/// The side(s) of the order book to return when querying depth
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSide {
+    Bids,
+    Asks,
+    Both,
+}
+
+/// Order Book Level
+///
+/// The aggregated static liquidity at a single tick
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct OrderBookLevel {
+    pub tick: Tick,
+    /// price corresponding to the tick
+    pub price: Amount,
+    /// Liquidity
+    ///
+    /// amount of base (perp) asset for an ask level or quote (collateral) asset for a bid level
+    pub liquidity: Amount,
+}
+
+/// Order Book
+///
+/// Bid and ask levels ordered from the current tick outwards
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
+pub struct OrderBook {
+    pub current_tick: Tick,
+    pub bids: Vec<OrderBookLevel>,
+    pub asks: Vec<OrderBookLevel>,
+}
+
+/// Position Health
+///
+/// The valuation of an open position at the current tick
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct PositionHealth {
+    /// PNL
+    ///
+    /// unrealised profit or loss as a percentage of the initial position value
+    pub pnl: i128,
+    /// PNL Value
+    ///
+    /// unrealised profit or loss in the collateral asset
+    pub pnl_value: i128,
+    /// Accrued Interest
+    ///
+    /// interest accrued on the debt in the collateral asset
+    pub accrued_interest: Amount,
+    /// Accrued Funding
+    ///
+    /// funding received (positive) or paid (negative) by the position since it was opened in the collateral asset
+    pub accrued_funding: i128,
+    /// Margin Ratio
+    ///
+    /// equity as a percentage of the current position value ,zero if equity is exhausted
+    pub margin_ratio: u64,
+    /// Liquidation Price
+    ///
+    /// the price at which the position's equity is exhausted ,zero if position has no debt
+    pub liquidation_price: Amount,
+    /// Maintenance Margin
+    ///
+    /// the maintenance margin of the position's risk tier ,zero if no risk tiers are set
+    pub maintenance_margin: u64,
+    /// Below Maintenance
+    ///
+    /// true if the margin ratio is below the maintenance margin
+    pub below_maintenance: bool,
+}
+
+/// Swap Quote
+///
+/// The expected result of a swap at the current state of the book
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+pub struct SwapQuote {
+    pub amount_out: Amount,
+    pub amount_remaining: Amount,
+    pub resulting_tick: Tick,
+    /// Average Price
+    ///
+    /// the average execution price of the swap ,zero if nothing can be swapped
+    pub average_price: Amount,
+    /// Price Impact
+    ///
+    /// deviation of the average price from the price at the current tick in basis points
+    pub price_impact: u64,
+}
+
 #[derive(CandidType, Default, Deserialize, Clone, Copy)]
 pub struct TickDetails {
     pub liq_bounds_token0: LiquidityBoundary,
@@ -329,6 +1135,22 @@ pub struct TickDetails {
     pub liq_bounds_token1: LiquidityBoundary,
 }
 
+impl Storable for TickDetails {
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl BoundedStorable for TickDetails {
+    const IS_FIXED_SIZE: bool = false;
+
+    const MAX_SIZE: u32 = 300;
+}
+
 impl TickDetails {
     /// Add_liquidity function
     ///
@@ -419,3 +1241,344 @@ impl LiquidityBoundary {
         self.lifetime_removed_liquidity += delta
     }
 }
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+    use crate::corelib::constants::_ONE_PERCENT;
+
+    #[test]
+    fn test_risk_tiers() {
+        let risk_tiers = RiskTiers {
+            tiers: vec![
+                RiskTier {
+                    max_notional: 10_000,
+                    max_leverage_bps: 100_000,
+                    maintenance_margin: _ONE_PERCENT,
+                },
+                RiskTier {
+                    max_notional: 100_000,
+                    max_leverage_bps: 50_000,
+                    maintenance_margin: 2 * _ONE_PERCENT,
+                },
+            ],
+        };
+        assert_eq!(risk_tiers.validate(), Ok(()));
+
+        assert_eq!(risk_tiers.tier_for(10_000), Some(risk_tiers.tiers[0]));
+        assert_eq!(risk_tiers.tier_for(10_001), Some(risk_tiers.tiers[1]));
+        assert_eq!(risk_tiers.tier_for(100_001), None);
+        assert_eq!(RiskTiers::default().tier_for(1), None);
+
+        let mut increasing_leverage = risk_tiers.clone();
+        increasing_leverage.tiers[1].max_leverage_bps = 150_000;
+        assert!(increasing_leverage.validate().is_err());
+
+        let mut unordered = risk_tiers.clone();
+        unordered.tiers.reverse();
+        assert!(unordered.validate().is_err());
+
+        let mut decreasing_margin = risk_tiers.clone();
+        decreasing_margin.tiers[1].maintenance_margin = 0;
+        assert!(decreasing_margin.validate().is_err());
+
+        // 5x leverage has an initial margin of 20%
+        let mut margin_above_initial = risk_tiers.clone();
+        margin_above_initial.tiers[1].maintenance_margin = 20 * _ONE_PERCENT;
+        assert!(margin_above_initial.validate().is_err());
+        margin_above_initial.tiers[1].maintenance_margin = 20 * _ONE_PERCENT - 1;
+        assert_eq!(margin_above_initial.validate(), Ok(()));
+
+        let max_tiers = RiskTiers {
+            tiers: (0..RiskTiers::MAX_TIERS as u128)
+                .map(|index| RiskTier {
+                    max_notional: Amount::MAX - RiskTiers::MAX_TIERS as u128 + index,
+                    max_leverage_bps: u64::MAX,
+                    maintenance_margin: u64::MAX,
+                })
+                .collect(),
+        };
+        assert!(max_tiers.to_bytes().len() <= RiskTiers::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn test_open_interest_limits() {
+        let limits = OpenInterestLimits {
+            max_long_open_interest: 1_000_000,
+            max_short_open_interest: 0,
+            max_account_notional: 400_000,
+        };
+
+        let mut tracker = FundingRateTracker::default();
+        tracker.add_volume(800_000, true).unwrap();
+
+        assert_eq!(
+            limits.remaining_side_capacity(&tracker, true),
+            Some(200_000)
+        );
+        // short side is not capped
+        assert_eq!(limits.remaining_side_capacity(&tracker, false), None);
+
+        assert!(limits.within_limits(&tracker, 200_000, true));
+        assert!(!limits.within_limits(&tracker, 200_001, true));
+        // account cap still applies to uncapped side
+        assert!(limits.within_limits(&tracker, 400_000, false));
+        assert!(!limits.within_limits(&tracker, 400_001, false));
+
+        assert_eq!(limits.remaining_account_capacity(500_000), Some(0));
+    }
+
+    #[test]
+    fn test_volume_share_value() {
+        let mut tracker = FundingRateTracker::default();
+
+        let share = tracker.add_volume(1_000_000, true).unwrap();
+        tracker.add_volume(1_000_000, true).unwrap();
+
+        // funding paid by longs
+        tracker
+            .settle_funding_rate(10 * _ONE_PERCENT, true)
+            .unwrap();
+
+        let value = tracker.volume_share_value(share, true).unwrap();
+        assert_eq!(value, 900_000);
+
+        // valuing a share does not modify the tracker
+        assert_eq!(tracker.net_volume_long, 1_800_000);
+        assert_eq!(tracker.volume_share_value(0, false), Ok(0));
+
+        assert_eq!(tracker.remove_volume(share, true), Ok(value));
+        assert_eq!(tracker.net_volume_long, 900_000);
+
+        // a failed calculation leaves the tracker unchanged
+        let init_tracker = tracker;
+        assert_eq!(
+            tracker.add_volume(u128::MAX, true),
+            Err(MathError::Overflow)
+        );
+        assert_eq!(tracker.net_volume_long, init_tracker.net_volume_long);
+        assert_eq!(tracker.total_long_shares, init_tracker.total_long_shares);
+    }
+
+    #[test]
+    fn test_market_event_within_bound() {
+        let kinds = [
+            MarketEventKind::PositionOpened {
+                long: true,
+                limit: true,
+                collateral_value: Amount::MAX,
+                debt_value: Amount::MAX,
+                entry_tick: Tick::MAX,
+            },
+            MarketEventKind::PositionOpenFailed {
+                long: true,
+                collateral_value: Amount::MAX,
+                debt_value: Amount::MAX,
+            },
+            MarketEventKind::PositionClosed {
+                long: false,
+                amount_out: Amount::MAX,
+                resulting_tick: Tick::MAX,
+            },
+            MarketEventKind::PositionConverted {
+                long: false,
+                entry_tick: Tick::MAX,
+                filled: true,
+            },
+            MarketEventKind::FundingSettled {
+                funding_rate: u64::MAX,
+                long_pays: true,
+                net_volume_long: Amount::MAX,
+                net_volume_short: Amount::MAX,
+            },
+            MarketEventKind::OwnershipTransferred {
+                previous_owner: Principal::from_slice(&[u8::MAX; 29]),
+                new_owner: Principal::from_slice(&[u8::MAX; 29]),
+            },
+            MarketEventKind::ParameterChangeExecuted {
+                id: u64::MAX,
+                parameter: StateParameter::MinCollateral(Amount::MAX),
+            },
+            MarketEventKind::LegacyPositionUpdate {
+                profit: Amount::MAX,
+            },
+        ];
+
+        for kind in kinds {
+            let event = MarketEvent {
+                sequence: u64::MAX,
+                timestamp: u64::MAX,
+                account: Some([u8::MAX; 32]),
+                kind,
+            };
+            let bytes = event.to_bytes();
+            assert!(bytes.len() <= MarketEvent::MAX_SIZE as usize);
+            assert_eq!(MarketEvent::from_bytes(bytes).kind, kind);
+        }
+    }
+
+    #[test]
+    fn test_state_parameter_apply() {
+        let mut state_details = StateDetails::default();
+        state_details.mode = MarketMode::ReduceOnly;
+
+        StateParameter::MaxLeverageBps(50_000).apply(&mut state_details);
+        StateParameter::MinCollateral(1000).apply(&mut state_details);
+
+        assert_eq!(state_details.max_leverage_bps, 50_000);
+        assert_eq!(state_details.min_collateral, 1000);
+        // other fields are untouched
+        assert_eq!(state_details.current_tick, 0);
+        assert_eq!(state_details.mode, MarketMode::ReduceOnly);
+    }
+
+    #[test]
+    fn test_state_parameter_validate() {
+        let grid = TickGrid::default();
+        assert_eq!(
+            StateParameter::CurrentTick(200 * _ONE_PERCENT).validate(&grid),
+            Ok(())
+        );
+        assert!(StateParameter::CurrentTick(200 * _ONE_PERCENT + 1)
+            .validate(&grid)
+            .is_err());
+
+        let geometric = TickGrid {
+            model: TickModel::Geometric,
+            ..TickGrid::default()
+        };
+        let beyond_max_tick = _max_valid_tick(&geometric) + geometric.tick_spacing;
+        assert!(StateParameter::CurrentTick(beyond_max_tick)
+            .validate(&geometric)
+            .is_err());
+
+        assert_eq!(
+            StateParameter::MaxLeverageBps(_ONE_X_LEVERAGE_BPS).validate(&grid),
+            Ok(())
+        );
+        assert!(StateParameter::MaxLeverageBps(_ONE_X_LEVERAGE_BPS - 1)
+            .validate(&grid)
+            .is_err());
+
+        let state_details = StateDetails::default();
+        assert!(StateParameter::all(&state_details)
+            .iter()
+            .any(|parameter| parameter.validate(&grid).is_err()));
+    }
+
+    #[test]
+    fn test_market_mode_checks() {
+        assert_eq!(MarketMode::Active.check_open(true), Ok(()));
+        assert_eq!(MarketMode::Active.check_close(), Ok(()));
+
+        assert_eq!(
+            MarketMode::ReduceOnly.check_open(false),
+            Err(MarketError::ReduceOnly)
+        );
+        assert_eq!(MarketMode::ReduceOnly.check_close(), Ok(()));
+
+        assert_eq!(
+            MarketMode::LongsHalted.check_open(true),
+            Err(MarketError::SideHalted { long: true })
+        );
+        assert_eq!(MarketMode::LongsHalted.check_open(false), Ok(()));
+        assert_eq!(
+            MarketMode::ShortsHalted.check_open(false),
+            Err(MarketError::SideHalted { long: false })
+        );
+        assert_eq!(MarketMode::ShortsHalted.check_open(true), Ok(()));
+
+        assert_eq!(
+            MarketMode::Paused.check_open(true),
+            Err(MarketError::MarketPaused)
+        );
+        assert_eq!(
+            MarketMode::Paused.check_close(),
+            Err(MarketError::MarketPaused)
+        );
+
+        // an uninitialised market is paused
+        assert_eq!(StateDetails::default().mode, MarketMode::Paused);
+    }
+
+    #[test]
+    fn test_role_members() {
+        let principal = Principal::anonymous();
+        let mut role_members = RoleMembers::default();
+
+        assert!(role_members.grant(principal, Role::Pauser));
+        assert!(!role_members.grant(principal, Role::Pauser));
+        assert!(role_members.grant(principal, Role::RiskManager));
+
+        assert!(role_members.has_role(principal, Role::Pauser));
+        assert!(!role_members.has_role(principal, Role::OracleOperator));
+
+        assert!(role_members.revoke(principal, Role::Pauser));
+        assert!(!role_members.revoke(principal, Role::Pauser));
+        assert!(!role_members.has_role(principal, Role::Pauser));
+        assert!(role_members.has_role(principal, Role::RiskManager));
+    }
+
+    #[test]
+    fn test_candle_update() {
+        let resolution = CandleResolution::OneMinute;
+
+        let timestamp = 5 * resolution.duration() + 30;
+        let open_time = resolution.open_time(timestamp);
+        assert_eq!(open_time, 5 * resolution.duration());
+
+        let mut candle = Candle::new(open_time, 100, 10);
+        candle.update(120, 5);
+        candle.update(90, 5);
+        candle.update(110, 0);
+
+        assert_eq!(
+            candle,
+            Candle {
+                open_time,
+                open: 100,
+                high: 120,
+                low: 90,
+                close: 110,
+                volume: 20,
+            }
+        );
+
+        let max_candle = Candle::new(u64::MAX, Amount::MAX, Amount::MAX);
+        assert!(max_candle.to_bytes().len() <= Candle::MAX_SIZE as usize);
+    }
+
+    #[test]
+    fn test_tick_details_within_bound() {
+        let boundary = LiquidityBoundary {
+            upper_bound: Amount::MAX,
+            lower_bound: Amount::MAX,
+            lifetime_removed_liquidity: Amount::MAX,
+        };
+        let tick_details = TickDetails {
+            liq_bounds_token0: boundary,
+            liq_bounds_token1: boundary,
+        };
+
+        let bytes = tick_details.to_bytes();
+        assert!(bytes.len() <= TickDetails::MAX_SIZE as usize);
+        assert!(TickDetails::from_bytes(bytes).liq_bounds_token1 == boundary);
+    }
+
+    #[test]
+    fn test_oracle_divergence_tracking() {
+        let mut oracle_details = OracleDetails::default();
+        let max_duration = 100;
+
+        assert!(!oracle_details.update(1000, true, max_duration, 10));
+        assert_eq!(oracle_details.divergence_start, Some(10));
+
+        assert!(!oracle_details.update(1000, true, max_duration, 50));
+        assert!(oracle_details.update(1000, true, max_duration, 110));
+
+        // divergence resets once book and oracle converge
+        assert!(!oracle_details.update(1000, false, max_duration, 120));
+        assert_eq!(oracle_details.divergence_start, None);
+        assert!(!oracle_details.update(1000, true, max_duration, 130));
+    }
+}
@@ -2,9 +2,12 @@ use candid::{CandidType, Deserialize, Principal};
 
 use sha2::{Digest, Sha256};
 
-use core_lib::staking::{StakeDetails, StakeSpan};
+use core_lib::staking::{StakeDetails, StakeSpan, _ONE_PERCENT};
 use std::cell::RefCell;
-use types::VaultDetails;
+use types::{
+    AdminAction, AuditEntry, MarketBreakdown, Reservation, ReservationState, Role, RoleDetails,
+    RoleMembers, VaultDetails, VaultMarket, VaultUpgradeArgs,
+};
 
 type Amount = u128;
 type Time = u64;
@@ -20,6 +23,37 @@ type Memory = VirtualMemory<DefaultMemoryImpl>;
 const _VAULT_DETAILS_MEMORY: MemoryId = MemoryId::new(1);
 const _USERS_STAKES_DETAILS_MEMORY: MemoryId = MemoryId::new(2);
 const _USERS_MARGIN_BALANCE_MEMORY: MemoryId = MemoryId::new(3);
+const _APPLIED_OPERATIONS_MEMORY: MemoryId = MemoryId::new(4);
+const _APPLIED_OPERATIONS_EXPIRY_MEMORY: MemoryId = MemoryId::new(5);
+
+/// Retention window of applied operation ids ,retries older than this are no longer recognised as duplicates
+const OPERATION_RETENTION: Time = 30 * 24 * 3600 * 1_000_000_000; // 30 days
+
+/// Max number of expired operation ids removed per update
+const MAX_OPERATIONS_PRUNED: usize = 50;
+
+const _RESERVATIONS_MEMORY: MemoryId = MemoryId::new(6);
+
+/// Time after which a reservation that has not been committed is released
+const RESERVATION_TIMEOUT: Time = 3600 * 1_000_000_000; // 1 hour
+
+/// Time a settled reservation is kept after its expiry ,so commits and releases can be retried safely
+const RESERVATION_RETENTION: Time = 24 * 3600 * 1_000_000_000; // 1 day
+
+/// Max number of reservations released or removed per call
+const MAX_RESERVATIONS_PRUNED: usize = 50;
+
+const _ADMIN_MEMORY: MemoryId = MemoryId::new(7);
+const _MARKETS_MEMORY: MemoryId = MemoryId::new(8);
+const _AUDIT_LOG_MEMORY: MemoryId = MemoryId::new(9);
+const _PENDING_OWNER_MEMORY: MemoryId = MemoryId::new(10);
+const _ROLE_MEMBERS_MEMORY: MemoryId = MemoryId::new(11);
+
+/// Max number of role grants across all roles
+const MAX_ROLE_MEMBERS: usize = 50;
+
+/// Max number of audit log entries returned per query
+const MAX_AUDIT_LOG_PAGE: u64 = 100;
 
 thread_local! {
 
@@ -37,6 +71,50 @@ thread_local! {
     })));
 
 
+    /// Operation ids applied by managePositionUpdate ,keyed by market and operation id
+    static APPLIED_OPERATIONS :RefCell<StableBTreeMap<(Principal,u64),Time,Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_APPLIED_OPERATIONS_MEMORY)
+    })));
+
+    /// Applied operation ids keyed by the time they were applied ,utilised for removing expired operation ids
+    static APPLIED_OPERATIONS_EXPIRY :RefCell<StableBTreeMap<(Time,Principal,u64),(),Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_APPLIED_OPERATIONS_EXPIRY_MEMORY)
+    })));
+
+    /// Reservations keyed by market and operation id
+    static RESERVATIONS :RefCell<StableBTreeMap<(Principal,u64),Reservation,Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_RESERVATIONS_MEMORY)
+    })));
+
+    /// Owner of the vault ,holds every role
+    static ADMIN :RefCell<StableCell<Principal,Memory>> = RefCell::new(StableCell::init(MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_ADMIN_MEMORY)
+    }),Principal::anonymous()).unwrap());
+
+    /// Principal proposed as the next owner ,anonymous if there is no pending transfer
+    static PENDING_OWNER :RefCell<StableCell<Principal,Memory>> = RefCell::new(StableCell::init(MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_PENDING_OWNER_MEMORY)
+    }),Principal::anonymous()).unwrap());
+
+    static ROLE_MEMBERS :RefCell<StableCell<RoleMembers,Memory>> = RefCell::new(StableCell::init(MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_ROLE_MEMBERS_MEMORY)
+    }),RoleMembers::default()).unwrap());
+
+    /// Registered perp market canisters
+    static MARKETS :RefCell<StableBTreeMap<Principal,VaultMarket,Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_MARKETS_MEMORY)
+    })));
+
+    /// Admin actions keyed by sequence
+    static AUDIT_LOG :RefCell<StableBTreeMap<u64,AuditEntry,Memory>> = RefCell::new(StableBTreeMap::init(
+        MEMORY_MANAGER.with_borrow(|reference|{
+        reference.get(_AUDIT_LOG_MEMORY)
+    })));
+
     static USERS_STAKES :RefCell<StableBTreeMap<(Subaccount,Time),StakeDetails,Memory>> = RefCell::new(StableBTreeMap::init(
         MEMORY_MANAGER.with_borrow(|reference|{
         reference.get(_USERS_STAKES_DETAILS_MEMORY)
@@ -46,9 +124,39 @@ thread_local! {
 
 #[ic_cdk::init]
 fn init(vault_details: VaultDetails) {
+    ADMIN.with_borrow_mut(|reference| reference.set(ic_cdk::caller()).unwrap());
     VAULT_DETAILS.with_borrow_mut(|reference| reference.set(vault_details).unwrap());
 }
 
+/// Post Upgrade
+///
+/// Sets the owner of a vault that has none and migrates the outstanding debt of existing markets
+///
+/// Note
+///  - The debt of a migrated market is set ,not added ,so upgrading again with the same args has no further effect
+///  - A migrated market that is not registered is registered as revoked ,it can settle its positions till it is approved
+#[ic_cdk::post_upgrade]
+fn post_upgrade(upgrade_args: Option<VaultUpgradeArgs>) {
+    let upgrade_args = upgrade_args.unwrap_or_default();
+
+    if _get_owner() == Principal::anonymous() {
+        let new_owner = upgrade_args.owner.unwrap_or(ic_cdk::caller());
+        ADMIN.with_borrow_mut(|reference| reference.set(new_owner).unwrap());
+        _record_admin_action(AdminAction::OwnershipTransferred {
+            previous_owner: Principal::anonymous(),
+            new_owner,
+        });
+    }
+
+    for (market, debt) in upgrade_args.market_debts {
+        let mut vault_market = _get_market(market).unwrap_or_default();
+        vault_market.debt = debt;
+        _update_market(market, vault_market);
+
+        _record_admin_action(AdminAction::MarketDebtMigrated { market, debt });
+    }
+}
+
 #[ic_cdk::query]
 fn get_user_account(user: Principal) -> Account {
     return Account {
@@ -57,76 +165,357 @@ fn get_user_account(user: Principal) -> Account {
     };
 }
 
-#[ic_cdk::update(name = "createPositionValidityCheck")]
-async fn create_position_validity_check(
+/// Get Markets
+///
+/// Returns all registered markets
+#[ic_cdk::query(name = "getMarkets")]
+fn get_markets() -> Vec<(Principal, VaultMarket)> {
+    MARKETS.with_borrow(|reference| reference.iter().collect())
+}
+
+/// Get Market Breakdown
+///
+/// Returns the debt ,utilization ,fees earned and bad debt of each registered market
+///
+/// Note
+///  - Leverage providers share a single pool ,so the utilization of all markets adds up to the vault's utilization
+#[ic_cdk::query(name = "getMarketBreakdown")]
+fn get_market_breakdown() -> Vec<MarketBreakdown> {
+    let vault_details = _get_vault_details();
+
+    let total_liquidity = vault_details.debt + vault_details.free_liquidity;
+
+    MARKETS.with_borrow(|reference| {
+        reference
+            .iter()
+            .map(|(market, vault_market)| MarketBreakdown {
+                market,
+                approved: vault_market.approved,
+                debt_ceiling: vault_market.debt_ceiling,
+                debt: vault_market.debt,
+                utilization: _utilization(vault_market.debt, total_liquidity),
+                fees_earned: vault_market.fees_earned,
+                bad_debt: vault_market.bad_debt,
+            })
+            .collect()
+    })
+}
+
+/// Get Audit Log
+///
+/// Returns up to limit admin actions starting from the start sequence
+#[ic_cdk::query(name = "getAuditLog")]
+fn get_audit_log(start: u64, limit: u64) -> Vec<(u64, AuditEntry)> {
+    AUDIT_LOG.with_borrow(|reference| {
+        reference
+            .range(start..)
+            .take(limit.min(MAX_AUDIT_LOG_PAGE) as usize)
+            .collect()
+    })
+}
+
+///////////////////////////
+///  Admin Functions
+//////////////////////////
+
+/// Approve Market
+///
+/// Registers a perp market canister or re-approves a revoked one ,allowing it to reserve up to the debt ceiling
+#[ic_cdk::update(name = "approveMarket", guard = "risk_manager_guard")]
+fn approve_market(market: Principal, debt_ceiling: Amount) {
+    let mut vault_market = _get_market(market).unwrap_or_default();
+    vault_market.approved = true;
+    vault_market.debt_ceiling = debt_ceiling;
+    _update_market(market, vault_market);
+
+    _record_admin_action(AdminAction::MarketApproved {
+        market,
+        debt_ceiling,
+    });
+}
+
+/// Revoke Market
+///
+/// Stops a market from making new reservations
+///
+/// Note
+///  - A revoked market can still commit ,release and update its existing positions so funds are not stuck
+#[ic_cdk::update(name = "revokeMarket", guard = "risk_manager_guard")]
+fn revoke_market(market: Principal) -> Result<(), String> {
+    let mut vault_market = _get_market(market).ok_or("Market not registered".to_string())?;
+    vault_market.approved = false;
+    _update_market(market, vault_market);
+
+    _record_admin_action(AdminAction::MarketRevoked { market });
+
+    return Ok(());
+}
+
+/// Set Market Debt Ceiling
+///
+/// Updates the max debt a market can hold ,a ceiling below the current debt only blocks new reservations
+#[ic_cdk::update(name = "setMarketDebtCeiling", guard = "risk_manager_guard")]
+fn set_market_debt_ceiling(market: Principal, debt_ceiling: Amount) -> Result<(), String> {
+    let mut vault_market = _get_market(market).ok_or("Market not registered".to_string())?;
+    vault_market.debt_ceiling = debt_ceiling;
+    _update_market(market, vault_market);
+
+    _record_admin_action(AdminAction::DebtCeilingUpdated {
+        market,
+        debt_ceiling,
+    });
+
+    return Ok(());
+}
+
+/// Get Roles
+///
+/// Returns the owner ,the pending owner if any and the principals holding each role
+#[ic_cdk::query(name = "getRoles")]
+fn get_roles() -> RoleDetails {
+    let pending_owner = PENDING_OWNER.with_borrow(|reference| *reference.get());
+    RoleDetails {
+        owner: _get_owner(),
+        pending_owner: if pending_owner == Principal::anonymous() {
+            None
+        } else {
+            Some(pending_owner)
+        },
+        members: ROLE_MEMBERS.with_borrow(|reference| reference.get().members.clone()),
+    }
+}
+
+/// Grant Role
+///
+/// Grants a role to a principal ,the change is recorded in the audit log
+#[ic_cdk::update(name = "grantRole", guard = "owner_guard")]
+fn grant_role(principal: Principal, role: Role) -> Result<(), String> {
+    let mut role_members = ROLE_MEMBERS.with_borrow(|reference| reference.get().clone());
+
+    if role_members.members.len() >= MAX_ROLE_MEMBERS {
+        return Err("Max role members reached".to_string());
+    }
+
+    if role_members.grant(principal, role) {
+        ROLE_MEMBERS.with_borrow_mut(|reference| reference.set(role_members).unwrap());
+        _record_admin_action(AdminAction::RoleGranted { principal, role });
+    }
+
+    return Ok(());
+}
+
+/// Revoke Role
+///
+/// Revokes a role from a principal ,the change is recorded in the audit log
+#[ic_cdk::update(name = "revokeRole", guard = "owner_guard")]
+fn revoke_role(principal: Principal, role: Role) {
+    let mut role_members = ROLE_MEMBERS.with_borrow(|reference| reference.get().clone());
+
+    if role_members.revoke(principal, role) {
+        ROLE_MEMBERS.with_borrow_mut(|reference| reference.set(role_members).unwrap());
+        _record_admin_action(AdminAction::RoleRevoked { principal, role });
+    }
+}
+
+/// Propose Owner
+///
+/// First step of transferring ownership ,the new owner has to accept the transfer with acceptOwnership
+///
+/// Note
+///  - Proposing another principal replaces the pending owner
+#[ic_cdk::update(name = "proposeOwner", guard = "owner_guard")]
+fn propose_owner(new_owner: Principal) {
+    PENDING_OWNER.with_borrow_mut(|reference| reference.set(new_owner).unwrap());
+    _record_admin_action(AdminAction::OwnershipProposed { new_owner });
+}
+
+/// Accept Ownership
+///
+/// Second step of transferring ownership ,called by the pending owner
+#[ic_cdk::update(name = "acceptOwnership")]
+fn accept_ownership() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    let pending_owner = PENDING_OWNER.with_borrow(|reference| *reference.get());
+
+    if pending_owner == Principal::anonymous() || caller != pending_owner {
+        return Err("Caller is not the pending owner".to_string());
+    }
+
+    let previous_owner = _get_owner();
+
+    ADMIN.with_borrow_mut(|reference| reference.set(caller).unwrap());
+    PENDING_OWNER.with_borrow_mut(|reference| reference.set(Principal::anonymous()).unwrap());
+
+    _record_admin_action(AdminAction::OwnershipTransferred {
+        previous_owner,
+        new_owner: caller,
+    });
+
+    return Ok(());
+}
+
+/// Reserve
+///
+/// First phase of opening a position ,reserves the user's collateral from the margin balance and the debt from the free liquidity
+///
+/// Returns
+///  - Valid :true if user has sufficient balance and vault has sufficient free liquidity
+///  - Interest Rate :The current interest rate for the debt
+///
+/// Note
+///  - A reservation is released if it is not committed before RESERVATION_TIMEOUT
+///  - Reserving an operation id the calling market has already reserved does not reserve again
+///  - The market's debt can not exceed its debt ceiling
+#[ic_cdk::update(name = "reserve", guard = "approved_market_guard")]
+async fn reserve(
+    operation_id: u64,
     user: Principal,
     collateral: Amount,
     debt: Amount,
 ) -> (bool, u32) {
+    let market = ic_cdk::caller();
+
+    _prune_reservations();
+
+    let valid = _reserve(
+        market,
+        operation_id,
+        user,
+        collateral,
+        debt,
+        ic_cdk::api::time(),
+    );
+
+    return (valid, 0);
+}
+
+/// Reserve
+///
+/// Reserves the collateral and debt of a market's operation at the given time (see reserve)
+fn _reserve(
+    market: Principal,
+    operation_id: u64,
+    user: Principal,
+    collateral: Amount,
+    debt: Amount,
+    now: u64,
+) -> bool {
+    if let Some(reservation) =
+        RESERVATIONS.with_borrow(|reference| reference.get(&(market, operation_id)))
+    {
+        return reservation.state == ReservationState::Reserved;
+    }
+
     let account = user._to_subaccount();
 
     let account_balance = _get_user_balance(account);
 
     let mut vault_details = _get_vault_details();
 
-    let valid = account_balance >= collateral && vault_details.free_liquidity >= debt;
+    let mut vault_market = _get_market(market).unwrap();
+
+    let valid = account_balance >= collateral
+        && vault_details.free_liquidity >= debt
+        && vault_market.debt + debt <= vault_market.debt_ceiling;
 
     if valid {
         vault_details.free_liquidity -= debt;
         vault_details.debt += debt;
+        _update_vault_details(vault_details);
+
+        vault_market.debt += debt;
+        _update_market(market, vault_market);
+
         _update_user_margin_balance(account, collateral, false);
+
+        let reservation = Reservation {
+            user,
+            collateral,
+            debt,
+            expiry: now + RESERVATION_TIMEOUT,
+            state: ReservationState::Reserved,
+        };
+        RESERVATIONS
+            .with_borrow_mut(|reference| reference.insert((market, operation_id), reservation));
     }
 
-    return (valid, 0);
+    return valid;
+}
+
+/// Commit
+///
+/// Second phase of opening a position ,keeps the actual collateral and debt utilised by the position and returns the rest
+///
+/// Returns
+///  - Committed :true if the reservation is committed ,false if it was released or does not exist
+///
+/// Note
+///  - The position of an expired reservation is already open ,so an expired reservation is committed by taking the
+///  actual collateral and debt again ,false if the user's balance or the free liquidity no longer covers them
+///  - The market unwinds a position whose commit returns false ,booking the difference to its entry value as interest
+///  received or bad debt (see managePositionUpdate)
+#[ic_cdk::update(name = "commit", guard = "market_guard")]
+async fn commit(operation_id: u64, actual_collateral: Amount, actual_debt: Amount) -> bool {
+    _settle_reservation(
+        ic_cdk::caller(),
+        operation_id,
+        actual_collateral,
+        actual_debt,
+        ReservationState::Committed,
+    )
+}
+
+/// Release
+///
+/// Second phase of a position that could not be opened ,returns all reserved collateral and debt
+///
+/// Returns
+///  - Released :true if the reservation is released ,false if it was committed or does not exist
+#[ic_cdk::update(name = "release", guard = "market_guard")]
+async fn release(operation_id: u64) -> bool {
+    _settle_reservation(
+        ic_cdk::caller(),
+        operation_id,
+        0,
+        0,
+        ReservationState::Released,
+    )
 }
 
-#[ic_cdk::update(name = "managePositionUpdate")]
+/// Release Expired Reservations
+///
+/// Releases reservations that have passed their expiry without being committed
+#[ic_cdk::update(name = "releaseExpiredReservations")]
+fn release_expired_reservations() {
+    _prune_reservations();
+}
+
+/// Manage Position Update
+///
+/// Updates a trader's margin balance and the vault's debt after a position is updated or closed
+///
+/// Returns
+///  - Err if the repaid debt exceeds the debt held by the vault or the market ,nothing is updated
+///
+/// Note
+///  - Updates are idempotent ,an operation id already applied by the calling market is ignored
+///  - Bad debt is removed from the debt without being returned to the free liquidity ,the loss is shared by all leverage providers
+#[ic_cdk::update(name = "managePositionUpdate", guard = "market_guard")]
 async fn manage_position_update(
+    operation_id: u64,
     user: Principal,
     margin_delta: Amount,
     manage_debt_params: ManageDebtParams,
-) {
-    if margin_delta != 0 {
-        let account = user._to_subaccount();
-        _update_user_margin_balance(account, margin_delta, true);
-    }
-
-    let mut vault_details = _get_vault_details();
-
-    vault_details.debt += manage_debt_params.new_debt - manage_debt_params.initial_debt;
-    vault_details.free_liquidity += manage_debt_params.interest_received
-        + manage_debt_params.initial_debt
-        - manage_debt_params.new_debt;
-    vault_details.lifetime_fees += manage_debt_params.interest_received;
-
-    if manage_debt_params.interest_received == 0 {
-        return;
-    }
-
-    {
-        vault_details
-            .staking_details
-            ._create_stake(0, vault_details.lifetime_fees, StakeSpan::None)
-    };
-    {
-        vault_details.staking_details._create_stake(
-            0,
-            vault_details.lifetime_fees,
-            StakeSpan::Month2,
-        )
-    };
-    {
-        vault_details.staking_details._create_stake(
-            0,
-            vault_details.lifetime_fees,
-            StakeSpan::Month6,
-        )
-    };
-    {
-        vault_details
-            .staking_details
-            ._create_stake(0, vault_details.lifetime_fees, StakeSpan::Year)
-    };
+) -> Result<(), String> {
+    _apply_position_update(
+        ic_cdk::caller(),
+        operation_id,
+        user,
+        margin_delta,
+        manage_debt_params,
+        ic_cdk::api::time(),
+    )
 }
 
 /// Funds a Traders margin account to make a thread
@@ -385,11 +774,355 @@ fn _get_user_stake(user: Subaccount, timestamp: Time) -> StakeDetails {
     USERS_STAKES.with_borrow(|reference| reference.get(&(user, timestamp)).unwrap())
 }
 
+/// Returns the debt as a percentage of the total liquidity
+fn _utilization(debt: Amount, total_liquidity: Amount) -> u64 {
+    if total_liquidity == 0 {
+        return 0;
+    }
+    ((debt * (100 * _ONE_PERCENT) as u128) / total_liquidity) as u64
+}
+
+fn _get_market(market: Principal) -> Option<VaultMarket> {
+    MARKETS.with_borrow(|reference| reference.get(&market))
+}
+
+fn _update_market(market: Principal, vault_market: VaultMarket) {
+    MARKETS.with_borrow_mut(|reference| reference.insert(market, vault_market));
+}
+
+/// Records an admin action made by the caller in the audit log
+fn _record_admin_action(action: AdminAction) {
+    AUDIT_LOG.with_borrow_mut(|reference| {
+        let sequence = reference.last_key_value().map_or(0, |(key, _)| key + 1);
+        reference.insert(
+            sequence,
+            AuditEntry {
+                timestamp: ic_cdk::api::time(),
+                admin: ic_cdk::caller(),
+                action,
+            },
+        )
+    });
+}
+
+fn owner_guard() -> Result<(), String> {
+    if ic_cdk::caller() == _get_owner() {
+        return Ok(());
+    }
+    return Err("Caller is not the owner".to_string());
+}
+
+fn risk_manager_guard() -> Result<(), String> {
+    _role_guard(Role::RiskManager)
+}
+
+/// Allows the owner and principals granted the role
+fn _role_guard(role: Role) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    if caller == _get_owner()
+        || ROLE_MEMBERS.with_borrow(|reference| reference.get().has_role(caller, role))
+    {
+        return Ok(());
+    }
+    return Err(format!("Caller does not have the {:?} role", role));
+}
+
+fn _get_owner() -> Principal {
+    ADMIN.with_borrow(|reference| *reference.get())
+}
+
+/// Allows registered markets ,approved or revoked
+fn market_guard() -> Result<(), String> {
+    if _get_market(ic_cdk::caller()).is_some() {
+        return Ok(());
+    }
+    return Err("Caller is not a registered market".to_string());
+}
+
+/// Allows only approved markets
+fn approved_market_guard() -> Result<(), String> {
+    match _get_market(ic_cdk::caller()) {
+        Some(vault_market) if vault_market.approved => Ok(()),
+        _ => Err("Caller is not an approved market".to_string()),
+    }
+}
+
+/// Apply Position Update
+///
+/// Applies a market's position update at the given time (see managePositionUpdate)
+fn _apply_position_update(
+    market: Principal,
+    operation_id: u64,
+    user: Principal,
+    margin_delta: Amount,
+    manage_debt_params: ManageDebtParams,
+    now: u64,
+) -> Result<(), String> {
+    if _is_operation_applied(market, operation_id) {
+        return Ok(());
+    }
+
+    let mut vault_market = _get_market(market).unwrap();
+    let mut vault_details = _get_vault_details();
+
+    let ManageDebtParams {
+        new_debt,
+        initial_debt,
+        interest_received,
+        bad_debt,
+    } = manage_debt_params;
+
+    vault_market.debt = (vault_market.debt + new_debt)
+        .checked_sub(initial_debt)
+        .ok_or("Repaid debt exceeds the market's debt".to_string())?;
+
+    vault_details.debt = (vault_details.debt + new_debt)
+        .checked_sub(initial_debt)
+        .ok_or("Repaid debt exceeds the vault's debt".to_string())?;
+
+    vault_details.free_liquidity =
+        (vault_details.free_liquidity + interest_received + initial_debt)
+            .checked_sub(new_debt + bad_debt)
+            .ok_or("Debt exceeds the vault's free liquidity".to_string())?;
+
+    _apply_operation(market, operation_id, now);
+
+    vault_market.fees_earned += interest_received;
+    vault_market.bad_debt += bad_debt;
+    _update_market(market, vault_market);
+
+    if margin_delta != 0 {
+        let account = user._to_subaccount();
+        _update_user_margin_balance(account, margin_delta, true);
+    }
+
+    vault_details.lifetime_fees += interest_received;
+
+    if interest_received == 0 {
+        _update_vault_details(vault_details);
+        return Ok(());
+    }
+
+    {
+        vault_details
+            .staking_details
+            ._create_stake(0, vault_details.lifetime_fees, StakeSpan::None)
+    };
+    {
+        vault_details.staking_details._create_stake(
+            0,
+            vault_details.lifetime_fees,
+            StakeSpan::Month2,
+        )
+    };
+    {
+        vault_details.staking_details._create_stake(
+            0,
+            vault_details.lifetime_fees,
+            StakeSpan::Month6,
+        )
+    };
+    {
+        vault_details
+            .staking_details
+            ._create_stake(0, vault_details.lifetime_fees, StakeSpan::Year)
+    };
+
+    _update_vault_details(vault_details);
+
+    return Ok(());
+}
+
+/// Settle Reservation
+///
+/// Returns the unused collateral to the user's margin balance and the unused debt to the free liquidity
+///
+/// Params
+///  - Market :The market that made the reservation
+///  - Operation Id :The operation id of the reservation
+///  - Actual Collateral :The collateral utilised by the position ,capped at the reserved collateral
+///  - Actual Debt :The debt utilised by the position ,capped at the reserved debt
+///  - State :The state the reservation is settled to ,Committed or Released
+///
+/// Returns
+///  - Settled :true if the reservation is settled or was already settled to the same state
+///
+/// Note
+///  - An expired reservation is released ,releasing it again succeeds and committing it takes the actual collateral
+///  and debt again (see commit)
+fn _settle_reservation(
+    market: Principal,
+    operation_id: u64,
+    actual_collateral: Amount,
+    actual_debt: Amount,
+    state: ReservationState,
+) -> bool {
+    let mut reservation =
+        match RESERVATIONS.with_borrow(|reference| reference.get(&(market, operation_id))) {
+            Some(reservation) => reservation,
+            None => return false,
+        };
+
+    if reservation.state == ReservationState::Expired {
+        return match state {
+            ReservationState::Committed => {
+                _commit_expired_reservation(market, operation_id, actual_collateral, actual_debt)
+            }
+            // an expired reservation is already released
+            _ => true,
+        };
+    }
+
+    if reservation.state != ReservationState::Reserved {
+        return reservation.state == state;
+    }
+
+    let unused_collateral = reservation.collateral - actual_collateral.min(reservation.collateral);
+    let unused_debt = reservation.debt - actual_debt.min(reservation.debt);
+
+    if unused_collateral != 0 {
+        _update_user_margin_balance(reservation.user._to_subaccount(), unused_collateral, true);
+    }
+
+    let mut vault_details = _get_vault_details();
+    vault_details.free_liquidity += unused_debt;
+    vault_details.debt -= unused_debt;
+    _update_vault_details(vault_details);
+
+    if let Some(mut vault_market) = _get_market(market) {
+        vault_market.debt -= unused_debt;
+        _update_market(market, vault_market);
+    }
+
+    reservation.state = state;
+    RESERVATIONS.with_borrow_mut(|reference| reference.insert((market, operation_id), reservation));
+
+    return true;
+}
+
+/// Commit Expired Reservation
+///
+/// Takes the actual collateral and debt of an expired reservation again and commits it
+///
+/// Note:The market's debt ceiling is not checked ,the position utilising the debt is already open
+///
+/// Returns
+///  - Committed :false if the user's balance or the vault's free liquidity does not cover the actual collateral and debt
+fn _commit_expired_reservation(
+    market: Principal,
+    operation_id: u64,
+    actual_collateral: Amount,
+    actual_debt: Amount,
+) -> bool {
+    let mut reservation = RESERVATIONS
+        .with_borrow(|reference| reference.get(&(market, operation_id)))
+        .unwrap();
+
+    let collateral = actual_collateral.min(reservation.collateral);
+    let debt = actual_debt.min(reservation.debt);
+
+    let account = reservation.user._to_subaccount();
+
+    let mut vault_details = _get_vault_details();
+
+    if _get_user_balance(account) < collateral || vault_details.free_liquidity < debt {
+        return false;
+    }
+
+    if collateral != 0 {
+        _update_user_margin_balance(account, collateral, false);
+    }
+
+    vault_details.free_liquidity -= debt;
+    vault_details.debt += debt;
+    _update_vault_details(vault_details);
+
+    if let Some(mut vault_market) = _get_market(market) {
+        vault_market.debt += debt;
+        _update_market(market, vault_market);
+    }
+
+    reservation.state = ReservationState::Committed;
+    RESERVATIONS.with_borrow_mut(|reference| reference.insert((market, operation_id), reservation));
+
+    return true;
+}
+
+/// Prune Reservations
+///
+/// Releases reservations that have expired without being committed and removes settled reservations past the retention window
+///
+/// Note
+///  - Expired reservations are kept for the retention window so they can still be committed (see commit)
+fn _prune_reservations() {
+    _prune_reservations_at(ic_cdk::api::time());
+}
+
+fn _prune_reservations_at(now: u64) {
+    let pruned: Vec<((Principal, u64), Reservation)> = RESERVATIONS.with_borrow(|reference| {
+        reference
+            .iter()
+            .filter(|(_, reservation)| {
+                if reservation.state == ReservationState::Reserved {
+                    reservation.expiry < now
+                } else {
+                    reservation.expiry + RESERVATION_RETENTION < now
+                }
+            })
+            .take(MAX_RESERVATIONS_PRUNED)
+            .collect()
+    });
+
+    for ((market, operation_id), reservation) in pruned {
+        if reservation.state == ReservationState::Reserved {
+            _settle_reservation(market, operation_id, 0, 0, ReservationState::Expired);
+        } else {
+            RESERVATIONS.with_borrow_mut(|reference| reference.remove(&(market, operation_id)));
+        }
+    }
+}
+
+/// Returns true if the market has already applied the operation id within the retention window
+fn _is_operation_applied(market: Principal, operation_id: u64) -> bool {
+    APPLIED_OPERATIONS.with_borrow(|reference| reference.contains_key(&(market, operation_id)))
+}
+
+/// Apply Operation
+///
+/// Records an operation id applied by a market and removes expired operation ids
+fn _apply_operation(market: Principal, operation_id: u64, now: u64) {
+    let expired: Vec<(Time, Principal, u64)> = APPLIED_OPERATIONS_EXPIRY.with_borrow(|reference| {
+        reference
+            .range(
+                ..(
+                    now.saturating_sub(OPERATION_RETENTION),
+                    Principal::anonymous(),
+                    0,
+                ),
+            )
+            .take(MAX_OPERATIONS_PRUNED)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    for (applied_time, market, operation_id) in expired {
+        APPLIED_OPERATIONS_EXPIRY
+            .with_borrow_mut(|reference| reference.remove(&(applied_time, market, operation_id)));
+        APPLIED_OPERATIONS.with_borrow_mut(|reference| reference.remove(&(market, operation_id)));
+    }
+
+    APPLIED_OPERATIONS.with_borrow_mut(|reference| reference.insert((market, operation_id), now));
+    APPLIED_OPERATIONS_EXPIRY
+        .with_borrow_mut(|reference| reference.insert((now, market, operation_id), ()));
+}
+
 #[derive(Copy, Clone, Default, Deserialize, CandidType)]
 struct ManageDebtParams {
     new_debt: Amount,
     initial_debt: Amount,
     interest_received: Amount,
+    /// part of the repaid debt (initial debt - new debt) that could not be paid back
+    bad_debt: Amount,
 }
 
 trait UniqueSubAccount {
@@ -417,3 +1150,458 @@ fn _vault_subaccount() -> Subaccount {
 
 pub mod core_lib;
 pub mod types;
+
+#[cfg(test)]
+mod unit_test {
+    use super::*;
+
+    fn _setup_market(market: Principal, free_liquidity: Amount) {
+        let mut vault_details = _get_vault_details();
+        vault_details.free_liquidity = free_liquidity;
+        _update_vault_details(vault_details);
+
+        _update_market(
+            market,
+            VaultMarket {
+                approved: true,
+                debt_ceiling: Amount::MAX,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_position_update_exceeding_debt() {
+        let market = Principal::from_slice(&[3]);
+        let user = Principal::from_slice(&[4]);
+
+        _setup_market(market, 1_000);
+
+        // position opened before the market registry ,its debt was never added to the market
+        let mut vault_details = _get_vault_details();
+        vault_details.debt += 500;
+        _update_vault_details(vault_details);
+
+        let repay = ManageDebtParams {
+            new_debt: 0,
+            initial_debt: 500,
+            interest_received: 0,
+            bad_debt: 0,
+        };
+
+        assert!(_apply_position_update(market, 1, user, 50, repay, 0).is_err());
+        assert_eq!(_get_user_balance(user._to_subaccount()), 0);
+        assert!(!_is_operation_applied(market, 1));
+
+        // once the debt is migrated the update is applied
+        let mut vault_market = _get_market(market).unwrap();
+        vault_market.debt = 500;
+        _update_market(market, vault_market);
+
+        assert_eq!(
+            _apply_position_update(market, 1, user, 50, repay, 0),
+            Ok(())
+        );
+        assert_eq!(_get_user_balance(user._to_subaccount()), 50);
+        assert_eq!(_get_market(market).unwrap().debt, 0);
+
+        // retrying the applied update has no effect
+        assert_eq!(
+            _apply_position_update(market, 1, user, 50, repay, 0),
+            Ok(())
+        );
+        assert_eq!(_get_user_balance(user._to_subaccount()), 50);
+    }
+
+    #[test]
+    fn test_commit_after_expiry() {
+        let market = Principal::from_slice(&[1]);
+        let user = Principal::from_slice(&[2]);
+        let account = user._to_subaccount();
+
+        _setup_market(market, 1_000);
+        _update_user_margin_balance(account, 100, true);
+
+        assert!(_reserve(market, 1, user, 100, 500, 0));
+        assert_eq!(_get_user_balance(account), 0);
+
+        // reservation expires before the commit arrives ,all funds are returned
+        _prune_reservations_at(RESERVATION_TIMEOUT + 1);
+        assert_eq!(_get_user_balance(account), 100);
+        assert_eq!(_get_vault_details().free_liquidity, 1_000);
+        assert_eq!(_get_vault_details().debt, 0);
+
+        // commit takes the actual collateral and debt again
+        assert!(_settle_reservation(
+            market,
+            1,
+            80,
+            400,
+            ReservationState::Committed
+        ));
+        assert_eq!(_get_user_balance(account), 20);
+        assert_eq!(_get_vault_details().free_liquidity, 600);
+        assert_eq!(_get_vault_details().debt, 400);
+        assert_eq!(_get_market(market).unwrap().debt, 400);
+
+        // retrying the commit does not take the funds twice
+        assert!(_settle_reservation(
+            market,
+            1,
+            80,
+            400,
+            ReservationState::Committed
+        ));
+        assert_eq!(_get_user_balance(account), 20);
+        assert!(!_settle_reservation(
+            market,
+            1,
+            0,
+            0,
+            ReservationState::Released
+        ));
+
+        // expired reservation whose collateral was withdrawn can not be committed
+        assert!(_reserve(market, 2, user, 20, 100, 0));
+        _prune_reservations_at(RESERVATION_TIMEOUT + 1);
+        _update_user_margin_balance(account, 20, false);
+
+        assert!(!_settle_reservation(
+            market,
+            2,
+            20,
+            100,
+            ReservationState::Committed
+        ));
+        assert_eq!(_get_vault_details().debt, 400);
+        // releasing an expired reservation succeeds
+        assert!(_settle_reservation(
+            market,
+            2,
+            0,
+            0,
+            ReservationState::Released
+        ));
+    }
+
+    #[test]
+    fn test_randomized_operations_keep_accounting() {
+        for seed in 1..=20 {
+            _run_random_operations(seed, 400);
+        }
+    }
+
+    /// Model Reservation
+    ///
+    /// A reservation as tracked by the test ,with the user as an index into the test's users
+    #[derive(Clone, Copy)]
+    struct ModelReservation {
+        operation_id: u64,
+        user: usize,
+        collateral: Amount,
+        debt: Amount,
+        expiry: u64,
+        state: ReservationState,
+    }
+
+    /// Run Random Operations
+    ///
+    /// Reserves ,commits ,releases and expires reservations and closes positions in a random sequence ,as markets do ,
+    /// while tracking every user balance ,the outstanding debt and the bad debt of the sequence
+    ///
+    /// Asserts that after every operation
+    ///  - each user balance equals the tracked balance ,i.e collateral is neither lost nor paid out twice
+    ///  - the vault's and the market's debt equal the debt of live reservations and open positions
+    ///  - the free liquidity and the debt add up to the initial liquidity minus the bad debt
+    ///
+    /// and that no debt is left once every reservation is released and every position closed
+    ///
+    /// Note
+    ///  - A vault amount going negative panics ,failing the test
+    ///  - Interest is not received ,as staking fees requires the canister time
+    fn _run_random_operations(seed: u64, steps: u64) {
+        let market = Principal::from_slice(&[10, seed as u8]);
+        let users: Vec<Principal> = (0..3u8)
+            .map(|index| Principal::from_slice(&[11, seed as u8, index]))
+            .collect();
+
+        let initial_liquidity: Amount = 10_000;
+        _setup_market(market, initial_liquidity);
+
+        let debt_ceiling: Amount = 6_000;
+        let mut vault_market = _get_market(market).unwrap();
+        vault_market.debt_ceiling = debt_ceiling;
+        _update_market(market, vault_market);
+
+        let mut balances: Vec<Amount> = vec![1_000; users.len()];
+        for user in &users {
+            _update_user_margin_balance(user._to_subaccount(), 1_000, true);
+        }
+
+        let mut random_state = seed;
+        let mut now: u64 = 0;
+        let mut operation_id: u64 = 0;
+        let mut bad_debt_total: Amount = 0;
+        let mut reservations: Vec<ModelReservation> = Vec::new();
+        // user ,collateral and debt of open positions
+        let mut positions: Vec<(usize, Amount, Amount)> = Vec::new();
+
+        for _ in 0..steps {
+            operation_id += 1;
+
+            match _next_random(&mut random_state) % 6 {
+                0 => {
+                    let user = (_next_random(&mut random_state) as usize) % users.len();
+                    let collateral = (_next_random(&mut random_state) as Amount) % 400;
+                    let debt = (_next_random(&mut random_state) as Amount) % 2_000;
+
+                    let vault_details = _get_vault_details();
+                    let expected = balances[user] >= collateral
+                        && vault_details.free_liquidity >= debt
+                        && _get_market(market).unwrap().debt + debt <= debt_ceiling;
+
+                    let valid = _reserve(market, operation_id, users[user], collateral, debt, now);
+                    assert_eq!(valid, expected);
+
+                    if valid {
+                        balances[user] -= collateral;
+                        reservations.push(ModelReservation {
+                            operation_id,
+                            user,
+                            collateral,
+                            debt,
+                            expiry: now + RESERVATION_TIMEOUT,
+                            state: ReservationState::Reserved,
+                        });
+                    }
+                }
+                1 if !reservations.is_empty() => {
+                    let index = (_next_random(&mut random_state) as usize) % reservations.len();
+                    let reservation = reservations[index];
+                    let actual_collateral =
+                        (_next_random(&mut random_state) as Amount) % (reservation.collateral + 1);
+                    let actual_debt =
+                        (_next_random(&mut random_state) as Amount) % (reservation.debt + 1);
+
+                    let expected = match reservation.state {
+                        ReservationState::Reserved => true,
+                        ReservationState::Expired => {
+                            balances[reservation.user] >= actual_collateral
+                                && _get_vault_details().free_liquidity >= actual_debt
+                        }
+                        // committing again is acknowledged without taking anything
+                        ReservationState::Committed => true,
+                        _ => false,
+                    };
+
+                    let committed = _settle_reservation(
+                        market,
+                        reservation.operation_id,
+                        actual_collateral,
+                        actual_debt,
+                        ReservationState::Committed,
+                    );
+                    assert_eq!(committed, expected);
+
+                    if committed && reservation.state != ReservationState::Committed {
+                        match reservation.state {
+                            ReservationState::Reserved => {
+                                balances[reservation.user] +=
+                                    reservation.collateral - actual_collateral
+                            }
+                            _ => balances[reservation.user] -= actual_collateral,
+                        }
+                        positions.push((reservation.user, actual_collateral, actual_debt));
+                        reservations[index].state = ReservationState::Committed;
+                    }
+
+                    if committed {
+                        // retrying the commit takes nothing twice
+                        assert!(_settle_reservation(
+                            market,
+                            reservation.operation_id,
+                            actual_collateral,
+                            actual_debt,
+                            ReservationState::Committed,
+                        ));
+                    }
+                }
+                2 if !reservations.is_empty() => {
+                    let index = (_next_random(&mut random_state) as usize) % reservations.len();
+                    let reservation = reservations[index];
+
+                    let released = _settle_reservation(
+                        market,
+                        reservation.operation_id,
+                        0,
+                        0,
+                        ReservationState::Released,
+                    );
+                    assert_eq!(released, reservation.state != ReservationState::Committed);
+
+                    if reservation.state == ReservationState::Reserved {
+                        balances[reservation.user] += reservation.collateral;
+                        reservations[index].state = ReservationState::Released;
+                    }
+                }
+                3 => {
+                    now += RESERVATION_TIMEOUT + 1;
+                    // every expired reservation is settled even if there are more than a single prune removes
+                    for _ in 0..10 {
+                        _prune_reservations_at(now);
+                    }
+
+                    reservations.retain_mut(|reservation| {
+                        if reservation.state == ReservationState::Reserved {
+                            if reservation.expiry < now {
+                                balances[reservation.user] += reservation.collateral;
+                                reservation.state = ReservationState::Expired;
+                            }
+                            return true;
+                        }
+                        reservation.expiry + RESERVATION_RETENTION >= now
+                    });
+                }
+                4 if !positions.is_empty() => {
+                    let index = (_next_random(&mut random_state) as usize) % positions.len();
+                    let (user, collateral, debt) = positions[index];
+
+                    let margin_delta =
+                        (_next_random(&mut random_state) as Amount) % (2 * collateral + 1);
+                    let partial = debt != 0 && _next_random(&mut random_state) % 3 == 0;
+                    let new_debt = if partial {
+                        (_next_random(&mut random_state) as Amount) % debt
+                    } else {
+                        0
+                    };
+                    let bad_debt = if _next_random(&mut random_state) % 4 == 0 {
+                        (_next_random(&mut random_state) as Amount) % (debt - new_debt + 1)
+                    } else {
+                        0
+                    };
+
+                    let params = ManageDebtParams {
+                        new_debt,
+                        initial_debt: debt,
+                        interest_received: 0,
+                        bad_debt,
+                    };
+                    assert_eq!(
+                        _apply_position_update(
+                            market,
+                            operation_id,
+                            users[user],
+                            margin_delta,
+                            params,
+                            now
+                        ),
+                        Ok(())
+                    );
+                    // retrying the applied update has no effect
+                    assert_eq!(
+                        _apply_position_update(
+                            market,
+                            operation_id,
+                            users[user],
+                            margin_delta,
+                            params,
+                            now
+                        ),
+                        Ok(())
+                    );
+
+                    balances[user] += margin_delta;
+                    bad_debt_total += bad_debt;
+                    if partial {
+                        positions[index].2 = new_debt;
+                    } else {
+                        positions.remove(index);
+                    }
+                }
+                _ => {
+                    // repaying more than the outstanding debt is rejected without any change
+                    let params = ManageDebtParams {
+                        new_debt: 0,
+                        initial_debt: _get_market(market).unwrap().debt + 1,
+                        interest_received: 0,
+                        bad_debt: 0,
+                    };
+                    assert!(
+                        _apply_position_update(market, operation_id, users[0], 1, params, now)
+                            .is_err()
+                    );
+                }
+            }
+
+            for (index, user) in users.iter().enumerate() {
+                assert_eq!(_get_user_balance(user._to_subaccount()), balances[index]);
+            }
+
+            let model_debt = _model_debt(&reservations, &positions);
+            let vault_details = _get_vault_details();
+            assert_eq!(vault_details.debt, model_debt);
+            assert_eq!(_get_market(market).unwrap().debt, model_debt);
+            assert_eq!(
+                vault_details.free_liquidity + vault_details.debt,
+                initial_liquidity - bad_debt_total
+            );
+        }
+
+        // once every reservation is released and every position closed ,no debt is left
+        for reservation in reservations {
+            if reservation.state == ReservationState::Reserved {
+                _settle_reservation(
+                    market,
+                    reservation.operation_id,
+                    0,
+                    0,
+                    ReservationState::Released,
+                );
+            }
+        }
+        for (user, _, debt) in positions {
+            operation_id += 1;
+            let params = ManageDebtParams {
+                new_debt: 0,
+                initial_debt: debt,
+                interest_received: 0,
+                bad_debt: 0,
+            };
+            assert_eq!(
+                _apply_position_update(market, operation_id, users[user], 0, params, now),
+                Ok(())
+            );
+        }
+
+        let vault_details = _get_vault_details();
+        assert_eq!(vault_details.debt, 0);
+        assert_eq!(_get_market(market).unwrap().debt, 0);
+        assert_eq!(
+            vault_details.free_liquidity,
+            initial_liquidity - bad_debt_total
+        );
+    }
+
+    /// Returns the debt of live reservations and open positions
+    fn _model_debt(
+        reservations: &[ModelReservation],
+        positions: &[(usize, Amount, Amount)],
+    ) -> Amount {
+        let reserved_debt: Amount = reservations
+            .iter()
+            .filter(|reservation| reservation.state == ReservationState::Reserved)
+            .map(|reservation| reservation.debt)
+            .sum();
+        return reserved_debt + positions.iter().map(|(_, _, debt)| debt).sum::<Amount>();
+    }
+
+    /// Next Random
+    ///
+    /// Xorshift pseudo random numbers ,so every sequence can be reproduced from its seed
+    fn _next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+}
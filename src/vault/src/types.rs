@@ -1,7 +1,7 @@
 use crate::core_lib::token::Asset;
 
 use super::core_lib::staking::*;
-use candid::{CandidType, Decode, Encode};
+use candid::{CandidType, Decode, Encode, Principal};
 
 use serde::Deserialize;
 
@@ -48,3 +48,247 @@ impl Storable for VaultDetails {
         Cow::Owned(Encode!(self).unwrap())
     }
 }
+
+/// Reservation State
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReservationState {
+    /// Collateral and debt are held for the position being opened
+    Reserved,
+    /// Position was opened ,unused collateral and debt have been returned
+    Committed,
+    /// Position was not opened ,all collateral and debt have been returned
+    Released,
+    /// Reservation was not settled before its expiry ,all collateral and debt have been returned
+    ///
+    /// Note:An expired reservation can still be committed while the user's balance and the free liquidity cover it
+    Expired,
+}
+
+/// Reservation
+///
+/// Collateral and debt reserved by a market for a position being opened till it is committed or released
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct Reservation {
+    pub user: Principal,
+    pub collateral: Amount,
+    pub debt: Amount,
+    /// Expiry
+    ///
+    /// time after which the reservation is released if it has not been committed
+    pub expiry: u64,
+    pub state: ReservationState,
+}
+
+impl Storable for Reservation {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 200,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Vault Market
+///
+/// A perp market canister registered with the vault
+#[derive(CandidType, Deserialize, Clone, Copy, Default)]
+pub struct VaultMarket {
+    /// Approved
+    ///
+    /// true if the market can make new reservations ,a revoked market can only settle its existing positions
+    pub approved: bool,
+    /// Debt Ceiling
+    ///
+    /// max debt the market can hold at any time
+    pub debt_ceiling: Amount,
+    /// Debt
+    ///
+    /// current debt held by the market's positions and reservations
+    pub debt: Amount,
+    /// Fees Earned
+    ///
+    /// total interest received from the market's positions
+    pub fees_earned: Amount,
+    /// Bad Debt
+    ///
+    /// total debt of the market's positions that could not be repaid
+    pub bad_debt: Amount,
+}
+
+impl Storable for VaultMarket {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 200,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Market Breakdown
+///
+/// The vault figures of a single market
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct MarketBreakdown {
+    pub market: Principal,
+    pub approved: bool,
+    pub debt_ceiling: Amount,
+    pub debt: Amount,
+    /// Utilization
+    ///
+    /// the market's debt as a percentage of the vault's total liquidity (debt and free liquidity)
+    pub utilization: u64,
+    pub fees_earned: Amount,
+    pub bad_debt: Amount,
+}
+
+/// Role
+///
+/// Permissions that can be granted to a principal for the admin endpoints ,the owner holds every role
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Approves and revokes markets and sets their debt ceilings
+    RiskManager,
+}
+
+/// Role Members
+///
+/// The principals holding each role
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct RoleMembers {
+    pub members: Vec<(Principal, Role)>,
+}
+
+impl RoleMembers {
+    /// Returns true if principal has been granted the role
+    pub fn has_role(&self, principal: Principal, role: Role) -> bool {
+        self.members.contains(&(principal, role))
+    }
+
+    /// Grants the role to principal ,returns false if it was already granted
+    pub fn grant(&mut self, principal: Principal, role: Role) -> bool {
+        if self.has_role(principal, role) {
+            return false;
+        }
+        self.members.push((principal, role));
+        return true;
+    }
+
+    /// Revokes the role from principal ,returns false if it was not granted
+    pub fn revoke(&mut self, principal: Principal, role: Role) -> bool {
+        let initial_len = self.members.len();
+        self.members.retain(|member| *member != (principal, role));
+        return self.members.len() != initial_len;
+    }
+}
+
+impl Storable for RoleMembers {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 3000,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Role Details
+///
+/// The owner ,pending owner and role members of the vault
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RoleDetails {
+    pub owner: Principal,
+    /// Pending Owner
+    ///
+    /// principal proposed by the owner that has not accepted ownership yet
+    pub pending_owner: Option<Principal>,
+    pub members: Vec<(Principal, Role)>,
+}
+
+/// Admin Action
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum AdminAction {
+    MarketApproved {
+        market: Principal,
+        debt_ceiling: Amount,
+    },
+    MarketRevoked {
+        market: Principal,
+    },
+    DebtCeilingUpdated {
+        market: Principal,
+        debt_ceiling: Amount,
+    },
+    RoleGranted {
+        principal: Principal,
+        role: Role,
+    },
+    RoleRevoked {
+        principal: Principal,
+        role: Role,
+    },
+    OwnershipProposed {
+        new_owner: Principal,
+    },
+    OwnershipTransferred {
+        previous_owner: Principal,
+        new_owner: Principal,
+    },
+    MarketDebtMigrated {
+        market: Principal,
+        debt: Amount,
+    },
+}
+
+/// Audit Entry
+///
+/// An admin action recorded in the audit log
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub admin: Principal,
+    pub action: AdminAction,
+}
+
+impl Storable for AuditEntry {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 300,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Vault Upgrade Args
+///
+/// Arguments for upgrading a vault installed before the market registry and the owner
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct VaultUpgradeArgs {
+    /// Owner
+    ///
+    /// owner of a vault that has none ,the upgrading principal if not set
+    pub owner: Option<Principal>,
+    /// Market Debts
+    ///
+    /// outstanding debt of each market's open positions ,positions opened before the market registry were not added
+    /// to their market's debt
+    pub market_debts: Vec<(Principal, Amount)>,
+}